@@ -0,0 +1,109 @@
+//! Just enough of the EPUB container format (a zip archive) to read a book's metadata.
+use crate::{Error, Result};
+use roxmltree::{Document, ParsingOptions};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+const CONTAINER_PATH: &str = "META-INF/container.xml";
+
+const CONTAINER_NS: &str = "urn:oasis:names:tc:opendocument:xmlns:container";
+const DUBLIN_CORE_NS: &str = "http://purl.org/dc/elements/1.1/";
+
+/// An open EPUB file.
+pub struct Epub {
+    archive: ZipArchive<File>,
+}
+
+impl Epub {
+    pub fn open(path: impl AsRef<Path>) -> Result<Epub> {
+        Ok(Epub {
+            archive: ZipArchive::new(File::open(path)?)?,
+        })
+    }
+
+    /// The book's title from the `dc:title` of its package (OPF) file, if it has one.
+    pub fn title(&mut self) -> Result<Option<String>> {
+        let container = self.read_string(CONTAINER_PATH)?;
+        let rootfile = parse_xml(&container)?
+            .descendants()
+            .find(|n| n.has_tag_name((CONTAINER_NS, "rootfile")))
+            .and_then(|n| n.attribute("full-path"))
+            .map(str::to_string)
+            .ok_or_else(|| Error::EpubFormatError("no rootfile in container.xml".into()))?;
+        let opf = self.read_string(&rootfile)?;
+        Ok(parse_xml(&opf)?
+            .descendants()
+            .find(|n| n.has_tag_name((DUBLIN_CORE_NS, "title")))
+            .and_then(|n| n.text())
+            .map(|title| title.trim().to_string())
+            .filter(|title| !title.is_empty()))
+    }
+
+    fn read_string(&mut self, name: &str) -> Result<String> {
+        let mut entry = match self.archive.by_name(name) {
+            Err(ZipError::FileNotFound) => {
+                return Err(Error::EpubFormatError(format!("{} is missing", name)))
+            }
+            entry => entry?,
+        };
+        let mut bytes = Vec::default();
+        entry.read_to_end(&mut bytes)?;
+        String::from_utf8(bytes)
+            .map_err(|_| Error::EpubFormatError(format!("{} is not UTF-8", name)))
+    }
+}
+
+// Some packages still have a DOCTYPE, which roxmltree refuses unless asked.
+fn parse_xml(text: &str) -> Result<Document<'_>> {
+    let options = ParsingOptions {
+        allow_dtd: true,
+        ..ParsingOptions::default()
+    };
+    Ok(Document::parse_with_options(text, options)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    #[test]
+    fn reads_the_title() {
+        let mut epub = Epub::open(fixture("epub/titled.epub")).unwrap();
+        assert_eq!(epub.title().unwrap().as_deref(), Some("Pride & Prejudice"));
+    }
+
+    #[test]
+    fn empty_title_is_none() {
+        let mut epub = Epub::open(fixture("epub/untitled.epub")).unwrap();
+        assert_eq!(epub.title().unwrap(), None);
+    }
+
+    #[test]
+    fn missing_container_is_an_error() {
+        let mut epub = Epub::open(fixture("epub/no-container.epub")).unwrap();
+        match epub.title() {
+            Err(Error::EpubFormatError(msg)) => {
+                assert_eq!(msg, "META-INF/container.xml is missing")
+            }
+            result => panic!("expected EpubFormatError, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn other_files_are_errors() {
+        match Epub::open(fixture("content/firmware-2.content")) {
+            Err(Error::ZipError(_)) => {}
+            result => panic!("expected ZipError, got {:?}", result.map(|_| ())),
+        }
+    }
+}
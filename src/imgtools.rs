@@ -1,15 +1,108 @@
-use crate::Result;
-use printpdf::image::imageops;
-use printpdf::image::{DynamicImage, ImageBuffer, Luma, Pixel, Primitive, Rgb};
+use crate::{Error, Result};
+use printpdf::image::imageops::{self, FilterType};
+use printpdf::image::{
+    ColorType, DynamicImage, GenericImageView, GrayImage, ImageBuffer, Pixel, Primitive, Rgba,
+    RgbaImage,
+};
 use std::borrow::Cow;
+use std::str::FromStr;
+
+mod dither;
+mod enhance;
+mod perspective;
+mod template;
+pub use dither::{dither, Dither};
+pub use enhance::{enhance, parse_steps, Step};
+pub use template::{render_template, TemplateStyle};
+
+/// Deskewing, whitening and finding the page in a photo all look at a copy of the image that is
+/// no bigger than this.
+pub(super) const ANALYSIS_SIZE: u32 = 800;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum ColorTransform {
-    ToBlackAndWhite,
+    ToBlackAndWhite(Dither),
     ToGrayscale,
     None,
 }
 
+/// The size of a page, in pixels, and the resolution at which it is shown.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageSize {
+    pub width: u32,
+    pub height: u32,
+    pub dpi: f64,
+}
+
+impl PageSize {
+    /// The screen of the Remarkable 1 and 2.
+    pub const REMARKABLE: PageSize = PageSize {
+        width: 1404,
+        height: 1872,
+        dpi: 226.0,
+    };
+
+    /// The screen of the Remarkable Paper Pro.
+    pub const PAPER_PRO: PageSize = PageSize {
+        width: 1620,
+        height: 2160,
+        dpi: 229.0,
+    };
+}
+
+impl FromStr for PageSize {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "remarkable" | "rm" => Ok(PageSize::REMARKABLE),
+            "paper-pro" | "pro" => Ok(PageSize::PAPER_PRO),
+            _ => Err(Error::BadArgsError(format!(
+                "unknown page size '{}', expected remarkable or paper-pro",
+                s
+            ))),
+        }
+    }
+}
+
+/// How an image is scaled to the area of a page inside the margins.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Scaling {
+    /// Scale the image up or down so that all of it fits.
+    Fit,
+    /// Scale the image so that it covers the whole area, cropping what sticks out.
+    Fill,
+    /// Leave the image at its own size, only shrinking it if it doesn't fit.
+    Center,
+}
+
+impl FromStr for Scaling {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fit" => Ok(Scaling::Fit),
+            "fill" => Ok(Scaling::Fill),
+            "center" => Ok(Scaling::Center),
+            _ => Err(Error::BadArgsError(format!(
+                "unknown fit '{}', expected fit, fill or center",
+                s
+            ))),
+        }
+    }
+}
+
+/// Where images go on a page.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layout {
+    pub page: PageSize,
+    pub scaling: Scaling,
+    /// White space around the image, in pixels.
+    pub margin: u32,
+    /// Turn landscape images a quarter turn clockwise to fill more of a portrait page.
+    pub rotate: bool,
+}
+
 trait MulAlpha
 where
     Self: Pixel + 'static,
@@ -39,40 +132,37 @@ where
     }
 }
 
-impl<S: Primitive + std::fmt::Debug + 'static> MulAlpha for Luma<S> {
+impl<P> MulAlpha for P
+where
+    P: Pixel + 'static,
+    P::Subpixel: 'static,
+{
+    /// Composites the pixel onto white using its own alpha channel, if it has one, then fades
+    /// it toward white by `alpha`. The result is fully opaque.
     fn mul_alpha(&self, alpha: f32) -> Self {
-        use num_traits::cast;
-        self.map_with_alpha(
-            |p| {
-                // unwrap: S fits into an f32.
-                let max_pixel: f32 = cast(S::max_value()).unwrap();
-                let bgrnd: f32 = (1.0 - alpha) * max_pixel;
-                // unwrap: S fits into an f32.
-                let p_as_f32: f32 = cast(p).unwrap();
-                let fgrnd: f32 = alpha * p_as_f32;
-                // unwrap: computed value *should* fit into S.
-                cast(bgrnd + fgrnd).unwrap()
-            },
-            |_| S::max_value(),
-        )
-    }
-}
+        use num_traits::{cast, Bounded};
+        // unwrap: Subpixel fits into an f32.
+        let max_pixel: f32 = cast(P::Subpixel::max_value()).unwrap();
+        let alpha = if P::COLOR_TYPE.has_alpha() {
+            // The alpha channel is always the last one.
+            let own_alpha = self.channels()[P::CHANNEL_COUNT as usize - 1];
+            // unwrap: Subpixel fits into an f32.
+            let own_alpha: f32 = cast(own_alpha).unwrap();
+            alpha * own_alpha / max_pixel
+        } else {
+            alpha
+        };
 
-impl<S: Primitive + std::fmt::Debug + 'static> MulAlpha for Rgb<S> {
-    fn mul_alpha(&self, alpha: f32) -> Self {
-        use num_traits::cast;
         self.map_with_alpha(
             |p| {
-                // unwrap: S fits into an f32.
-                let max_pixel: f32 = cast(S::max_value()).unwrap();
                 let bgrnd: f32 = (1.0 - alpha) * max_pixel;
-                // unwrap: S fits into an f32.
+                // unwrap: Subpixel fits into an f32.
                 let p_as_f32: f32 = cast(p).unwrap();
                 let fgrnd: f32 = alpha * p_as_f32;
-                // unwrap: computed value *should* fit into S.
+                // unwrap: computed value *should* fit into Subpixel.
                 cast(bgrnd + fgrnd).unwrap()
             },
-            |_| S::max_value(),
+            |_| P::Subpixel::max_value(),
         )
     }
 }
@@ -83,42 +173,92 @@ fn mul_alpha_to_image(img: &DynamicImage, alpha: f32) -> DynamicImage {
         DynamicImage::ImageLuma8(buffer) => {
             DynamicImage::ImageLuma8(MulAlpha::mul_alpha_buffer(buffer, alpha))
         }
+        DynamicImage::ImageLumaA8(buffer) => {
+            DynamicImage::ImageLumaA8(MulAlpha::mul_alpha_buffer(buffer, alpha))
+        }
         DynamicImage::ImageRgb8(buffer) => {
             DynamicImage::ImageRgb8(MulAlpha::mul_alpha_buffer(buffer, alpha))
         }
+        DynamicImage::ImageRgba8(buffer) => {
+            DynamicImage::ImageRgba8(MulAlpha::mul_alpha_buffer(buffer, alpha))
+        }
+        DynamicImage::ImageBgr8(buffer) => {
+            DynamicImage::ImageBgr8(MulAlpha::mul_alpha_buffer(buffer, alpha))
+        }
+        DynamicImage::ImageBgra8(buffer) => {
+            DynamicImage::ImageBgra8(MulAlpha::mul_alpha_buffer(buffer, alpha))
+        }
+        DynamicImage::ImageLuma16(buffer) => {
+            DynamicImage::ImageLuma16(MulAlpha::mul_alpha_buffer(buffer, alpha))
+        }
+        DynamicImage::ImageLumaA16(buffer) => {
+            DynamicImage::ImageLumaA16(MulAlpha::mul_alpha_buffer(buffer, alpha))
+        }
         DynamicImage::ImageRgb16(buffer) => {
             DynamicImage::ImageRgb16(MulAlpha::mul_alpha_buffer(buffer, alpha))
         }
-        _ => unimplemented!("add_alpha_to_image"),
+        DynamicImage::ImageRgba16(buffer) => {
+            DynamicImage::ImageRgba16(MulAlpha::mul_alpha_buffer(buffer, alpha))
+        }
+    }
+}
+
+/// Returns `img` as 8-bit grayscale or RGB without an alpha channel, or None if it already is.
+///
+/// That is all the PDF and thumbnail code can handle. Transparent parts of the image are
+/// composited onto white, since they would otherwise turn black.
+pub fn flatten_image(img: &DynamicImage) -> Option<DynamicImage> {
+    let opaque = if img.color().has_alpha() {
+        Cow::Owned(mul_alpha_to_image(img, 1.0))
+    } else {
+        Cow::Borrowed(img)
+    };
+    match opaque.as_ref() {
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageRgb8(_) => match opaque {
+            Cow::Owned(flat) => Some(flat),
+            Cow::Borrowed(_) => None,
+        },
+        DynamicImage::ImageLumaA8(_)
+        | DynamicImage::ImageLuma16(_)
+        | DynamicImage::ImageLumaA16(_) => Some(DynamicImage::ImageLuma8(opaque.to_luma8())),
+        _ => Some(DynamicImage::ImageRgb8(opaque.to_rgb8())),
     }
 }
 
 /// Returns a (possibly new) image with the requested transform applied.
 ///
 /// The resulting image is "pre-multiplied" (since the Remarkable seems to do
-/// weird things otherwise), and the alphas in the final image, if present,
-/// will be set tofully opaque.
+/// weird things otherwise), and flattened like `flatten_image` first, so the
+/// final image is 8-bit without an alpha channel.
 ///
-/// If `to_gray` is true, the image will be converted to grayscale.
+/// The `steps` are applied first, in order, to clean up the image.
+///
+/// The `color_transform` may convert the image to grayscale, or reduce it to black and
+/// white with one of the `Dither` methods.
 ///
 /// If `alpha` is true, the image will be multiplied by its value. Legal values are
 /// `0-100`, where 100 = opaque, 0 = fully transparent.
-pub fn process_image(
-    img: &DynamicImage,
+pub fn process_image<'a>(
+    img: &'a DynamicImage,
+    steps: &[Step],
     color_transform: ColorTransform,
     alpha: u8,
-) -> Result<Cow<DynamicImage>> {
+) -> Result<Cow<'a, DynamicImage>> {
     let mut output = Cow::Borrowed(img);
+    if !steps.is_empty() {
+        output = Cow::Owned(enhance(img, steps));
+    } else if let Some(flat) = flatten_image(img) {
+        output = Cow::Owned(flat);
+    }
 
     match color_transform {
         ColorTransform::ToGrayscale => {
             let temp = DynamicImage::ImageLuma8(imageops::grayscale(output.as_ref()));
             output = Cow::Owned(temp);
         }
-        ColorTransform::ToBlackAndWhite => {
-            // TODO: have a way to dial in the exact threshold for B/W.
+        ColorTransform::ToBlackAndWhite(method) => {
             let mut gray = imageops::grayscale(output.as_ref());
-            imageops::colorops::dither(&mut gray, &imageops::colorops::BiLevel);
+            dither(&mut gray, method);
             output = Cow::Owned(DynamicImage::ImageLuma8(gray));
         }
         ColorTransform::None => {}
@@ -132,3 +272,216 @@ pub fn process_image(
 
     Ok(output)
 }
+
+/// Cuts `img`, along its longer side, into pieces with the proportions of `page`, so that each
+/// piece can fill a page.
+///
+/// Neighboring pieces share `overlap` (0.0 up to 0.5) of their length, so that lines on a cut
+/// show up whole on one of the pages. The last piece is moved back to end with the image, so
+/// it may overlap more.
+pub fn split_image(img: &DynamicImage, page: &PageSize, overlap: f64) -> Vec<DynamicImage> {
+    let (width, height) = img.dimensions();
+    let page_ratio = f64::from(page.height) / f64::from(page.width);
+    let tall = f64::from(height) / f64::from(width) > page_ratio;
+    let (length, piece_length) = if tall {
+        (height, (f64::from(width) * page_ratio).round() as u32)
+    } else {
+        (width, (f64::from(height) / page_ratio).round() as u32)
+    };
+    let piece_length = piece_length.max(1);
+    if piece_length >= length {
+        return vec![img.clone()];
+    }
+
+    let step = ((f64::from(piece_length) * (1.0 - overlap)).round() as u32).max(1);
+    let mut starts = (0..)
+        .map(|i| i * step)
+        .take_while(|start| start + piece_length < length)
+        .collect::<Vec<_>>();
+    starts.push(length - piece_length);
+
+    starts
+        .into_iter()
+        .map(|start| {
+            if tall {
+                img.crop_imm(0, start, width, piece_length)
+            } else {
+                img.crop_imm(start, 0, piece_length, height)
+            }
+        })
+        .collect()
+}
+
+/// A copy of `img` shrunk to fit in `ANALYSIS_SIZE`, for finding things out about it quickly,
+/// and the scale that it was shrunk by. Smaller images keep their size.
+pub(super) fn analysis_copy(img: &GrayImage) -> (GrayImage, f64) {
+    let (width, height) = img.dimensions();
+    let scale = (f64::from(ANALYSIS_SIZE) / f64::from(width.max(height))).min(1.0);
+    let small_width = ((f64::from(width) * scale) as u32).max(1);
+    let small_height = ((f64::from(height) * scale) as u32).max(1);
+    let small = imageops::resize(img, small_width, small_height, FilterType::Triangle);
+    (small, scale)
+}
+
+/// Places `img` on a white page of the size given by `layout`, centered inside the margins.
+///
+/// Grayscale images stay grayscale, everything else becomes RGB. Transparent parts of the
+/// image show the page through them.
+pub fn layout_image(img: &DynamicImage, layout: &Layout) -> Result<DynamicImage> {
+    let page = layout.page;
+    let margins = layout.margin.checked_mul(2);
+    let (area_width, area_height) = match (
+        margins.and_then(|m| page.width.checked_sub(m)),
+        margins.and_then(|m| page.height.checked_sub(m)),
+    ) {
+        (Some(w), Some(h)) if w > 0 && h > 0 => (w, h),
+        _ => {
+            return Err(Error::BadArgsError(format!(
+                "a margin of {} leaves no room on a {}x{} page",
+                layout.margin, page.width, page.height
+            )))
+        }
+    };
+
+    let mut image = Cow::Borrowed(img);
+    if layout.rotate && image.width() > image.height() && page.width < page.height {
+        image = Cow::Owned(image.rotate90());
+    }
+
+    let (width, height) = image.dimensions();
+    let x_scale = f64::from(area_width) / f64::from(width);
+    let y_scale = f64::from(area_height) / f64::from(height);
+    let scale = match layout.scaling {
+        Scaling::Fit => x_scale.min(y_scale),
+        Scaling::Fill => x_scale.max(y_scale),
+        Scaling::Center => x_scale.min(y_scale).min(1.0),
+    };
+    let scaled_width = ((f64::from(width) * scale).round() as u32).max(1);
+    let scaled_height = ((f64::from(height) * scale).round() as u32).max(1);
+    if (scaled_width, scaled_height) != (width, height) {
+        image = Cow::Owned(image.resize_exact(scaled_width, scaled_height, FilterType::CatmullRom));
+    }
+
+    // Only Fill can make the image bigger than the area.
+    let shown_width = scaled_width.min(area_width);
+    let shown_height = scaled_height.min(area_height);
+    let shown = image.crop_imm(
+        (scaled_width - shown_width) / 2,
+        (scaled_height - shown_height) / 2,
+        shown_width,
+        shown_height,
+    );
+
+    let mut canvas = RgbaImage::from_pixel(page.width, page.height, Rgba([255, 255, 255, 255]));
+    imageops::overlay(
+        &mut canvas,
+        &shown.to_rgba8(),
+        layout.margin + (area_width - shown_width) / 2,
+        layout.margin + (area_height - shown_height) / 2,
+    );
+
+    let canvas = DynamicImage::ImageRgba8(canvas);
+    Ok(match img.color() {
+        ColorType::L8 | ColorType::La8 | ColorType::L16 | ColorType::La16 => {
+            DynamicImage::ImageLuma8(canvas.to_luma8())
+        }
+        _ => DynamicImage::ImageRgb8(canvas.to_rgb8()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use printpdf::image::{GrayAlphaImage, GrayImage, Luma};
+
+    fn layout(margin: u32) -> Layout {
+        Layout {
+            page: PageSize::REMARKABLE,
+            scaling: Scaling::Fit,
+            margin,
+            rotate: false,
+        }
+    }
+
+    #[test]
+    fn lays_out_inside_the_margins() {
+        let img = DynamicImage::ImageLuma8(GrayImage::from_pixel(10, 10, Luma([0])));
+        let page = layout_image(&img, &layout(2)).unwrap().to_luma8();
+        assert_eq!(page.dimensions(), (1404, 1872));
+        assert_eq!(page.get_pixel(1, 500)[0], 255);
+        assert_eq!(page.get_pixel(2, 500)[0], 0);
+        assert_eq!(page.get_pixel(1401, 500)[0], 0);
+        assert_eq!(page.get_pixel(1402, 500)[0], 255);
+    }
+
+    #[test]
+    fn analysis_copy_fits_in_the_analysis_size() {
+        let (small, scale) = analysis_copy(&GrayImage::new(1600, 400));
+        assert_eq!(small.dimensions(), (800, 200));
+        assert_eq!(scale, 0.5);
+        let (small, scale) = analysis_copy(&GrayImage::new(10, 5));
+        assert_eq!(small.dimensions(), (10, 5));
+        assert_eq!(scale, 1.0);
+    }
+
+    #[test]
+    fn huge_margins_are_errors() {
+        let img = DynamicImage::ImageLuma8(GrayImage::new(10, 10));
+        for margin in &[702, u32::MAX / 2 + 1, u32::MAX] {
+            match layout_image(&img, &layout(*margin)) {
+                Err(Error::BadArgsError(_)) => {}
+                result => panic!(
+                    "{}: expected BadArgsError, got {:?}",
+                    margin,
+                    result.is_ok()
+                ),
+            }
+        }
+    }
+
+    /// `img` at an alpha of 50: every channel ends up halfway between its value and white,
+    /// after anything transparent has been put on white, so 255 - (255 - c) / 2, rounded down.
+    fn faded(img: DynamicImage) -> DynamicImage {
+        process_image(&img, &[], ColorTransform::None, 50)
+            .unwrap()
+            .into_owned()
+    }
+
+    #[test]
+    fn fades_rgba8_on_white() {
+        let img =
+            RgbaImage::from_raw(3, 1, vec![0, 100, 200, 255, 0, 0, 0, 0, 0, 0, 0, 51]).unwrap();
+        let faded = faded(DynamicImage::ImageRgba8(img));
+        // Opaque; transparent; and 80% of the way to white before fading.
+        assert_eq!(
+            faded.as_rgb8().unwrap().as_raw(),
+            &[127, 177, 227, 255, 255, 255, 229, 229, 229]
+        );
+    }
+
+    #[test]
+    fn fades_luma_a8_on_white() {
+        let img = GrayAlphaImage::from_raw(3, 1, vec![100, 255, 0, 0, 0, 51]).unwrap();
+        let faded = faded(DynamicImage::ImageLumaA8(img));
+        assert_eq!(faded.as_luma8().unwrap().as_raw(), &[177, 255, 229]);
+    }
+
+    #[test]
+    fn fades_rgba16_on_white() {
+        let img =
+            ImageBuffer::<Rgba<u16>, _>::from_raw(2, 1, vec![0, 65535, 25700, 65535, 0, 0, 0, 0])
+                .unwrap();
+        let faded = faded(DynamicImage::ImageRgba16(img));
+        assert_eq!(
+            faded.as_rgb8().unwrap().as_raw(),
+            &[127, 255, 177, 255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn fades_luma16() {
+        let img = ImageBuffer::<Luma<u16>, _>::from_raw(3, 1, vec![0, 25700, 65535]).unwrap();
+        let faded = faded(DynamicImage::ImageLuma16(img));
+        assert_eq!(faded.as_luma8().unwrap().as_raw(), &[127, 177, 255]);
+    }
+}
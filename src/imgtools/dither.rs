@@ -0,0 +1,392 @@
+use crate::{Error, Result};
+use printpdf::image::imageops::colorops::{self, ColorMap};
+use printpdf::image::{GrayImage, Luma};
+use std::str::FromStr;
+
+const BLACK: u8 = 0;
+const WHITE: u8 = 255;
+
+const DEFAULT_THRESHOLD: u8 = 128;
+
+/// How far below the average of its neighborhood a pixel has to be to turn black with
+/// `Dither::Adaptive`, as a fraction of that average.
+const ADAPTIVE_SENSITIVITY: f64 = 0.15;
+
+/// The number of grays that the e-ink screen shows.
+const GRAY_LEVELS: u8 = 16;
+
+/// The classic 8x8 ordered dithering matrix, with values in [0..64).
+const BAYER_8X8: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// Ways of reducing a grayscale image to black and white (or, for `Gray16`, a few grays).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Dither {
+    /// Error diffusion, spreading all of the error to the neighbors. Good for photos.
+    FloydSteinberg,
+    /// Pixels darker than the value turn black, and the rest white.
+    Threshold(u8),
+    /// A fixed threshold, picked from the image's histogram to best separate ink from paper.
+    Otsu,
+    /// A threshold for each pixel from the average of its neighborhood, for scans with shadows
+    /// or uneven lighting.
+    Adaptive,
+    /// Ordered dithering with a Bayer matrix, which makes regular patterns instead of noise.
+    Bayer,
+    /// Error diffusion that only spreads 3/4 of the error, for more contrast and cleaner whites.
+    Atkinson,
+    /// Floyd-Steinberg error diffusion to the 16 grays that the screen can show.
+    Gray16,
+}
+
+impl FromStr for Dither {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bad = || {
+            Error::BadArgsError(format!(
+                "unknown dither '{}', expected floyd-steinberg, threshold[=N], otsu, adaptive, \
+                 bayer, atkinson or gray16",
+                s
+            ))
+        };
+        match s {
+            "floyd-steinberg" | "fs" => Ok(Dither::FloydSteinberg),
+            "threshold" => Ok(Dither::Threshold(DEFAULT_THRESHOLD)),
+            "otsu" => Ok(Dither::Otsu),
+            "adaptive" => Ok(Dither::Adaptive),
+            "bayer" => Ok(Dither::Bayer),
+            "atkinson" => Ok(Dither::Atkinson),
+            "gray16" => Ok(Dither::Gray16),
+            _ => {
+                let value = s.strip_prefix("threshold=").ok_or_else(bad)?;
+                Ok(Dither::Threshold(value.parse().map_err(|_| bad())?))
+            }
+        }
+    }
+}
+
+/// Reduces `img`, in place, to black and white (or a few grays) with `method`.
+pub fn dither(img: &mut GrayImage, method: Dither) {
+    match method {
+        Dither::FloydSteinberg => colorops::dither(img, &colorops::BiLevel),
+        Dither::Threshold(threshold) => apply_threshold(img, threshold),
+        Dither::Otsu => {
+            let threshold = otsu_threshold(img);
+            apply_threshold(img, threshold);
+        }
+        Dither::Adaptive => adaptive_threshold(img),
+        Dither::Bayer => bayer(img),
+        Dither::Atkinson => atkinson(img),
+        Dither::Gray16 => colorops::dither(img, &Gray16),
+    }
+}
+
+/// Turns pixels darker than `threshold` black, and the rest white.
+fn apply_threshold(img: &mut GrayImage, threshold: u8) {
+    for pixel in img.pixels_mut() {
+        pixel[0] = if pixel[0] < threshold { BLACK } else { WHITE };
+    }
+}
+
+/// The threshold that maximizes the variance between the dark and light pixels (Otsu's method).
+pub(super) fn otsu_threshold(img: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in img.pixels() {
+        histogram[pixel[0] as usize] += 1;
+    }
+    let total: u64 = histogram.iter().sum();
+    let total_sum: u64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(v, &n)| v as u64 * n)
+        .sum();
+
+    let mut best = (0.0, DEFAULT_THRESHOLD);
+    let mut dark_count = 0u64;
+    let mut dark_sum = 0u64;
+    // Everything up to and including `value` is dark, so the threshold is one more.
+    for (value, &count) in histogram.iter().enumerate().take(255) {
+        dark_count += count;
+        dark_sum += value as u64 * count;
+        let light_count = total - dark_count;
+        if dark_count == 0 || light_count == 0 {
+            continue;
+        }
+        let dark_mean = dark_sum as f64 / dark_count as f64;
+        let light_mean = (total_sum - dark_sum) as f64 / light_count as f64;
+        let variance = dark_count as f64 * light_count as f64 * (dark_mean - light_mean).powi(2);
+        if variance > best.0 {
+            best = (variance, value as u8 + 1);
+        }
+    }
+    best.1
+}
+
+/// Turns pixels black that are noticeably darker than the average of the square around them
+/// (Bradley's method), which follows changes in the lighting across a page.
+fn adaptive_threshold(img: &mut GrayImage) {
+    let (width, height) = img.dimensions();
+    let (w, h) = (width as usize, height as usize);
+    let radius = (width.max(height) / 16).max(1) as usize;
+
+    // sums[y][x] is the sum of all pixels above and to the left of (x, y).
+    let mut sums = vec![0u64; (w + 1) * (h + 1)];
+    for y in 0..h {
+        let mut row_sum = 0u64;
+        for x in 0..w {
+            row_sum += u64::from(img.get_pixel(x as u32, y as u32)[0]);
+            sums[(y + 1) * (w + 1) + x + 1] = sums[y * (w + 1) + x + 1] + row_sum;
+        }
+    }
+
+    for y in 0..h {
+        let (top, bottom) = (y.saturating_sub(radius), (y + radius + 1).min(h));
+        for x in 0..w {
+            let (left, right) = (x.saturating_sub(radius), (x + radius + 1).min(w));
+            let area = ((bottom - top) * (right - left)) as f64;
+            let sum = sums[bottom * (w + 1) + right] + sums[top * (w + 1) + left]
+                - sums[top * (w + 1) + right]
+                - sums[bottom * (w + 1) + left];
+            let pixel = img.get_pixel_mut(x as u32, y as u32);
+            let limit = sum as f64 / area * (1.0 - ADAPTIVE_SENSITIVITY);
+            pixel[0] = if f64::from(pixel[0]) < limit {
+                BLACK
+            } else {
+                WHITE
+            };
+        }
+    }
+}
+
+/// Ordered dithering: each pixel is compared to its own threshold from a tiled Bayer matrix.
+fn bayer(img: &mut GrayImage) {
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        let level = u16::from(BAYER_8X8[y as usize % 8][x as usize % 8]);
+        // Spreads the 64 levels evenly over [0..256), centered in their steps.
+        let threshold = level * 4 + 2;
+        pixel[0] = if u16::from(pixel[0]) < threshold {
+            BLACK
+        } else {
+            WHITE
+        };
+    }
+}
+
+/// Atkinson dithering: 1/8 of the error goes to each of six neighbors, and the rest is dropped.
+fn atkinson(img: &mut GrayImage) {
+    const NEIGHBORS: [(i64, i64); 6] = [(1, 0), (2, 0), (-1, 1), (0, 1), (1, 1), (0, 2)];
+
+    let (width, height) = img.dimensions();
+    let (w, h) = (i64::from(width), i64::from(height));
+    let mut values = img.pixels().map(|p| i32::from(p[0])).collect::<Vec<_>>();
+    for y in 0..h {
+        for x in 0..w {
+            let index = (y * w + x) as usize;
+            let old = values[index];
+            let new = if old < i32::from(DEFAULT_THRESHOLD) {
+                i32::from(BLACK)
+            } else {
+                i32::from(WHITE)
+            };
+            values[index] = new;
+            let error = (old - new) / 8;
+            for (dx, dy) in NEIGHBORS.iter() {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx >= 0 && nx < w && ny < h {
+                    values[(ny * w + nx) as usize] += error;
+                }
+            }
+        }
+    }
+    for (pixel, value) in img.pixels_mut().zip(values) {
+        pixel[0] = value as u8;
+    }
+}
+
+/// The evenly spaced grays of the e-ink screen, for error diffusion with `colorops::dither`.
+struct Gray16;
+
+impl Gray16 {
+    const STEP: u8 = WHITE / (GRAY_LEVELS - 1);
+}
+
+impl ColorMap for Gray16 {
+    type Color = Luma<u8>;
+
+    fn index_of(&self, color: &Luma<u8>) -> usize {
+        ((u16::from(color[0]) + u16::from(Self::STEP) / 2) / u16::from(Self::STEP)) as usize
+    }
+
+    fn lookup(&self, index: usize) -> Option<Luma<u8>> {
+        if index < GRAY_LEVELS as usize {
+            Some(Luma([index as u8 * Self::STEP]))
+        } else {
+            None
+        }
+    }
+
+    fn has_lookup(&self) -> bool {
+        true
+    }
+
+    fn map_color(&self, color: &mut Luma<u8>) {
+        color[0] = self.index_of(color) as u8 * Self::STEP;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32, pixels: &[u8]) -> GrayImage {
+        GrayImage::from_raw(width, height, pixels.to_vec()).unwrap()
+    }
+
+    fn dithered(mut img: GrayImage, method: Dither) -> Vec<u8> {
+        dither(&mut img, method);
+        img.into_raw()
+    }
+
+    #[test]
+    fn parses_methods() {
+        assert_eq!("fs".parse::<Dither>().unwrap(), Dither::FloydSteinberg);
+        assert_eq!("otsu".parse::<Dither>().unwrap(), Dither::Otsu);
+        assert_eq!("gray16".parse::<Dither>().unwrap(), Dither::Gray16);
+        assert_eq!(
+            "threshold".parse::<Dither>().unwrap(),
+            Dither::Threshold(DEFAULT_THRESHOLD)
+        );
+        assert_eq!(
+            "threshold=100".parse::<Dither>().unwrap(),
+            Dither::Threshold(100)
+        );
+        assert_eq!(
+            "threshold=0".parse::<Dither>().unwrap(),
+            Dither::Threshold(0)
+        );
+
+        for bad in &[
+            "",
+            "sepia",
+            "Otsu",
+            "threshold=",
+            "threshold=256",
+            "threshold=-1",
+            "threshold=1.5",
+            "bayer=4",
+        ] {
+            match bad.parse::<Dither>() {
+                Err(Error::BadArgsError(_)) => {}
+                result => panic!("'{}': expected BadArgsError, got {:?}", bad, result),
+            }
+        }
+    }
+
+    #[test]
+    fn floyd_steinberg_is_black_and_white() {
+        let pixels = (0..=255).collect::<Vec<u8>>();
+        let result = dithered(image(16, 16, &pixels), Dither::FloydSteinberg);
+        assert!(result.iter().all(|&p| p == BLACK || p == WHITE));
+        assert_eq!(result[0], BLACK);
+        assert_eq!(result[255], WHITE);
+    }
+
+    #[test]
+    fn threshold_splits_at_the_value() {
+        let img = image(4, 1, &[0, 127, 128, 255]);
+        assert_eq!(
+            dithered(img.clone(), Dither::Threshold(128)),
+            [0, 0, 255, 255]
+        );
+        assert_eq!(dithered(img.clone(), Dither::Threshold(0)), [255; 4]);
+        assert_eq!(dithered(img, Dither::Threshold(255)), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn otsu_separates_two_grays() {
+        let img = image(2, 2, &[10, 200, 200, 10]);
+        // The first threshold that puts the 10s on one side and the 200s on the other.
+        assert_eq!(otsu_threshold(&img), 11);
+        assert_eq!(dithered(img, Dither::Otsu), [0, 255, 255, 0]);
+
+        // With only one gray, there is nothing to separate.
+        assert_eq!(otsu_threshold(&image(2, 2, &[90; 4])), DEFAULT_THRESHOLD);
+    }
+
+    #[test]
+    fn adaptive_finds_ink_in_shadow() {
+        // A page in shadow, darker everywhere than a fixed threshold, with one mark on it.
+        let mut pixels = [60; 16 * 16];
+        pixels[3 * 16 + 3] = 20;
+        let mut expected = [255; 16 * 16];
+        expected[3 * 16 + 3] = 0;
+        assert_eq!(
+            dithered(image(16, 16, &pixels), Dither::Adaptive),
+            expected.to_vec()
+        );
+    }
+
+    #[test]
+    fn bayer_makes_the_matrix_pattern() {
+        // Half gray turns the cells with the upper half of the matrix's values black, which makes
+        // a checkerboard.
+        let expected = (0..64)
+            .map(|i| if (i % 8 + i / 8) % 2 == 1 { 0 } else { 255 })
+            .collect::<Vec<u8>>();
+        assert_eq!(dithered(image(8, 8, &[128; 64]), Dither::Bayer), expected);
+
+        // At a quarter of white, a quarter of the cells stay white.
+        let result = dithered(image(8, 8, &[64; 64]), Dither::Bayer);
+        assert_eq!(result.iter().filter(|&&p| p == WHITE).count(), 16);
+        assert!(result.iter().all(|&p| p == BLACK || p == WHITE));
+
+        // The matrix repeats every 8 pixels.
+        let result = dithered(image(16, 8, &[128; 128]), Dither::Bayer);
+        for (i, pixel) in result.iter().enumerate() {
+            assert_eq!(*pixel, expected[(i / 16) * 8 + i % 8]);
+        }
+    }
+
+    #[test]
+    fn atkinson_diffuses_the_error() {
+        #[rustfmt::skip]
+        let dark = [
+            0, 0, 0, 255,
+            0, 255, 0, 0,
+            0, 255, 255, 0,
+            0, 0, 0, 255,
+        ];
+        assert_eq!(dithered(image(4, 4, &[100; 16]), Dither::Atkinson), dark);
+
+        #[rustfmt::skip]
+        let light = [
+            255, 255, 255, 255,
+            255, 255, 255, 255,
+            255, 255, 255, 255,
+            255, 255, 0, 255,
+        ];
+        assert_eq!(dithered(image(4, 4, &[200; 16]), Dither::Atkinson), light);
+    }
+
+    #[test]
+    fn gray16_uses_the_screen_grays() {
+        let pixels = (0..=255).collect::<Vec<u8>>();
+        let result = dithered(image(16, 16, &pixels), Dither::Gray16);
+        assert!(result.iter().all(|p| p % Gray16::STEP == 0));
+
+        // Grays that the screen can show are left alone.
+        let grays = (0..GRAY_LEVELS)
+            .map(|i| i * Gray16::STEP)
+            .collect::<Vec<_>>();
+        assert_eq!(dithered(image(16, 1, &grays), Dither::Gray16), grays);
+    }
+}
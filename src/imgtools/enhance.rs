@@ -0,0 +1,591 @@
+use super::dither::otsu_threshold;
+use super::perspective::correct_perspective;
+use super::{analysis_copy, flatten_image};
+use crate::{Error, Result};
+use printpdf::image::imageops::{self, FilterType};
+use printpdf::image::{DynamicImage, GenericImageView, GrayImage, Luma};
+use std::str::FromStr;
+
+const DEFAULT_UNSHARP_SIGMA: f32 = 1.0;
+const DEFAULT_UNSHARP_THRESHOLD: i32 = 4;
+
+/// The fraction of the darkest and of the lightest pixels that auto-levels ignores, so that a
+/// few specks don't decide the range.
+const LEVELS_CLIP: f64 = 0.005;
+
+/// The largest skew, in degrees, that deskew looks for.
+const MAX_SKEW: f64 = 10.0;
+
+/// How different from the edge a pixel has to be to count as content when cropping.
+const CROP_TOLERANCE: i16 = 48;
+
+/// The fraction of a line that may differ from the edge for the line to still be cropped.
+const CROP_NOISE: f64 = 0.01;
+
+const WHITE: u8 = 255;
+
+/// One step of cleaning up an image, usually a scan.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    /// Stretches the contrast so that the darkest pixels are black and the lightest white.
+    AutoLevels,
+    /// Gamma correction. Values above 1 lighten the midtones, values below 1 darken them.
+    Gamma(f64),
+    /// Sharpens edges with an unsharp mask of the given blur and threshold.
+    Unsharp { sigma: f32, threshold: i32 },
+    /// Removes specks by replacing each pixel with the median of its neighborhood.
+    Despeckle,
+    /// Turns the paper white, evening out shadows and yellowing, without touching the ink.
+    Whiten,
+    /// Straightens an image whose lines of text are slightly rotated.
+    Deskew,
+    /// Finds the sheet of paper in a photo and flattens it into a rectangle, as if scanned.
+    Perspective,
+    /// Cuts off borders of a single color, like the dark edges around a scanned page.
+    AutoCrop,
+}
+
+impl FromStr for Step {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bad = || {
+            Error::BadArgsError(format!(
+                "unknown enhancement '{}', expected levels, gamma=G, unsharp[=SIGMA[:THRESHOLD]], \
+                 despeckle, whiten, deskew, page or crop",
+                s
+            ))
+        };
+        let (name, params) = match s.find('=') {
+            Some(eq) => (&s[..eq], Some(&s[eq + 1..])),
+            None => (s, None),
+        };
+        match (name, params) {
+            ("levels" | "auto-levels", None) => Ok(Step::AutoLevels),
+            ("gamma", Some(gamma)) => match gamma.parse() {
+                Ok(gamma) if gamma > 0.0 => Ok(Step::Gamma(gamma)),
+                _ => Err(bad()),
+            },
+            ("unsharp", params) => {
+                let mut params = params.unwrap_or_default().split(':');
+                let sigma = match params.next().filter(|p| !p.is_empty()) {
+                    Some(sigma) => sigma.parse().map_err(|_| bad())?,
+                    None => DEFAULT_UNSHARP_SIGMA,
+                };
+                let threshold = match params.next() {
+                    Some(threshold) => threshold.parse().map_err(|_| bad())?,
+                    None => DEFAULT_UNSHARP_THRESHOLD,
+                };
+                if params.next().is_some() || sigma <= 0.0 {
+                    return Err(bad());
+                }
+                Ok(Step::Unsharp { sigma, threshold })
+            }
+            ("despeckle", None) => Ok(Step::Despeckle),
+            ("whiten", None) => Ok(Step::Whiten),
+            ("deskew", None) => Ok(Step::Deskew),
+            ("page" | "perspective", None) => Ok(Step::Perspective),
+            ("crop" | "auto-crop", None) => Ok(Step::AutoCrop),
+            _ => Err(bad()),
+        }
+    }
+}
+
+/// Parses a comma separated list of steps, like `levels,gamma=1.2,unsharp`.
+pub fn parse_steps(spec: &str) -> Result<Vec<Step>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// Applies `steps` to `img`, in order. The result is flattened like `flatten_image`.
+pub fn enhance(img: &DynamicImage, steps: &[Step]) -> DynamicImage {
+    let mut image = flatten_image(img).unwrap_or_else(|| img.clone());
+    for step in steps {
+        image = match *step {
+            Step::AutoLevels => auto_levels(image),
+            Step::Gamma(gamma) => gamma_correct(image, gamma),
+            Step::Unsharp { sigma, threshold } => image.unsharpen(sigma, threshold),
+            Step::Despeckle => despeckle(&image),
+            Step::Whiten => whiten(image),
+            Step::Deskew => deskew(&image),
+            Step::Perspective => correct_perspective(&image),
+            Step::AutoCrop => auto_crop(&image),
+        };
+    }
+    image
+}
+
+/// The channel values of a flattened image.
+fn subpixels_mut(img: &mut DynamicImage) -> &mut [u8] {
+    match img {
+        DynamicImage::ImageLuma8(buffer) => buffer,
+        DynamicImage::ImageRgb8(buffer) => buffer,
+        // enhance flattens the image first, and none of the steps change its type.
+        _ => unreachable!("enhancing an image that wasn't flattened"),
+    }
+}
+
+fn apply_lut(mut img: DynamicImage, lut: &[u8; 256]) -> DynamicImage {
+    for value in subpixels_mut(&mut img) {
+        *value = lut[*value as usize];
+    }
+    img
+}
+
+fn auto_levels(img: DynamicImage) -> DynamicImage {
+    let mut histogram = [0u64; 256];
+    for pixel in img.to_luma8().pixels() {
+        histogram[pixel[0] as usize] += 1;
+    }
+    let clip = (LEVELS_CLIP * f64::from(img.width() * img.height())) as u64;
+    let low = percentile(&histogram, clip, 0..256);
+    let high = percentile(&histogram, clip, (0..256).rev());
+    if high <= low {
+        return img;
+    }
+
+    let mut lut = [0; 256];
+    for (v, entry) in lut.iter_mut().enumerate() {
+        *entry = ((v as f64 - low) / (high - low) * 255.0)
+            .round()
+            .clamp(0.0, 255.0) as u8;
+    }
+    apply_lut(img, &lut)
+}
+
+/// The first of `values` at which more than `clip` pixels have been seen.
+fn percentile(histogram: &[u64; 256], clip: u64, values: impl Iterator<Item = usize>) -> f64 {
+    let mut seen = 0;
+    for v in values {
+        seen += histogram[v];
+        if seen > clip {
+            return v as f64;
+        }
+    }
+    0.0
+}
+
+fn gamma_correct(img: DynamicImage, gamma: f64) -> DynamicImage {
+    let mut lut = [0; 256];
+    for (v, entry) in lut.iter_mut().enumerate() {
+        *entry = ((v as f64 / 255.0).powf(1.0 / gamma) * 255.0).round() as u8;
+    }
+    apply_lut(img, &lut)
+}
+
+/// A 3x3 median filter on each channel.
+fn despeckle(img: &DynamicImage) -> DynamicImage {
+    let mut out = img.clone();
+    let (width, height) = img.dimensions();
+    let (w, h) = (width as usize, height as usize);
+    let source = img.as_bytes();
+    let channels = source.len() / (w * h).max(1);
+    let target = subpixels_mut(&mut out);
+    let mut window = Vec::with_capacity(9);
+    for y in 0..h {
+        for x in 0..w {
+            for c in 0..channels {
+                window.clear();
+                for ny in y.saturating_sub(1)..(y + 2).min(h) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(w) {
+                        window.push(source[(ny * w + nx) * channels + c]);
+                    }
+                }
+                window.sort_unstable();
+                target[(y * w + x) * channels + c] = window[window.len() / 2];
+            }
+        }
+    }
+    out
+}
+
+/// Divides every pixel by an estimate of the paper behind it.
+///
+/// The paper is found by shrinking the image, spreading the lightest pixels over the ink with
+/// a maximum filter, and blurring. Since that follows gradual changes in the lighting, shadows
+/// and yellowed paper go, while ink stays as dark as it was relative to its surroundings.
+fn whiten(mut img: DynamicImage) -> DynamicImage {
+    let (width, height) = img.dimensions();
+    let (small, _) = analysis_copy(&img.to_luma8());
+    let paper = imageops::blur(&max_filter(&small, 4), 4.0);
+    let paper = imageops::resize(&paper, width, height, FilterType::Triangle);
+
+    let channels = img.as_bytes().len() / (width * height).max(1) as usize;
+    for (i, value) in subpixels_mut(&mut img).iter_mut().enumerate() {
+        let background = paper.as_raw()[i / channels].max(1);
+        *value = (u32::from(*value) * u32::from(WHITE) / u32::from(background)).min(255) as u8;
+    }
+    img
+}
+
+/// Replaces each pixel with the lightest pixel within `radius` of it.
+pub(super) fn max_filter(img: &GrayImage, radius: u32) -> GrayImage {
+    extreme_filter(img, radius, u8::max)
+}
+
+/// Replaces each pixel with the darkest pixel within `radius` of it.
+pub(super) fn min_filter(img: &GrayImage, radius: u32) -> GrayImage {
+    extreme_filter(img, radius, u8::min)
+}
+
+/// Replaces each pixel with the one that `pick` prefers in the square of `radius` around it.
+fn extreme_filter(img: &GrayImage, radius: u32, pick: fn(u8, u8) -> u8) -> GrayImage {
+    let (width, height) = img.dimensions();
+    // The filter is separable, so rows and then columns.
+    let rows = GrayImage::from_fn(width, height, |x, y| {
+        let (left, right) = (x.saturating_sub(radius), (x + radius).min(width - 1));
+        let first = img.get_pixel(left, y)[0];
+        Luma([(left..=right).fold(first, |v, nx| pick(v, img.get_pixel(nx, y)[0]))])
+    });
+    GrayImage::from_fn(width, height, |x, y| {
+        let (top, bottom) = (y.saturating_sub(radius), (y + radius).min(height - 1));
+        let first = rows.get_pixel(x, top)[0];
+        Luma([(top..=bottom).fold(first, |v, ny| pick(v, rows.get_pixel(x, ny)[0]))])
+    })
+}
+
+pub(super) fn deskew(img: &DynamicImage) -> DynamicImage {
+    let angle = skew_angle(&img.to_luma8());
+    if angle.abs() < 0.05 {
+        return img.clone();
+    }
+    let (width, height) = img.dimensions();
+    let (cx, cy) = (f64::from(width) / 2.0, f64::from(height) / 2.0);
+    let (sin, cos) = angle.to_radians().sin_cos();
+    // Each pixel of the straight image comes from the pixel rotated by the skew.
+    warp(img, width, height, |x, y| {
+        let (dx, dy) = (x - cx, y - cy);
+        (cx + dx * cos - dy * sin, cy + dx * sin + dy * cos)
+    })
+}
+
+/// The angle, in degrees clockwise, that the lines of text in `img` are rotated by, or 0 if
+/// there is no text to go by.
+///
+/// When the angle is right, the dark pixels, projected onto the vertical axis, bunch up into
+/// lines with white space in between, which is measured by the sum of the squares of the counts
+/// in each row.
+pub(super) fn skew_angle(img: &GrayImage) -> f64 {
+    let (small, _) = analysis_copy(img);
+    let (small_width, small_height) = small.dimensions();
+    let threshold = otsu_threshold(&small);
+    let (cx, cy) = (f64::from(small_width) / 2.0, f64::from(small_height) / 2.0);
+    let ink = small
+        .enumerate_pixels()
+        .filter(|(_, _, p)| p[0] < threshold)
+        .map(|(x, y, _)| (f64::from(x) - cx, f64::from(y) - cy))
+        .collect::<Vec<_>>();
+    // Too little ink, or too much (a photo rather than text), says nothing about the angle.
+    let pixels = (small_width * small_height) as usize;
+    if ink.len() < 100 || ink.len() > pixels / 2 {
+        return 0.0;
+    }
+
+    let rows = (f64::from(small_width.max(small_height)) * 1.5) as usize;
+    let mut counts = vec![0u32; rows];
+    let mut score = |angle: f64| {
+        counts.iter_mut().for_each(|c| *c = 0);
+        let (sin, cos) = angle.to_radians().sin_cos();
+        for (x, y) in &ink {
+            let row = (y * cos - x * sin + rows as f64 / 2.0) as usize;
+            if let Some(count) = counts.get_mut(row) {
+                *count += 1;
+            }
+        }
+        counts
+            .iter()
+            .map(|&c| u64::from(c) * u64::from(c))
+            .sum::<u64>()
+    };
+
+    let mut best = (score(0.0), 0.0);
+    for (step, range) in [(0.5, MAX_SKEW), (0.05, 0.5)] {
+        let center = best.1;
+        let steps = (range / step) as i32;
+        for i in -steps..=steps {
+            let angle = center + f64::from(i) * step;
+            let s = score(angle);
+            if s > best.0 {
+                best = (s, angle);
+            }
+        }
+    }
+    best.1
+}
+
+/// Makes a `width` by `height` image whose pixel at (x, y) is the pixel of `img` at
+/// `source(x, y)`, interpolated. Pixels from outside of `img` are white.
+pub(super) fn warp(
+    img: &DynamicImage,
+    width: u32,
+    height: u32,
+    source: impl Fn(f64, f64) -> (f64, f64),
+) -> DynamicImage {
+    let (src_width, src_height) = img.dimensions();
+    let (sw, sh) = (src_width as usize, src_height as usize);
+    let pixels = img.as_bytes();
+    let channels = pixels.len() / (sw * sh).max(1);
+    let sample = |x: f64, y: f64, c: usize| -> f64 {
+        if x >= 0.0 && y >= 0.0 && (x as usize) < sw && (y as usize) < sh {
+            f64::from(pixels[(y as usize * sw + x as usize) * channels + c])
+        } else {
+            f64::from(WHITE)
+        }
+    };
+
+    let mut out = match img {
+        DynamicImage::ImageLuma8(_) => DynamicImage::new_luma8(width, height),
+        _ => DynamicImage::new_rgb8(width, height),
+    };
+    let target = subpixels_mut(&mut out);
+    for y in 0..height as usize {
+        for x in 0..width as usize {
+            // Pixel centers are at the halves.
+            let (sx, sy) = source(x as f64 + 0.5, y as f64 + 0.5);
+            let (sx, sy) = (sx - 0.5, sy - 0.5);
+            let (x0, y0) = (sx.floor(), sy.floor());
+            let (fx, fy) = (sx - x0, sy - y0);
+            for c in 0..channels {
+                let top = sample(x0, y0, c) * (1.0 - fx) + sample(x0 + 1.0, y0, c) * fx;
+                let bottom =
+                    sample(x0, y0 + 1.0, c) * (1.0 - fx) + sample(x0 + 1.0, y0 + 1.0, c) * fx;
+                let value = top * (1.0 - fy) + bottom * fy;
+                target[(y * width as usize + x) * channels + c] = value.round() as u8;
+            }
+        }
+    }
+    out
+}
+
+/// Crops each side of the image while its outermost line is all one color.
+fn auto_crop(img: &DynamicImage) -> DynamicImage {
+    let gray = img.to_luma8();
+    let (width, height) = gray.dimensions();
+    if width == 0 || height == 0 {
+        return img.clone();
+    }
+
+    // Whether the pixels of a line are, nearly all, the same as its median.
+    let uniform = |line: &mut dyn Iterator<Item = (u32, u32)>| {
+        let mut values = line
+            .map(|(x, y)| gray.get_pixel(x, y)[0])
+            .collect::<Vec<_>>();
+        values.sort_unstable();
+        let median = i16::from(values[values.len() / 2]);
+        let differing = values
+            .iter()
+            .filter(|&&v| (i16::from(v) - median).abs() > CROP_TOLERANCE)
+            .count();
+        (differing as f64) <= CROP_NOISE * values.len() as f64
+    };
+
+    let (mut left, mut top, mut right, mut bottom) = (0, 0, width, height);
+    while top + 1 < bottom && uniform(&mut (left..right).map(|x| (x, top))) {
+        top += 1;
+    }
+    while bottom - 1 > top && uniform(&mut (left..right).map(|x| (x, bottom - 1))) {
+        bottom -= 1;
+    }
+    while left + 1 < right && uniform(&mut (top..bottom).map(|y| (left, y))) {
+        left += 1;
+    }
+    while right - 1 > left && uniform(&mut (top..bottom).map(|y| (right - 1, y))) {
+        right -= 1;
+    }
+    // A blank image is all border.
+    if bottom - top <= 1 || right - left <= 1 {
+        return img.clone();
+    }
+    img.crop_imm(left, top, right - left, bottom - top)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32, pixels: &[u8]) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, pixels.to_vec()).unwrap())
+    }
+
+    fn pixels(img: &DynamicImage) -> &[u8] {
+        img.as_luma8().unwrap().as_raw()
+    }
+
+    #[test]
+    fn parses_steps() {
+        assert_eq!("levels".parse::<Step>().unwrap(), Step::AutoLevels);
+        assert_eq!("auto-levels".parse::<Step>().unwrap(), Step::AutoLevels);
+        assert_eq!("gamma=1.5".parse::<Step>().unwrap(), Step::Gamma(1.5));
+        assert_eq!(
+            "unsharp".parse::<Step>().unwrap(),
+            Step::Unsharp {
+                sigma: DEFAULT_UNSHARP_SIGMA,
+                threshold: DEFAULT_UNSHARP_THRESHOLD
+            }
+        );
+        assert_eq!(
+            "unsharp=2.5".parse::<Step>().unwrap(),
+            Step::Unsharp {
+                sigma: 2.5,
+                threshold: DEFAULT_UNSHARP_THRESHOLD
+            }
+        );
+        assert_eq!(
+            "unsharp=:10".parse::<Step>().unwrap(),
+            Step::Unsharp {
+                sigma: DEFAULT_UNSHARP_SIGMA,
+                threshold: 10
+            }
+        );
+        assert_eq!("despeckle".parse::<Step>().unwrap(), Step::Despeckle);
+        assert_eq!("whiten".parse::<Step>().unwrap(), Step::Whiten);
+        assert_eq!("deskew".parse::<Step>().unwrap(), Step::Deskew);
+        assert_eq!("page".parse::<Step>().unwrap(), Step::Perspective);
+        assert_eq!("perspective".parse::<Step>().unwrap(), Step::Perspective);
+        assert_eq!("crop".parse::<Step>().unwrap(), Step::AutoCrop);
+
+        for bad in &[
+            "gamma=0",
+            "gamma=-1",
+            "gamma",
+            "gamma=x",
+            "unsharp=0",
+            "unsharp=1:2:3",
+            "unsharp=1:x",
+            "levels=2",
+            "sharpen",
+            "",
+        ] {
+            match bad.parse::<Step>() {
+                Err(Error::BadArgsError(_)) => {}
+                result => panic!("{}: expected BadArgsError, got {:?}", bad, result),
+            }
+        }
+    }
+
+    #[test]
+    fn parses_lists_of_steps() {
+        assert_eq!(
+            parse_steps("levels, gamma=2,,crop").unwrap(),
+            [Step::AutoLevels, Step::Gamma(2.0), Step::AutoCrop]
+        );
+        assert_eq!(parse_steps("").unwrap(), []);
+        match parse_steps("levels,sharpen") {
+            Err(Error::BadArgsError(msg)) => assert!(msg.contains("'sharpen'"), "{}", msg),
+            result => panic!("expected BadArgsError, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn auto_levels_stretches_to_black_and_white() {
+        let img = image(4, 1, &[50, 100, 150, 200]);
+        assert_eq!(pixels(&auto_levels(img)), &[0, 85, 170, 255]);
+        // Nothing to stretch.
+        let img = image(2, 1, &[90, 90]);
+        assert_eq!(pixels(&auto_levels(img)), &[90, 90]);
+    }
+
+    #[test]
+    fn gamma_lightens_the_midtones() {
+        let img = image(4, 1, &[0, 64, 128, 255]);
+        assert_eq!(pixels(&gamma_correct(img, 2.0)), &[0, 128, 181, 255]);
+    }
+
+    #[test]
+    fn despeckle_removes_specks_and_keeps_shapes() {
+        // The speck goes, and the block loses only the corner that sticks out into the white.
+        #[rustfmt::skip]
+        let img = image(5, 5, &[
+            255, 255, 255, 255, 255,
+            255,   0, 255, 255, 255,
+            255, 255, 255,   0,   0,
+            255, 255, 255,   0,   0,
+            255, 255, 255,   0,   0,
+        ]);
+        #[rustfmt::skip]
+        let expected = [
+            255, 255, 255, 255, 255,
+            255, 255, 255, 255, 255,
+            255, 255, 255, 255,   0,
+            255, 255, 255,   0,   0,
+            255, 255, 255,   0,   0,
+        ];
+        assert_eq!(pixels(&despeckle(&img)), &expected);
+    }
+
+    #[test]
+    fn whiten_makes_the_paper_white_and_keeps_the_ink() {
+        // Gray paper with a dark mark in the middle.
+        let mut page = GrayImage::from_pixel(40, 40, Luma([200]));
+        for (x, y) in &[(20, 20), (21, 20), (20, 21), (21, 21)] {
+            page.put_pixel(*x, *y, Luma([50]));
+        }
+        let whitened = whiten(DynamicImage::ImageLuma8(page)).to_luma8();
+        assert_eq!(whitened.get_pixel(0, 0)[0], WHITE);
+        assert_eq!(whitened.get_pixel(10, 30)[0], WHITE);
+        // 50 on paper of 200 is as dark as 63 on white.
+        assert_eq!(whitened.get_pixel(20, 20)[0], 63);
+    }
+
+    /// White with dark lines, 3 pixels thick every 20 pixels, sloping down by `angle` degrees.
+    fn lines(angle: f64) -> GrayImage {
+        let slope = angle.to_radians().tan();
+        GrayImage::from_fn(400, 400, |x, y| {
+            let offset = f64::from(y) - f64::from(x) * slope;
+            Luma([if offset.rem_euclid(20.0) < 3.0 {
+                0
+            } else {
+                255
+            }])
+        })
+    }
+
+    #[test]
+    fn skew_angle_follows_the_lines() {
+        for &angle in &[0.0, 3.0, -6.5] {
+            let found = skew_angle(&lines(angle));
+            assert!((found - angle).abs() <= 0.1, "{} found as {}", angle, found);
+        }
+    }
+
+    #[test]
+    fn skew_angle_is_zero_without_lines() {
+        assert_eq!(
+            skew_angle(&GrayImage::from_pixel(100, 100, Luma([255]))),
+            0.0
+        );
+        assert_eq!(skew_angle(&GrayImage::from_pixel(100, 100, Luma([0]))), 0.0);
+    }
+
+    #[test]
+    fn auto_crop_cuts_the_borders() {
+        // A black frame, 2 pixels wide, with a mark inside.
+        let mut img = GrayImage::from_fn(12, 10, |x, y| {
+            let frame = x < 2 || y < 2 || x >= 10 || y >= 8;
+            Luma([if frame { 0 } else { 255 }])
+        });
+        for (x, y) in &[(5, 4), (6, 4), (5, 5), (6, 5)] {
+            img.put_pixel(*x, *y, Luma([0]));
+        }
+        let cropped = auto_crop(&DynamicImage::ImageLuma8(img));
+        // The frame goes, and then the white columns beside the mark.
+        assert_eq!(cropped.dimensions(), (2, 6));
+        assert_eq!(
+            pixels(&cropped),
+            &[255, 255, 255, 255, 0, 0, 0, 0, 255, 255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn auto_crop_keeps_blank_and_empty_images() {
+        let blank = image(3, 2, &[255; 6]);
+        assert_eq!(pixels(&auto_crop(&blank)), &[255; 6]);
+        for &(width, height) in &[(0, 0), (0, 5), (5, 0)] {
+            let empty = DynamicImage::new_luma8(width, height);
+            assert_eq!(auto_crop(&empty).dimensions(), (width, height));
+        }
+    }
+}
@@ -0,0 +1,320 @@
+use super::analysis_copy;
+use super::dither::otsu_threshold;
+use super::enhance::{deskew, max_filter, min_filter, warp};
+use log::debug;
+use printpdf::image::imageops;
+use printpdf::image::{DynamicImage, GrayImage};
+
+/// The smallest part of the picture that a page may take up.
+const MIN_PAGE_AREA: f64 = 0.2;
+
+/// How much of the quadrilateral through the corners the paper has to fill for it to be taken
+/// as a page. Anything else is probably not a sheet of paper.
+const MIN_FILL: f64 = 0.9;
+
+/// A page that takes up more of the picture than this is already as flat as it gets.
+const MAX_PAGE_AREA: f64 = 0.98;
+
+/// How far, as a fraction of the analysis size, writing on the page is filled in with the color
+/// of the paper before the page is looked for.
+const CLOSING_RADIUS: f64 = 0.01;
+
+/// How close to the edge of the picture a corner may be, in pixels of the analysis size. A page
+/// whose corner is cut off by the edge, or that runs off it, can't be flattened.
+const EDGE_MARGIN: f64 = 2.0;
+
+type Point = (f64, f64);
+
+/// Finds the sheet of paper in a photo, and stretches it back into a flat rectangle the way a
+/// scanner would have seen it, then straightens what rotation is left.
+///
+/// If no page can be found, the image is returned as it is.
+pub(super) fn correct_perspective(img: &DynamicImage) -> DynamicImage {
+    let corners = match find_page(&img.to_luma8()) {
+        Some(corners) => corners,
+        None => {
+            debug!("no page found, leaving the perspective alone");
+            return img.clone();
+        }
+    };
+    let [top_left, top_right, bottom_right, bottom_left] = corners;
+    debug!("page corners: {:?}", corners);
+
+    let width = distance(top_left, top_right).max(distance(bottom_left, bottom_right));
+    let height = distance(top_left, bottom_left).max(distance(top_right, bottom_right));
+    let (width, height) = (width.round() as u32, height.round() as u32);
+    let rect = [
+        (0.0, 0.0),
+        (f64::from(width), 0.0),
+        (f64::from(width), f64::from(height)),
+        (0.0, f64::from(height)),
+    ];
+    let homography = match Homography::mapping(&rect, &corners) {
+        Some(homography) => homography,
+        None => return img.clone(),
+    };
+
+    let flat = warp(img, width, height, |x, y| homography.apply((x, y)));
+    deskew(&flat)
+}
+
+/// The corners of the page in `img`, clockwise from the top left, in the coordinates of `img`.
+///
+/// The page is the largest light region, found with Otsu's threshold after the writing on it is
+/// filled in, and its corners are the points furthest along each of the diagonals.
+fn find_page(img: &GrayImage) -> Option<[Point; 4]> {
+    let (small, scale) = analysis_copy(img);
+    let (small_width, small_height) = small.dimensions();
+    let small = imageops::blur(&small, 2.0);
+    let radius = (f64::from(small_width.max(small_height)) * CLOSING_RADIUS).ceil() as u32;
+    let small = min_filter(&max_filter(&small, radius), radius);
+
+    let threshold = otsu_threshold(&small);
+    let region = largest_region(&small, threshold);
+    let total = f64::from(small_width * small_height);
+    if (region.len() as f64) < MIN_PAGE_AREA * total {
+        return None;
+    }
+
+    let extreme = |key: &dyn Fn(&(u32, u32)) -> i64| {
+        let min = *region.iter().min_by_key(|p| key(p))?;
+        let max = *region.iter().max_by_key(|p| key(p))?;
+        Some((min, max))
+    };
+    let (top_left, bottom_right) = extreme(&|&(x, y)| i64::from(x) + i64::from(y))?;
+    let (bottom_left, top_right) = extreme(&|&(x, y)| i64::from(x) - i64::from(y))?;
+    let corners = [top_left, top_right, bottom_right, bottom_left]
+        .map(|(x, y)| (f64::from(x) + 0.5, f64::from(y) + 0.5));
+
+    let area = quad_area(&corners);
+    if area <= 0.0 || (region.len() as f64) < MIN_FILL * area {
+        return None;
+    }
+    if area > MAX_PAGE_AREA * total {
+        return None;
+    }
+    let (right, bottom) = (f64::from(small_width), f64::from(small_height));
+    if corners.iter().any(|&(x, y)| {
+        x < EDGE_MARGIN || y < EDGE_MARGIN || x > right - EDGE_MARGIN || y > bottom - EDGE_MARGIN
+    }) {
+        return None;
+    }
+
+    Some(corners.map(|(x, y)| (x / scale, y / scale)))
+}
+
+/// The pixels of the largest connected region lighter than `threshold`.
+fn largest_region(img: &GrayImage, threshold: u8) -> Vec<(u32, u32)> {
+    let (width, height) = img.dimensions();
+    let mut seen = vec![false; (width * height) as usize];
+    let mut largest = Vec::default();
+    let mut stack = Vec::default();
+    for start in img
+        .enumerate_pixels()
+        .filter(|(_, _, p)| p[0] >= threshold)
+        .map(|(x, y, _)| (x, y))
+    {
+        if seen[(start.1 * width + start.0) as usize] {
+            continue;
+        }
+        seen[(start.1 * width + start.0) as usize] = true;
+        let mut region = Vec::default();
+        stack.push(start);
+        while let Some((x, y)) = stack.pop() {
+            region.push((x, y));
+            let neighbors = [
+                (x.wrapping_sub(1), y),
+                (x + 1, y),
+                (x, y.wrapping_sub(1)),
+                (x, y + 1),
+            ];
+            for (nx, ny) in neighbors.iter().copied() {
+                if nx < width && ny < height {
+                    let index = (ny * width + nx) as usize;
+                    if !seen[index] && img.get_pixel(nx, ny)[0] >= threshold {
+                        seen[index] = true;
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+        }
+        if region.len() > largest.len() {
+            largest = region;
+        }
+    }
+    largest
+}
+
+fn distance((x1, y1): Point, (x2, y2): Point) -> f64 {
+    (x2 - x1).hypot(y2 - y1)
+}
+
+/// The area of a quadrilateral with its corners in clockwise order, or a negative or zero area
+/// if they aren't (in screen coordinates, where y grows downward).
+fn quad_area(corners: &[Point; 4]) -> f64 {
+    let mut twice_area = 0.0;
+    for i in 0..4 {
+        let (x1, y1) = corners[i];
+        let (x2, y2) = corners[(i + 1) % 4];
+        twice_area += x1 * y2 - x2 * y1;
+    }
+    twice_area / 2.0
+}
+
+/// A projective transform of the plane.
+struct Homography([f64; 8]);
+
+impl Homography {
+    /// The transform that takes each of the points `from` to the matching point of `to`, or
+    /// None if there isn't one (e.g., three of the points are on a line).
+    fn mapping(from: &[Point; 4], to: &[Point; 4]) -> Option<Homography> {
+        // Each pair of points gives two linear equations in the eight unknowns.
+        let mut rows = [[0.0; 9]; 8];
+        for (i, (&(u, v), &(x, y))) in from.iter().zip(to.iter()).enumerate() {
+            rows[2 * i] = [u, v, 1.0, 0.0, 0.0, 0.0, -u * x, -v * x, x];
+            rows[2 * i + 1] = [0.0, 0.0, 0.0, u, v, 1.0, -u * y, -v * y, y];
+        }
+
+        // Gaussian elimination with partial pivoting.
+        for col in 0..8 {
+            let pivot = (col..8).max_by(|&a, &b| {
+                rows[a][col]
+                    .abs()
+                    .partial_cmp(&rows[b][col].abs())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })?;
+            if rows[pivot][col].abs() < 1e-9 {
+                return None;
+            }
+            rows.swap(col, pivot);
+            let pivot_row = rows[col];
+            for (i, row) in rows.iter_mut().enumerate() {
+                if i != col {
+                    let factor = row[col] / pivot_row[col];
+                    for (value, pivot_value) in row.iter_mut().zip(pivot_row.iter()).skip(col) {
+                        *value -= factor * pivot_value;
+                    }
+                }
+            }
+        }
+
+        let mut h = [0.0; 8];
+        for (i, value) in h.iter_mut().enumerate() {
+            *value = rows[i][8] / rows[i][i];
+        }
+        Some(Homography(h))
+    }
+
+    fn apply(&self, (u, v): Point) -> Point {
+        let h = &self.0;
+        let w = h[6] * u + h[7] * v + 1.0;
+        (
+            (h[0] * u + h[1] * v + h[2]) / w,
+            (h[3] * u + h[4] * v + h[5]) / w,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use printpdf::image::Luma;
+
+    const QUAD: [Point; 4] = [(80.0, 40.0), (330.0, 60.0), (310.0, 260.0), (60.0, 240.0)];
+
+    fn assert_near((x1, y1): Point, (x2, y2): Point, tolerance: f64) {
+        assert!(
+            (x1 - x2).abs() <= tolerance && (y1 - y2).abs() <= tolerance,
+            "{:?} is not near {:?}",
+            (x1, y1),
+            (x2, y2)
+        );
+    }
+
+    /// Whether `(x, y)` is inside the clockwise quadrilateral `quad`.
+    fn inside(quad: &[Point; 4], (x, y): Point) -> bool {
+        (0..4).all(|i| {
+            let ((x1, y1), (x2, y2)) = (quad[i], quad[(i + 1) % 4]);
+            (x2 - x1) * (y - y1) - (y2 - y1) * (x - x1) >= 0.0
+        })
+    }
+
+    /// A photo of a light page, the quadrilateral `QUAD`, on a dark table.
+    fn photo() -> GrayImage {
+        GrayImage::from_fn(400, 300, |x, y| {
+            let center = (f64::from(x) + 0.5, f64::from(y) + 0.5);
+            Luma([if inside(&QUAD, center) { 230 } else { 40 }])
+        })
+    }
+
+    #[test]
+    fn homography_maps_the_corners_and_back() {
+        let rect = [(0.0, 0.0), (200.0, 0.0), (200.0, 100.0), (0.0, 100.0)];
+        let forward = Homography::mapping(&rect, &QUAD).unwrap();
+        for (from, to) in rect.iter().zip(QUAD.iter()) {
+            assert_near(forward.apply(*from), *to, 1e-9);
+        }
+
+        let back = Homography::mapping(&QUAD, &rect).unwrap();
+        for point in &[(50.0, 25.0), (120.0, 80.0), (199.0, 1.0)] {
+            assert_near(back.apply(forward.apply(*point)), *point, 1e-9);
+        }
+    }
+
+    #[test]
+    fn homography_needs_four_corners() {
+        let rect = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        let line = [(0.0, 0.0), (1.0, 1.0), (2.0, 2.0), (3.0, 3.0)];
+        assert!(Homography::mapping(&rect, &line).is_none());
+    }
+
+    #[test]
+    fn quad_area_is_positive_clockwise() {
+        let square = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+        assert_eq!(quad_area(&square), 100.0);
+        let [a, b, c, d] = square;
+        assert_eq!(quad_area(&[a, d, c, b]), -100.0);
+        // Crossed over into a bow tie, the two halves cancel out.
+        assert_eq!(quad_area(&[a, b, d, c]), 0.0);
+    }
+
+    #[test]
+    fn finds_the_page() {
+        let corners = find_page(&photo()).unwrap();
+        for (found, expected) in corners.iter().zip(QUAD.iter()) {
+            assert_near(*found, *expected, 3.0);
+        }
+    }
+
+    #[test]
+    fn flattens_the_page() {
+        let flat = correct_perspective(&DynamicImage::ImageLuma8(photo())).to_luma8();
+        let (width, height) = flat.dimensions();
+        // The longer of each pair of opposite sides, 251 and 201, give or take the corners.
+        assert!((247..=255).contains(&width), "{}", width);
+        assert!((197..=205).contains(&height), "{}", height);
+        // All paper, apart from the edges.
+        let dark = flat
+            .enumerate_pixels()
+            .filter(|(x, y, _)| (4..width - 4).contains(x) && (4..height - 4).contains(y))
+            .filter(|(_, _, p)| p[0] < 200)
+            .count();
+        assert_eq!(dark, 0);
+    }
+
+    #[test]
+    fn keeps_images_without_a_page() {
+        let blank = GrayImage::from_pixel(200, 100, Luma([255]));
+        // A gradient, and noise, like a photo of something other than a page.
+        let gradient = GrayImage::from_fn(200, 100, |x, y| Luma([(x + y) as u8]));
+        let mut seed = 1u32;
+        let noise = GrayImage::from_fn(200, 100, |_, _| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            Luma([(seed >> 24) as u8])
+        });
+        for img in [blank, gradient, noise] {
+            let corrected = correct_perspective(&DynamicImage::ImageLuma8(img.clone()));
+            assert_eq!(corrected.as_luma8(), Some(&img));
+        }
+    }
+}
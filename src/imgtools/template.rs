@@ -0,0 +1,346 @@
+use super::PageSize;
+use crate::{Error, Result};
+use printpdf::image::{GrayImage, Luma};
+use std::str::FromStr;
+
+/// The gray of the lines and dots. Light enough to write over, dark enough to see on e-ink.
+const INK: u8 = 150;
+
+const PAPER: u8 = 255;
+
+/// The thickness of the lines, in pixels.
+const LINE_WIDTH: u32 = 2;
+
+/// The radius of the dots, in pixels.
+const DOT_RADIUS: f64 = 2.5;
+
+/// The space, in millimeters, that lines, staves and the Cornell frame keep from the edges.
+const MARGIN: f64 = 10.0;
+
+/// The parts of a Cornell page, as fractions of its height (the header and the summary) and its
+/// width (the column for cues).
+const CORNELL_HEADER: f64 = 0.12;
+const CORNELL_SUMMARY: f64 = 0.2;
+const CORNELL_CUES: f64 = 0.3;
+
+/// The white space between two staves, in multiples of the space between their lines.
+const STAFF_SPACING: f64 = 4.0;
+
+const MM_PER_INCH: f64 = 25.4;
+
+/// The kinds of templates that can be generated.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TemplateStyle {
+    /// Squares, like graph paper.
+    Grid,
+    /// A dot at each corner of a grid.
+    Dots,
+    /// Ruled lines, like notebook paper.
+    Lines,
+    /// Cornell notes: a header, a column for cues beside ruled notes, and a summary at the bottom.
+    Cornell,
+    /// Staves of five lines for writing music.
+    Music,
+}
+
+impl TemplateStyle {
+    /// The category of the device's template picker that the style belongs in.
+    pub fn category(self) -> &'static str {
+        match self {
+            TemplateStyle::Grid | TemplateStyle::Dots => "Grids",
+            TemplateStyle::Lines | TemplateStyle::Cornell => "Lines",
+            TemplateStyle::Music => "Creative",
+        }
+    }
+
+    /// The usual spacing, in millimeters, between the lines or dots (or, for music, between the
+    /// lines of a staff).
+    pub fn default_spacing(self) -> f64 {
+        match self {
+            TemplateStyle::Grid | TemplateStyle::Dots => 5.0,
+            TemplateStyle::Lines | TemplateStyle::Cornell => 7.0,
+            TemplateStyle::Music => 2.5,
+        }
+    }
+}
+
+impl FromStr for TemplateStyle {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "grid" => Ok(TemplateStyle::Grid),
+            "dots" => Ok(TemplateStyle::Dots),
+            "lines" => Ok(TemplateStyle::Lines),
+            "cornell" => Ok(TemplateStyle::Cornell),
+            "music" => Ok(TemplateStyle::Music),
+            _ => Err(Error::BadArgsError(format!(
+                "unknown template style '{}', expected grid, dots, lines, cornell or music",
+                s
+            ))),
+        }
+    }
+}
+
+/// Draws a template of `style` at the resolution of `page`, with `spacing` millimeters between
+/// its lines. Landscape templates are as wide as the page is tall.
+pub fn render_template(
+    style: TemplateStyle,
+    page: &PageSize,
+    landscape: bool,
+    spacing: f64,
+) -> GrayImage {
+    let (width, height) = if landscape {
+        (page.height, page.width)
+    } else {
+        (page.width, page.height)
+    };
+    let mut canvas = Canvas {
+        img: GrayImage::from_pixel(width, height, Luma([PAPER])),
+        width: f64::from(width),
+        height: f64::from(height),
+        pixels_per_mm: page.dpi / MM_PER_INCH,
+    };
+    let step = spacing * canvas.pixels_per_mm;
+    match style {
+        TemplateStyle::Grid => canvas.grid(step),
+        TemplateStyle::Dots => canvas.dots(step),
+        TemplateStyle::Lines => canvas.lines(step),
+        TemplateStyle::Cornell => canvas.cornell(step),
+        TemplateStyle::Music => canvas.music(step),
+    }
+    canvas.img
+}
+
+struct Canvas {
+    img: GrayImage,
+    // The size of `img` and the resolution, as floats for the arithmetic.
+    width: f64,
+    height: f64,
+    pixels_per_mm: f64,
+}
+
+impl Canvas {
+    fn grid(&mut self, step: f64) {
+        for x in centered(self.width, step) {
+            self.vertical_line(x, 0.0, self.height);
+        }
+        for y in centered(self.height, step) {
+            self.horizontal_line(y, 0.0, self.width);
+        }
+    }
+
+    fn dots(&mut self, step: f64) {
+        let rows = centered(self.height, step);
+        for x in centered(self.width, step) {
+            for &y in &rows {
+                self.dot(x, y);
+            }
+        }
+    }
+
+    fn lines(&mut self, step: f64) {
+        let margin = MARGIN * self.pixels_per_mm;
+        self.ruled(margin + step, self.height - margin, step);
+    }
+
+    fn cornell(&mut self, step: f64) {
+        let margin = MARGIN * self.pixels_per_mm;
+        let header = (self.height * CORNELL_HEADER).max(margin + step);
+        let summary = self.height * (1.0 - CORNELL_SUMMARY);
+        let cues = self.width * CORNELL_CUES;
+
+        self.horizontal_line(header, 0.0, self.width);
+        self.horizontal_line(summary, 0.0, self.width);
+        self.vertical_line(cues, header, summary);
+        // The ruling stops short of the summary's line, so that no line is doubled.
+        self.ruled(header + step, summary - step / 2.0, step);
+    }
+
+    fn music(&mut self, step: f64) {
+        let margin = MARGIN * self.pixels_per_mm;
+        let staff = 4.0 * step;
+        let pitch = staff + STAFF_SPACING * step;
+        let usable = self.height - 2.0 * margin;
+        let count = ((usable + STAFF_SPACING * step) / pitch).floor().max(1.0);
+        let top = (self.height - (count * pitch - STAFF_SPACING * step)) / 2.0;
+        for i in 0..count as u32 {
+            let staff_top = top + f64::from(i) * pitch;
+            for line in 0..5 {
+                let y = staff_top + f64::from(line) * step;
+                self.horizontal_line(y, margin, self.width - margin);
+            }
+        }
+    }
+
+    /// Draws lines across the page every `step` pixels, from `top` down to `bottom`.
+    fn ruled(&mut self, top: f64, bottom: f64, step: f64) {
+        let mut y = top;
+        while y <= bottom {
+            self.horizontal_line(y, 0.0, self.width);
+            y += step;
+        }
+    }
+
+    fn horizontal_line(&mut self, y: f64, left: f64, right: f64) {
+        let top = y.round() as i64 - i64::from(LINE_WIDTH / 2);
+        self.fill(
+            left.round() as i64,
+            top,
+            right.round() as i64,
+            top + i64::from(LINE_WIDTH),
+        );
+    }
+
+    fn vertical_line(&mut self, x: f64, top: f64, bottom: f64) {
+        let left = x.round() as i64 - i64::from(LINE_WIDTH / 2);
+        self.fill(
+            left,
+            top.round() as i64,
+            left + i64::from(LINE_WIDTH),
+            bottom.round() as i64,
+        );
+    }
+
+    fn dot(&mut self, x: f64, y: f64) {
+        let reach = DOT_RADIUS.ceil() as i64;
+        let (cx, cy) = (x.round() as i64, y.round() as i64);
+        for py in cy - reach..=cy + reach {
+            for px in cx - reach..=cx + reach {
+                let (dx, dy) = ((px - cx) as f64, (py - cy) as f64);
+                if dx.hypot(dy) <= DOT_RADIUS {
+                    self.fill(px, py, px + 1, py + 1);
+                }
+            }
+        }
+    }
+
+    /// Paints the rectangle from (`left`, `top`) up to, but not including, (`right`, `bottom`),
+    /// clipped to the image.
+    fn fill(&mut self, left: i64, top: i64, right: i64, bottom: i64) {
+        let (width, height) = self.img.dimensions();
+        let clip = |v: i64, max: u32| v.clamp(0, i64::from(max)) as u32;
+        for y in clip(top, height)..clip(bottom, height) {
+            for x in clip(left, width)..clip(right, width) {
+                self.img.put_pixel(x, y, Luma([INK]));
+            }
+        }
+    }
+}
+
+/// The positions, `step` apart, of lines across `size`, centered so that the space left over is
+/// split evenly between both edges.
+fn centered(size: f64, step: f64) -> Vec<f64> {
+    let count = (size / step).floor();
+    let start = (size - count * step) / 2.0;
+    (0..=count as u32)
+        .map(|i| start + f64::from(i) * step)
+        .filter(|&p| p < size)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A page at one pixel per millimeter, so that spacings are in pixels.
+    const PAGE: PageSize = PageSize {
+        width: 100,
+        height: 60,
+        dpi: MM_PER_INCH,
+    };
+
+    /// The rows with ink in column `x`.
+    fn ink_rows(img: &GrayImage, x: u32) -> Vec<u32> {
+        (0..img.height())
+            .filter(|&y| img.get_pixel(x, y)[0] == INK)
+            .collect()
+    }
+
+    /// The columns with ink in row `y`.
+    fn ink_columns(img: &GrayImage, y: u32) -> Vec<u32> {
+        (0..img.width())
+            .filter(|&x| img.get_pixel(x, y)[0] == INK)
+            .collect()
+    }
+
+    #[test]
+    fn centers_the_positions() {
+        assert_eq!(centered(40.0, 10.0), [0.0, 10.0, 20.0, 30.0]);
+        assert_eq!(centered(35.0, 10.0), [2.5, 12.5, 22.5, 32.5]);
+    }
+
+    #[test]
+    fn grid_lines() {
+        let img = render_template(TemplateStyle::Grid, &PAGE, false, 10.0);
+        assert_eq!(img.dimensions(), (100, 60));
+        // Lines are two pixels wide, the pixel before the position and the one at it.
+        assert_eq!(
+            ink_columns(&img, 5),
+            [0, 9, 10, 19, 20, 29, 30, 39, 40, 49, 50, 59, 60, 69, 70, 79, 80, 89, 90]
+        );
+        assert_eq!(
+            ink_rows(&img, 5),
+            [0, 9, 10, 19, 20, 29, 30, 39, 40, 49, 50]
+        );
+    }
+
+    #[test]
+    fn dots() {
+        let img = render_template(TemplateStyle::Dots, &PAGE, false, 10.0);
+        // Through the middle of a row of dots, each is five pixels across, and the first is cut
+        // off by the edge.
+        let columns = ink_columns(&img, 10);
+        assert_eq!(columns[..8], [0, 1, 2, 8, 9, 10, 11, 12]);
+        assert_eq!(columns.len(), 3 + 9 * 5);
+        // Three pixels above the middle is past the radius.
+        assert!(ink_columns(&img, 13).is_empty());
+        assert!(ink_columns(&img, 15).is_empty());
+    }
+
+    #[test]
+    fn ruled_lines_stay_inside_the_margins() {
+        let img = render_template(TemplateStyle::Lines, &PAGE, false, 10.0);
+        // The first line is a spacing below the top margin, the last on the bottom one.
+        assert_eq!(ink_rows(&img, 0), [19, 20, 29, 30, 39, 40, 49, 50]);
+        assert_eq!(ink_rows(&img, 99), ink_rows(&img, 0));
+    }
+
+    #[test]
+    fn cornell_sections() {
+        let page = PageSize {
+            height: 100,
+            ..PAGE
+        };
+        let img = render_template(TemplateStyle::Cornell, &page, false, 7.0);
+        // The header line, the ruling, and the summary line at 80%.
+        assert_eq!(
+            ink_rows(&img, 50),
+            [16, 17, 23, 24, 30, 31, 37, 38, 44, 45, 51, 52, 58, 59, 65, 66, 72, 73, 79, 80]
+        );
+        // The cue column's line, at 30% of the width, between the header and the summary.
+        assert_eq!(ink_columns(&img, 20), [29, 30]);
+        assert_eq!(ink_rows(&img, 29), (16..=80).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn music_staves() {
+        let img = render_template(TemplateStyle::Music, &PAGE, false, 3.0);
+        // Two staves of five lines, centered, four spacings apart.
+        assert_eq!(
+            ink_rows(&img, 50),
+            [11, 12, 14, 15, 17, 18, 20, 21, 23, 24, 35, 36, 38, 39, 41, 42, 44, 45, 47, 48]
+        );
+        assert_eq!(ink_columns(&img, 12), (10..90).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn landscape_turns_the_page() {
+        let img = render_template(TemplateStyle::Lines, &PAGE, true, 10.0);
+        assert_eq!(img.dimensions(), (60, 100));
+        assert_eq!(
+            ink_rows(&img, 0),
+            [19, 20, 29, 30, 39, 40, 49, 50, 59, 60, 69, 70, 79, 80, 89, 90]
+        );
+    }
+}
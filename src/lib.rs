@@ -1,9 +1,17 @@
 pub mod subcommands;
 
+mod epub;
 mod imgtools;
+mod render;
 
 mod remarkable;
-pub use remarkable::{Connection, File};
+pub use remarkable::lines;
+pub use remarkable::{
+    Ancestors, Backend, CPage, CPages, Connection, Content, DocumentMetadata, Entry, File,
+    LocalBackend, Metadata, Node, PageEditor, PageTag, Pagedata, SftpBackend, SshFsBackend, Tag,
+    Template, TemplateIndex, Timestamped, Transform, Tree, Walk, COLLECTION_TYPE, DEFAULT_TEMPLATE,
+    DOCUMENT_TYPE, ROOT_ID, TRASH_ID,
+};
 
 mod result;
 pub use result::{Error, Result};
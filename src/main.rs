@@ -1,7 +1,9 @@
 use argh::FromArgs;
 use log::error;
-use mrktools::subcommands::{copier, ipdf, ls, restart};
-use mrktools::{Connection, Result};
+use mrktools::subcommands::{
+    copier, empty_trash, export, import, ipdf, ls, mkdir, mv, pull, restart, restore, rm, template,
+};
+use mrktools::{Connection, Error, Result};
 
 const MOUNT_POINT_DEFAULT: &str = "/tmp/remarkable_mount";
 const REMARKABLE_HOST_DEFAULT: &str = "192.168.86.31";
@@ -23,6 +25,20 @@ struct Commands {
     #[argh(option, short = 'm', default = "MOUNT_POINT_DEFAULT.to_string()")]
     mount_point: String,
 
+    /// talk SFTP directly over ssh instead of mounting with sshfs.
+    /// No mount point is used.
+    #[argh(switch)]
+    sftp: bool,
+
+    /// operate on a local copy of the xochitl data directory instead of the device.
+    /// Commands that need the device (e.g., restart) will fail.
+    #[argh(option, short = 'l')]
+    local: Option<String>,
+
+    /// private key file to use for --sftp. Defaults to the ssh agent and ~/.ssh keys.
+    #[argh(option, short = 'i')]
+    identity: Option<String>,
+
     #[argh(subcommand)]
     nested: CommandsEnum,
 }
@@ -31,47 +47,59 @@ struct Commands {
 #[argh(subcommand)]
 enum CommandsEnum {
     Copier(copier::CopierArgs),
+    EmptyTrash(empty_trash::EmptyTrashArgs),
+    Export(export::ExportArgs),
+    Import(import::ImportArgs),
     IPdf(ipdf::IPdfArgs),
     Ls(ls::LsArgs),
+    Mkdir(mkdir::MkdirArgs),
+    Mv(mv::MvArgs),
+    Pull(pull::PullArgs),
     Restart(restart::RestartArgs),
+    Restore(restore::RestoreArgs),
+    Rm(rm::RmArgs),
+    Template(template::TemplateArgs),
 }
 
-fn with_connection<F>(
-    user: impl AsRef<str>,
-    host: impl AsRef<str>,
-    mount_point: impl AsRef<str>,
-    f: F,
-) -> Result<()>
+fn with_connection<F>(args: &Commands, f: F) -> Result<()>
 where
     F: FnOnce(&Connection) -> Result<()>,
 {
-    let mut conn = Connection::connect(user, host, mount_point)?;
-    f(&mut conn)
+    let conn = if let Some(local) = &args.local {
+        if args.sftp {
+            return Err(Error::BadArgsError(
+                "--local and --sftp may not be used together.".to_string(),
+            ));
+        }
+        Connection::local(local)?
+    } else if args.sftp {
+        Connection::connect_sftp(&args.user, &args.host, args.identity.as_ref())?
+    } else {
+        Connection::connect(&args.user, &args.host, &args.mount_point)?
+    };
+    f(&conn)
 }
 
 fn main() {
     pretty_env_logger::init();
 
     let args = argh::from_env::<Commands>();
-    if let Err(err) = match args.nested {
-        CommandsEnum::Copier(a) => {
-            with_connection(&args.user, &args.host, &args.mount_point, |conn| {
-                copier::copy(conn, a)
-            })
-        }
-        CommandsEnum::IPdf(a) => {
-            with_connection(&args.user, &args.host, &args.mount_point, |conn| {
-                ipdf::ipdf(conn, a)
-            })
-        }
-        CommandsEnum::Ls(a) => with_connection(&args.user, &args.host, &args.mount_point, |conn| {
-            ls::ls(conn, a)
-        }),
-        CommandsEnum::Restart(a) => {
-            with_connection(&args.user, &args.host, &args.mount_point, |conn| {
-                restart::restart(conn, a)
-            })
+    if let Err(err) = match &args.nested {
+        CommandsEnum::Copier(a) => with_connection(&args, |conn| copier::copy(conn, a)),
+        CommandsEnum::EmptyTrash(a) => {
+            with_connection(&args, |conn| empty_trash::empty_trash(conn, a))
         }
+        CommandsEnum::Export(a) => with_connection(&args, |conn| export::export(conn, a)),
+        CommandsEnum::Import(a) => with_connection(&args, |conn| import::import(conn, a)),
+        CommandsEnum::IPdf(a) => with_connection(&args, |conn| ipdf::ipdf(conn, a)),
+        CommandsEnum::Ls(a) => with_connection(&args, |conn| ls::ls(conn, a)),
+        CommandsEnum::Mkdir(a) => with_connection(&args, |conn| mkdir::mkdir(conn, a)),
+        CommandsEnum::Mv(a) => with_connection(&args, |conn| mv::mv(conn, a)),
+        CommandsEnum::Pull(a) => with_connection(&args, |conn| pull::pull(conn, a)),
+        CommandsEnum::Restart(a) => with_connection(&args, |conn| restart::restart(conn, a)),
+        CommandsEnum::Restore(a) => with_connection(&args, |conn| restore::restore(conn, a)),
+        CommandsEnum::Rm(a) => with_connection(&args, |conn| rm::rm(conn, a)),
+        CommandsEnum::Template(a) => with_connection(&args, |conn| template::template(conn, a)),
     } {
         error!("{}", err);
         eprintln!("Error: {}", err);
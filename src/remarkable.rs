@@ -1,22 +1,69 @@
+mod backend;
+pub use backend::{Backend, Entry};
+
 mod connection;
 pub use connection::Connection;
 
 mod content;
-pub use content::Content;
+pub use content::{CPage, CPages, Content, DocumentMetadata, PageTag, Tag, Timestamped, Transform};
 
 mod file;
 pub use file::File;
 
+pub mod lines;
+
+mod local;
+pub use local::LocalBackend;
+
 mod metadata;
-pub use metadata::Metadata;
+pub use metadata::{Metadata, COLLECTION_TYPE, DOCUMENT_TYPE};
+
+mod pagedata;
+pub use pagedata::{PageEditor, Pagedata, DEFAULT_TEMPLATE};
+
+mod sftp;
+pub use sftp::SftpBackend;
 
 mod sshfs;
+pub use sshfs::SshFsBackend;
+
+mod templates;
+pub use templates::{Template, TemplateIndex, TEMPLATES_DIR};
+
+mod tree;
+pub use tree::{Ancestors, Node, Tree, Walk, ROOT_ID, TRASH_ID};
 
 use crate::Result;
+use std::collections::HashMap;
 use std::fs::create_dir;
 use std::path::{Path, PathBuf};
+use uuid::Uuid;
 
+pub const CONTENT_EXTENSION: &str = "content";
 pub const METADATA_EXTENSION: &str = "metadata";
+pub const PAGEDATA_EXTENSION: &str = "pagedata";
+
+/// Creates a new, random id for a document, folder, or page.
+pub fn new_uuid() -> String {
+    let uu = Uuid::new_v4();
+    uu.to_hyphenated()
+        .encode_lower(&mut Uuid::encode_buffer())
+        .to_string()
+}
+
+/// Lists everything in the root of the data directory, grouped by the id it belongs to.
+///
+/// Every file and directory for an item is named after its id, with or without an extension.
+pub fn list_artifacts(backend: &dyn Backend) -> Result<HashMap<String, Vec<Entry>>> {
+    let mut artifacts: HashMap<String, Vec<Entry>> = HashMap::default();
+    for entry in backend.list(Path::new(""))? {
+        let name = entry.path.to_string_lossy().into_owned();
+        // unwrap: split always returns at least one item.
+        let id = name.split('.').next().unwrap().to_string();
+        artifacts.entry(id).or_default().push(entry);
+    }
+    Ok(artifacts)
+}
 
 /// Creates all of the directories (but none of the files) required for a new Remarkable item.
 /// The file stem for these directories will be based on the supplied uuid. All directories will
@@ -41,9 +88,9 @@ pub fn create_bare_fs(uuid: impl AsRef<str>, root: impl AsRef<Path>) -> Result<P
     let base = root.as_ref().join(uuid.as_ref());
 
     create_dir(&base)?;
-    create_dir(&base.with_extension("highlights"))?;
-    create_dir(&base.with_extension("textconvertion"))?;
-    create_dir(&base.with_extension("thumbnails"))?;
+    create_dir(base.with_extension("highlights"))?;
+    create_dir(base.with_extension("textconvertion"))?;
+    create_dir(base.with_extension("thumbnails"))?;
 
     Ok(base)
 }
@@ -0,0 +1,85 @@
+use crate::Result;
+use std::path::{Path, PathBuf};
+
+/// A single item returned from `Backend::list`.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// Path of the item, relative to the root of the backend.
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
+/// Storage for the xochitl data directory.
+///
+/// All paths are relative to the root of the data directory, so the same code can operate on
+/// a mounted device, an extracted backup, or a fixture directory.
+pub trait Backend {
+    /// Lists the immediate children of the directory at `path`.
+    fn list(&self, path: &Path) -> Result<Vec<Entry>>;
+
+    /// Reads the entire contents of the file at `path`.
+    fn read(&self, path: &Path) -> Result<Vec<u8>>;
+
+    /// Writes `data` to the file at `path`, creating any missing parent directories.
+    fn write(&self, path: &Path, data: &[u8]) -> Result<()>;
+
+    /// Removes the file or directory (recursively) at `path`.
+    fn remove(&self, path: &Path) -> Result<()>;
+
+    /// Renames the file or directory at `from` to `to`.
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+    /// Runs `program` with `args` on the device, returning its standard output. The arguments
+    /// are passed as they are, without being interpreted by a shell, and a non-zero exit status
+    /// is an error.
+    fn run_command(&self, program: &str, args: &[&str]) -> Result<Vec<u8>>;
+
+    /// Reads the entire contents of the file at the absolute `path` on the device, outside of
+    /// the data directory (e.g., the templates).
+    fn read_system_file(&self, path: &Path) -> Result<Vec<u8>>;
+
+    /// Writes `data` to the file at the absolute `path` on the device, outside of the data
+    /// directory. Its directory must already exist.
+    fn write_system_file(&self, path: &Path, data: &[u8]) -> Result<()>;
+}
+
+/// Quotes `arg` so that the shell on the device sees it as a single word, whatever it contains.
+pub(super) fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// The command line that runs `program` with `args` in the shell on the device.
+pub(super) fn command_line(program: &str, args: &[&str]) -> String {
+    std::iter::once(program)
+        .chain(args.iter().copied())
+        .map(shell_quote)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn shell_quote_survives_the_shell() {
+        for arg in &[
+            "plain",
+            "with space",
+            "it's",
+            "'",
+            "$(echo injected)",
+            "; echo injected",
+            "a\\b\"c",
+            "",
+        ] {
+            let output = Command::new("sh")
+                .arg("-c")
+                .arg(format!("printf %s {}", shell_quote(arg)))
+                .output()
+                .unwrap();
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), *arg);
+        }
+    }
+}
@@ -1,71 +1,119 @@
-use super::sshfs::SshFsMount;
+use super::backend::Backend;
+use super::local::LocalBackend;
+use super::sftp::SftpBackend;
+use super::sshfs::SshFsBackend;
+use super::templates::{TemplateIndex, TEMPLATES_BACKUP, TEMPLATES_DIR, TEMPLATES_INDEX};
+use super::tree::Tree;
 use super::File;
 use crate::{Error, Result};
-use log::{debug, trace};
+use log::{debug, info, trace};
 use std::cell::{Ref, RefCell};
-use std::fs::read_dir;
-use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::path::Path;
+use std::rc::Rc;
 
 const DATA_DIR: &str = ".local/share/remarkable/xochitl";
 
 pub struct Connection {
-    user: String,
-    host: String,
-
-    // The connection to the Remarkable filesystem via sshfs.
-    // This is never read. When dropped, it umounts the mount point.
-    mount: SshFsMount,
+    // The storage holding the xochitl files. All paths are relative to the data directory.
+    backend: Box<dyn Backend>,
 
     // List of files with metadata (or errors, if something couldn't be loaded)
     lazy_files: RefCell<Option<Vec<File>>>,
 
-    // The full path to the mounted file system where the xochitl files live.
-    path: PathBuf,
+    // The folder hierarchy built from lazy_files. Shared, so that it can be held across refresh().
+    lazy_tree: RefCell<Option<Rc<Tree>>>,
 }
 
 impl Connection {
+    /// Connects to the device by mounting it with `sshfs` at `mount_point`.
     pub fn connect(
         user: impl AsRef<str>,
         host: impl AsRef<str>,
         mount_point: impl AsRef<str>,
     ) -> Result<Connection> {
         debug!("connecting");
-        let mut mount = SshFsMount::new(&user, &host, mount_point.as_ref());
-        mount.mount()?;
+        let backend = SshFsBackend::connect(user, host, mount_point.as_ref(), DATA_DIR)?;
+        Ok(Connection::with_backend(backend))
+    }
 
-        let path = PathBuf::from(mount_point.as_ref()).join(DATA_DIR);
+    /// Connects to the device with SFTP over an SSH session. Nothing is mounted.
+    pub fn connect_sftp(
+        user: impl AsRef<str>,
+        host: impl AsRef<str>,
+        identity: Option<impl AsRef<Path>>,
+    ) -> Result<Connection> {
+        debug!("connecting with sftp");
+        let backend = SftpBackend::connect(user, host, identity, DATA_DIR)?;
+        Ok(Connection::with_backend(backend))
+    }
 
-        Ok(Connection {
-            user: user.as_ref().to_string(),
-            host: host.as_ref().to_string(),
-            mount,
-            lazy_files: Default::default(),
-            path,
-        })
+    /// Opens an existing local directory laid out like the xochitl data directory.
+    pub fn local(dir: impl AsRef<Path>) -> Result<Connection> {
+        debug!("opening local directory {:?}", dir.as_ref());
+        if !dir.as_ref().is_dir() {
+            return Err(Error::DirNotFound(dir.as_ref().to_path_buf()));
+        }
+        Ok(Connection::with_backend(LocalBackend::new(dir)))
     }
 
-    pub fn mount_point(&self) -> &Path {
-        self.mount.mount_point()
+    pub fn with_backend(backend: impl Backend + 'static) -> Connection {
+        Connection {
+            backend: Box::new(backend),
+            lazy_files: Default::default(),
+            lazy_tree: Default::default(),
+        }
     }
 
-    pub fn data_dir(&self) -> PathBuf {
-        self.mount.mount_point().join(DATA_DIR)
+    pub fn backend(&self) -> &dyn Backend {
+        self.backend.as_ref()
     }
 
     pub fn restart(&self) -> Result<()> {
         debug!("restart()");
-        Command::new("ssh")
-            .arg(format!("{}@{}", self.user, self.host))
-            .arg("systemctl")
-            .arg("restart")
-            .arg("xochitl")
-            .output()?;
+        self.backend
+            .run_command("systemctl", &["restart", "xochitl"])?;
         trace!("restart complete");
         Ok(())
     }
 
-    pub fn files(&self) -> Result<Ref<Vec<File>>> {
+    /// Loads the index of the templates installed on the device.
+    pub fn templates(&self) -> Result<TemplateIndex> {
+        debug!("loading template index");
+        let index_path = Path::new(TEMPLATES_DIR).join(TEMPLATES_INDEX);
+        let json = self.backend.read_system_file(&index_path)?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    /// Replaces the index of the templates installed on the device with `index`.
+    ///
+    /// The first time, the original index is copied to `TEMPLATES_BACKUP`. Later backups would
+    /// only hold our own changes, so an existing backup is left alone. The new index is written
+    /// next to the old one and then moved over it.
+    pub fn save_templates(&self, index: &TemplateIndex) -> Result<()> {
+        let index_path = Path::new(TEMPLATES_DIR).join(TEMPLATES_INDEX);
+        let backup_path = Path::new(TEMPLATES_DIR).join(TEMPLATES_BACKUP);
+        match self.backend.read_system_file(&backup_path) {
+            Ok(_) => {}
+            Err(err) if err.is_not_found() => {
+                info!("backing up the template index to {:?}", backup_path);
+                let original = self.backend.read_system_file(&index_path)?;
+                self.backend.write_system_file(&backup_path, &original)?;
+            }
+            Err(err) => return Err(err),
+        }
+
+        debug!("saving template index");
+        let tmp_path = index_path.with_extension("json.tmp");
+        let json = serde_json::to_vec_pretty(index)?;
+        self.backend.write_system_file(&tmp_path, &json)?;
+        self.backend.run_command(
+            "mv",
+            &[&tmp_path.to_string_lossy(), &index_path.to_string_lossy()],
+        )?;
+        Ok(())
+    }
+
+    pub fn files(&self) -> Result<Ref<'_, Vec<File>>> {
         if self.lazy_files.borrow().is_none() {
             debug!("Loading file cache.");
             let mut files = Vec::default();
@@ -77,23 +125,33 @@ impl Connection {
         Ok(Ref::map(self.lazy_files.borrow(), |o| o.as_ref().unwrap()))
     }
 
+    /// Forgets the loaded files, so that changes made since are seen by the next `files()`.
+    pub fn refresh(&self) {
+        self.lazy_files.replace(None);
+        self.lazy_tree.replace(None);
+    }
+
     fn load_files(&self, files: &mut Vec<File>) -> Result<()> {
         // For now, let's just load all of the file metadata in one big go.
         debug!("loading Remarkable file metadata into local cache");
-        for item in read_dir(&self.path)? {
-            let item = item?;
+        for item in self.backend.list(Path::new(""))? {
             // Load only the metadata files.
-            if !item.path().extension().map_or(false, |f| f == "metadata") {
+            if item.is_dir
+                || item
+                    .path
+                    .extension()
+                    .is_none_or(|f| f != super::METADATA_EXTENSION)
+            {
                 continue;
             }
             trace!(
                 "loading {}",
-                item.path()
+                item.path
                     .file_stem()
                     .map(|fs| fs.to_string_lossy().into_owned())
                     .unwrap_or_else(|| "<null>".to_owned())
             );
-            let file = File::load(item.path())?;
+            let file = File::load(self.backend(), item.path)?;
             trace!("file loaded: {:?}", file);
             files.push(file);
         }
@@ -101,25 +159,74 @@ impl Connection {
         Ok(())
     }
 
+    /// The folder hierarchy of the files, built the first time it's needed.
+    pub fn tree(&self) -> Result<Rc<Tree>> {
+        if self.lazy_tree.borrow().is_none() {
+            debug!("building folder tree");
+            let tree = Tree::new(&self.files()?);
+            self.lazy_tree.replace(Some(Rc::new(tree)));
+        }
+        // unwrap: at this point, lazy_tree should be populated.
+        Ok(Rc::clone(self.lazy_tree.borrow().as_ref().unwrap()))
+    }
+
+    /// Finds the id of the item at `path`, a slash-separated list of visible names such as
+    /// `Work/Papers/foo`, or an id. The empty path is the root folder, and `trash` is the trash.
+    pub fn resolve(&self, path: impl AsRef<str>) -> Result<String> {
+        debug!("resolving '{}'", path.as_ref());
+        let result = self.tree()?.resolve(path);
+        debug!("resolved: {:?}", result);
+        result
+    }
+
+    /// Builds the slash-separated path of visible names for the item with id `id`.
+    pub fn path_of(&self, id: impl AsRef<str>) -> Result<String> {
+        self.tree()?.path_of(id)
+    }
+
+    /// Finds the id of the document at the path (or with the id) `name`.
+    ///
+    /// For compatibility with older versions, a bare name that isn't at the top level is looked
+    /// for everywhere, as long as it is unique.
+    pub fn find_document(&self, name: impl AsRef<str>) -> Result<String> {
+        debug!("finding document '{}'", name.as_ref());
+        self.find_item(name.as_ref(), false)
+            .map_err(|err| match err {
+                Error::PathNotFound(name) => Error::DocumentNotFound(name),
+                err => err,
+            })
+    }
+
+    /// Finds the id of the folder at the path (or with the id) `folder`, looking for bare names
+    /// everywhere like `find_document`.
     pub fn find_folder(&self, folder: impl AsRef<str>) -> Result<String> {
-        debug!("finding '{}'", folder.as_ref());
-        let file_ref = self.files()?;
-        let found = file_ref.iter().find(|f| {
-            if let Ok(file_data) = &f.filedata {
-                if file_data.metadata.visible_name == folder.as_ref()
-                    && file_data.metadata.typ == "CollectionType"
-                {
-                    return true;
+        debug!("finding folder '{}'", folder.as_ref());
+        self.find_item(folder.as_ref(), true)
+            .map_err(|err| match err {
+                Error::PathNotFound(name) => Error::FolderNotFound(name),
+                err => err,
+            })
+    }
+
+    fn find_item(&self, name: &str, folder: bool) -> Result<String> {
+        let tree = self.tree()?;
+        let result = match tree.resolve(name) {
+            Err(Error::PathNotFound(_)) if !name.contains('/') => {
+                let mut matches = tree
+                    .find_named(name)
+                    .into_iter()
+                    .filter(|n| n.is_folder() == folder)
+                    .map(|n| n.id().to_string())
+                    .collect::<Vec<_>>();
+                match matches.len() {
+                    0 => Err(Error::PathNotFound(name.to_string())),
+                    1 => Ok(matches.remove(0)),
+                    _ => Err(Error::AmbiguousPath(name.to_string(), matches.join(", "))),
                 }
             }
-            false
-        });
-
-        let result = found
-            .and_then(|file| file.path.file_stem())
-            .and_then(|fs| fs.to_str())
-            .map(|fs| fs.to_string())
-            .ok_or_else(|| Error::FolderNotFound(folder.as_ref().to_string()));
+            Ok(id) if tree.is_folder(&id) != folder => Err(Error::PathNotFound(name.to_string())),
+            result => result,
+        };
         debug!("found: {:?}", result);
         result
     }
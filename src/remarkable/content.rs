@@ -1,77 +1,823 @@
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use super::backend::Backend;
+use crate::Result;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::path::Path;
 
+/// The `.content` file for a document or folder.
+///
+/// Older firmware lists the page ids in `pages`. Newer firmware (`formatVersion` 2 and up) keeps
+/// them in `cPages` instead, along with per-page templates and ordering. Fields that not every
+/// firmware writes are `Option`s, and fields that this type doesn't know about are kept in
+/// `extra`, so that a loaded file can be written back losslessly.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Content {
-    #[serde(rename = "coverPageNumber")]
-    cover_page_number: u32,
+    #[serde(rename = "cPages", default, skip_serializing_if = "Option::is_none")]
+    pub c_pages: Option<CPages>,
 
-    #[serde(rename = "dummyDocument")]
-    dummy_document: bool,
+    #[serde(
+        rename = "coverPageNumber",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cover_page_number: Option<i32>,
 
-    #[serde(rename = "extraMetadata")]
-    extra_metadata: HashMap<String, String>,
+    #[serde(
+        rename = "customZoomCenterX",
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "compact_opt_f64"
+    )]
+    pub custom_zoom_center_x: Option<f64>,
 
-    #[serde(rename = "fileType")]
-    file_type: String,
+    #[serde(
+        rename = "customZoomCenterY",
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "compact_opt_f64"
+    )]
+    pub custom_zoom_center_y: Option<f64>,
 
-    #[serde(rename = "fontName")]
-    font_name: String,
+    #[serde(
+        rename = "customZoomOrientation",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub custom_zoom_orientation: Option<String>,
 
-    #[serde(rename = "lineHeight")]
-    line_height: i32,
+    #[serde(
+        rename = "customZoomPageHeight",
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "compact_opt_f64"
+    )]
+    pub custom_zoom_page_height: Option<f64>,
 
-    margins: u32,
-    orientation: String,
+    #[serde(
+        rename = "customZoomPageWidth",
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "compact_opt_f64"
+    )]
+    pub custom_zoom_page_width: Option<f64>,
 
-    #[serde(rename = "pageCount")]
-    page_count: usize,
+    #[serde(
+        rename = "customZoomScale",
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "compact_opt_f64"
+    )]
+    pub custom_zoom_scale: Option<f64>,
 
-    pages: Vec<String>,
+    #[serde(
+        rename = "documentMetadata",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub document_metadata: Option<DocumentMetadata>,
 
-    #[serde(rename = "textAlignment")]
-    text_alignment: String,
+    #[serde(
+        rename = "dummyDocument",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub dummy_document: Option<bool>,
 
-    #[serde(rename = "textScale")]
-    text_scale: u32,
+    #[serde(
+        rename = "extraMetadata",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub extra_metadata: Option<Map<String, Value>>,
 
-    transform: HashMap<String, i32>,
+    #[serde(rename = "fileType", default, skip_serializing_if = "Option::is_none")]
+    pub file_type: Option<String>,
+
+    #[serde(rename = "fontName", default, skip_serializing_if = "Option::is_none")]
+    pub font_name: Option<String>,
+
+    #[serde(
+        rename = "formatVersion",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub format_version: Option<u32>,
+
+    #[serde(
+        rename = "lineHeight",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub line_height: Option<i32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub margins: Option<u32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orientation: Option<String>,
+
+    #[serde(
+        rename = "originalPageCount",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub original_page_count: Option<i32>,
+
+    #[serde(rename = "pageCount", default, skip_serializing_if = "Option::is_none")]
+    pub page_count: Option<usize>,
+
+    #[serde(rename = "pageTags", default, skip_serializing_if = "Option::is_none")]
+    pub page_tags: Option<Vec<PageTag>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pages: Option<Vec<String>>,
+
+    #[serde(
+        rename = "redirectionPageMap",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub redirection_page_map: Option<Vec<i32>>,
+
+    #[serde(
+        rename = "sizeInBytes",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub size_in_bytes: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<Tag>>,
+
+    #[serde(
+        rename = "textAlignment",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub text_alignment: Option<String>,
+
+    #[serde(
+        rename = "textScale",
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "compact_opt_f64"
+    )]
+    pub text_scale: Option<f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<Transform>,
+
+    #[serde(rename = "zoomMode", default, skip_serializing_if = "Option::is_none")]
+    pub zoom_mode: Option<String>,
+
+    /// Any fields not modeled above.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The page list used by `formatVersion` 2 and up.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CPages {
+    #[serde(
+        rename = "lastOpened",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub last_opened: Option<Timestamped<String>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original: Option<Timestamped<i64>>,
+
+    #[serde(default)]
+    pub pages: Vec<CPage>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuids: Option<Vec<Value>>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// A single page in `CPages`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CPage {
+    pub id: String,
+
+    /// Position of the page. Pages are ordered by comparing these strings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idx: Option<Timestamped<String>>,
+
+    /// Index of the page in the original PDF/EPUB, or -1 for an inserted page.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redir: Option<Timestamped<i64>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<Timestamped<String>>,
+
+    /// Present (with a non-zero value) if the page has been deleted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted: Option<Timestamped<i64>>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+// The author part of the timestamps of values that we create or change. xochitl numbers the
+// device itself 1.
+const LOCAL_AUTHOR: u32 = 1;
+
+/// A value tagged with the CRDT timestamp of its last change, `"author:counter"`, e.g. `"1:2"`.
+/// When two copies of a file are merged, the value with the higher counter wins.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Timestamped<T> {
+    pub timestamp: String,
+    pub value: T,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DocumentMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authors: Option<Vec<String>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Tag {
+    pub name: String,
+    pub timestamp: i64,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PageTag {
+    pub name: String,
+    #[serde(rename = "pageId")]
+    pub page_id: String,
+    pub timestamp: i64,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// A 3x3 transform matrix.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Transform {
+    #[serde(serialize_with = "compact_f64")]
+    pub m11: f64,
+    #[serde(serialize_with = "compact_f64")]
+    pub m12: f64,
+    #[serde(serialize_with = "compact_f64")]
+    pub m13: f64,
+    #[serde(serialize_with = "compact_f64")]
+    pub m21: f64,
+    #[serde(serialize_with = "compact_f64")]
+    pub m22: f64,
+    #[serde(serialize_with = "compact_f64")]
+    pub m23: f64,
+    #[serde(serialize_with = "compact_f64")]
+    pub m31: f64,
+    #[serde(serialize_with = "compact_f64")]
+    pub m32: f64,
+    #[serde(serialize_with = "compact_f64")]
+    pub m33: f64,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            m11: 1.0,
+            m12: 0.0,
+            m13: 0.0,
+            m21: 0.0,
+            m22: 1.0,
+            m23: 0.0,
+            m31: 0.0,
+            m32: 0.0,
+            m33: 1.0,
+        }
+    }
+}
+
+// Writes integral floats as integers, the way the device does. (`1`, not `1.0`.)
+fn compact_f64<S: Serializer>(value: &f64, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    if value.fract() == 0.0 && value.abs() < 9_007_199_254_740_992.0 {
+        serializer.serialize_i64(*value as i64)
+    } else {
+        serializer.serialize_f64(*value)
+    }
+}
+
+fn compact_opt_f64<S: Serializer>(
+    value: &Option<f64>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => compact_f64(value, serializer),
+        None => serializer.serialize_none(),
+    }
 }
 
 impl Content {
+    /// Loads the content for the item at `path` (relative to the root of `backend`).
+    pub fn load(backend: &dyn Backend, path: impl AsRef<Path>) -> Result<Content> {
+        let content_path = path.as_ref().with_extension(super::CONTENT_EXTENSION);
+        let content = serde_json::from_slice(&backend.read(&content_path)?)?;
+
+        Ok(content)
+    }
+
+    /// Saves the content for the item at `path` (relative to the root of `backend`).
+    ///
+    /// Like `Metadata::save`, this writes a temporary file and renames it over the old one.
+    pub fn save(&self, backend: &dyn Backend, path: impl AsRef<Path>) -> Result<()> {
+        let content_path = path.as_ref().with_extension(super::CONTENT_EXTENSION);
+        let tmp_path = content_path.with_extension(format!("{}.tmp", super::CONTENT_EXTENSION));
+
+        backend.write(&tmp_path, &serde_json::to_vec_pretty(self)?)?;
+        backend.rename(&tmp_path, &content_path)
+    }
+
+    /// The file format version. Files without a `formatVersion` are version 1.
+    pub fn format_version(&self) -> u32 {
+        self.format_version.unwrap_or(1)
+    }
+
+    /// The ids of the (non-deleted) pages, in display order.
+    pub fn page_ids(&self) -> Vec<&str> {
+        if let Some(c_pages) = &self.c_pages {
+            c_pages
+                .ordered()
+                .into_iter()
+                .map(|i| c_pages.pages[i].id.as_str())
+                .collect()
+        } else {
+            self.pages.iter().flatten().map(|s| s.as_str()).collect()
+        }
+    }
+
+    /// For each page in display order, the index of the page of the original PDF or EPUB it
+    /// shows, or `None` if it was inserted on the device.
+    pub fn page_redirections(&self) -> Vec<Option<usize>> {
+        let redirection = |i: usize, redir: Option<i64>| match redir {
+            Some(r) if r >= 0 => Some(r as usize),
+            Some(_) => None,
+            None => Some(i),
+        };
+        if let Some(c_pages) = &self.c_pages {
+            c_pages
+                .ordered()
+                .into_iter()
+                .enumerate()
+                .map(|(i, p)| redirection(i, c_pages.pages[p].redir.as_ref().map(|r| r.value)))
+                .collect()
+        } else {
+            let map = self.redirection_page_map.as_deref().unwrap_or_default();
+            (0..self.page_ids().len())
+                .map(|i| redirection(i, map.get(i).map(|r| i64::from(*r))))
+                .collect()
+        }
+    }
+
     pub fn add_page(&mut self, s: impl Into<String>) {
-        self.pages.push(s.into());
-        self.page_count = self.pages.len();
+        self.insert_page(self.page_ids().len(), s);
+    }
+
+    /// Inserts a page with id `s` so that it becomes page number `index`. In a PDF or EPUB, the
+    /// new page is a blank one, not a page of the original.
+    ///
+    /// Panics if `index` is greater than the number of pages.
+    pub fn insert_page(&mut self, index: usize, s: impl Into<String>) {
+        let id = s.into();
+        let has_original = self.has_original();
+        if let Some(c_pages) = &mut self.c_pages {
+            let timestamp = c_pages.next_timestamp();
+            let mut order = c_pages.ordered();
+            order.insert(index, c_pages.pages.len());
+            let mut page = CPage::new(&id);
+            if has_original {
+                page.redir = Some(Timestamped {
+                    timestamp: timestamp.clone(),
+                    value: -1,
+                });
+            }
+            c_pages.pages.push(page);
+            c_pages.renumber(&order, &timestamp);
+        }
+        if self.c_pages.is_none() || self.pages.is_some() {
+            if let Some(map) = self.redirection_map() {
+                map.insert(index, -1);
+            }
+            self.pages
+                .get_or_insert_with(Vec::default)
+                .insert(index, id);
+        }
+        self.update_page_count();
+    }
+
+    /// Removes page number `index`, returning its id, or `None` if there is no such page.
+    ///
+    /// In `cPages`, the page is marked deleted rather than dropped, as xochitl does, so that the
+    /// deletion wins when the file is merged with an older copy.
+    pub fn remove_page(&mut self, index: usize) -> Option<String> {
+        let mut removed = None;
+        if let Some(c_pages) = &mut self.c_pages {
+            let timestamp = c_pages.next_timestamp();
+            if let Some(position) = c_pages.ordered().get(index) {
+                let page = &mut c_pages.pages[*position];
+                page.deleted = Some(Timestamped {
+                    timestamp,
+                    value: 1,
+                });
+                removed = Some(page.id.clone());
+            }
+        }
+        if self.pages.as_ref().is_some_and(|pages| index < pages.len()) {
+            if let Some(map) = self.redirection_map() {
+                map.remove(index);
+            }
+            removed = self.pages.as_mut().map(|pages| pages.remove(index));
+        }
+        self.update_page_count();
+        removed
+    }
+
+    // Whether the document has an original PDF or EPUB, rather than being a notebook.
+    fn has_original(&self) -> bool {
+        let original = self.c_pages.as_ref().and_then(|c| c.original.as_ref());
+        self.original_page_count.is_some() || original.is_some_and(|o| o.value >= 0)
+    }
+
+    // The `redirectionPageMap` of a file with a `pages` list, ready to be edited along with it:
+    // it is created for a PDF or EPUB that doesn't have one yet, and filled out to the length
+    // of `pages`. Notebooks don't get one.
+    fn redirection_map(&mut self) -> Option<&mut Vec<i32>> {
+        let len = self.pages.as_ref()?.len();
+        if self.redirection_page_map.is_none() && self.original_page_count.is_none() {
+            return None;
+        }
+        let map = self.redirection_page_map.get_or_insert_with(Vec::default);
+        // Pages past the end of the map show the original page with the same index.
+        while map.len() < len {
+            map.push(map.len() as i32);
+        }
+        Some(map)
+    }
+
+    // Keeps `pageCount`, if the file has one, in step with the pages.
+    fn update_page_count(&mut self) {
+        let count = self.page_ids().len();
+        if let Some(page_count) = &mut self.page_count {
+            *page_count = count;
+        }
+    }
+
+    /// The template of the page with id `id`. Only files with `cPages` store templates.
+    pub fn page_template(&self, id: &str) -> Option<&str> {
+        self.c_pages
+            .iter()
+            .flat_map(|c| c.pages.iter())
+            .find(|p| p.id == id)
+            .and_then(|p| p.template.as_ref())
+            .map(|t| t.value.as_str())
+    }
+
+    /// Sets the template of the page with id `id`. Only files with `cPages` store templates;
+    /// for older files, this does nothing.
+    pub fn set_page_template(&mut self, id: &str, template: &str) {
+        if let Some(c_pages) = &mut self.c_pages {
+            let timestamp = c_pages.next_timestamp();
+            if let Some(page) = c_pages.pages.iter_mut().find(|p| p.id == id) {
+                page.template = Some(Timestamped {
+                    timestamp,
+                    value: template.to_string(),
+                });
+            }
+        }
+    }
+
+    /// Moves page number `from` so that it becomes page number `to`.
+    ///
+    /// Panics if either index is out of range.
+    pub fn move_page(&mut self, from: usize, to: usize) {
+        if let Some(c_pages) = &mut self.c_pages {
+            let timestamp = c_pages.next_timestamp();
+            let mut order = c_pages.ordered();
+            let position = order.remove(from);
+            order.insert(to, position);
+            c_pages.renumber(&order, &timestamp);
+        }
+        if let Some(map) = self.redirection_map() {
+            let redirection = map.remove(from);
+            map.insert(to, redirection);
+        }
+        if let Some(pages) = &mut self.pages {
+            let id = pages.remove(from);
+            pages.insert(to, id);
+        }
+    }
+}
+
+impl CPages {
+    /// Positions (in `pages`) of the non-deleted pages, in display order.
+    fn ordered(&self) -> Vec<usize> {
+        let mut order = (0..self.pages.len())
+            .filter(|i| !self.pages[*i].is_deleted())
+            .collect::<Vec<_>>();
+        order.sort_by(|i1, i2| {
+            let idx1 = self.pages[*i1].idx.as_ref().map(|i| i.value.as_str());
+            let idx2 = self.pages[*i2].idx.as_ref().map(|i| i.value.as_str());
+            idx1.cmp(&idx2)
+        });
+        order
+    }
+
+    /// A timestamp later than any in the file, for a change made now.
+    fn next_timestamp(&self) -> String {
+        let mut timestamps = Vec::default();
+        timestamps.extend(self.last_opened.as_ref().map(|t| &t.timestamp));
+        timestamps.extend(self.original.as_ref().map(|t| &t.timestamp));
+        for page in &self.pages {
+            timestamps.extend(page.idx.as_ref().map(|t| &t.timestamp));
+            timestamps.extend(page.redir.as_ref().map(|t| &t.timestamp));
+            timestamps.extend(page.template.as_ref().map(|t| &t.timestamp));
+            timestamps.extend(page.deleted.as_ref().map(|t| &t.timestamp));
+        }
+        let counter = timestamps
+            .into_iter()
+            .filter_map(|t| t.split_once(':'))
+            .filter_map(|(_, counter)| counter.parse::<u64>().ok())
+            .max()
+            .unwrap_or(0);
+        format!("{}:{}", LOCAL_AUTHOR, counter + 1)
+    }
+
+    /// Rewrites the `idx` of the pages at `order` so that they sort in that order, stamping the
+    /// ones that change with `timestamp`.
+    fn renumber(&mut self, order: &[usize], timestamp: &str) {
+        // All of the keys have the same length, so they compare the same as the numbers.
+        let mut width = 1;
+        while 26usize.pow(width) < order.len() {
+            width += 1;
+        }
+
+        for (n, position) in order.iter().enumerate() {
+            let mut key = Vec::default();
+            let mut remaining = n;
+            for _ in 0..width {
+                key.push(b'a' + (remaining % 26) as u8);
+                remaining /= 26;
+            }
+            key.push(b'b');
+            key.reverse();
+
+            // unwrap: the key is all ASCII.
+            let value = String::from_utf8(key).unwrap();
+            let page = &mut self.pages[*position];
+            if page.idx.as_ref().is_none_or(|i| i.value != value) {
+                page.idx = Some(Timestamped {
+                    timestamp: timestamp.to_string(),
+                    value,
+                });
+            }
+        }
+    }
+}
+
+impl CPage {
+    fn new(id: impl AsRef<str>) -> CPage {
+        CPage {
+            id: id.as_ref().to_string(),
+            idx: None,
+            redir: None,
+            template: None,
+            deleted: None,
+            extra: Default::default(),
+        }
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted.as_ref().is_some_and(|d| d.value != 0)
     }
 }
 
 impl Default for Content {
     fn default() -> Self {
-        let mut transform = HashMap::default();
-        transform.insert("m11".into(), 1);
-        transform.insert("m12".into(), 0);
-        transform.insert("m13".into(), 0);
-        transform.insert("m21".into(), 0);
-        transform.insert("m22".into(), 1);
-        transform.insert("m23".into(), 0);
-        transform.insert("m31".into(), 0);
-        transform.insert("m32".into(), 0);
-        transform.insert("m33".into(), 1);
-
         Content {
-            cover_page_number: 0,
-            dummy_document: false,
-            extra_metadata: Default::default(),
-            file_type: "pdf".into(),
-            font_name: "".into(),
-            line_height: -1,
-            margins: 100,
-            orientation: "portrait".into(),
-            page_count: 0,
-            pages: Default::default(),
-            text_alignment: "left".into(),
-            text_scale: 1,
-            transform,
+            c_pages: None,
+            cover_page_number: Some(0),
+            custom_zoom_center_x: None,
+            custom_zoom_center_y: None,
+            custom_zoom_orientation: None,
+            custom_zoom_page_height: None,
+            custom_zoom_page_width: None,
+            custom_zoom_scale: None,
+            document_metadata: None,
+            dummy_document: Some(false),
+            extra_metadata: Some(Default::default()),
+            file_type: Some("pdf".into()),
+            font_name: Some("".into()),
+            format_version: None,
+            line_height: Some(-1),
+            margins: Some(100),
+            orientation: Some("portrait".into()),
+            original_page_count: None,
+            page_count: Some(0),
+            page_tags: None,
+            pages: Some(Default::default()),
+            redirection_page_map: None,
+            size_in_bytes: None,
+            tags: None,
+            text_alignment: Some("left".into()),
+            text_scale: Some(1.0),
+            transform: Some(Default::default()),
+            zoom_mode: None,
+            extra: Default::default(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIRMWARE_2: &str = include_str!("../../tests/fixtures/content/firmware-2.content");
+    const FIRMWARE_3: &str = include_str!("../../tests/fixtures/content/firmware-3.content");
+
+    fn round_trip(json: &str) -> Content {
+        let value: Value = serde_json::from_str(json).unwrap();
+        let content: Content = serde_json::from_str(json).unwrap();
+        assert_eq!(serde_json::to_value(&content).unwrap(), value);
+        content
+    }
+
+    #[test]
+    fn firmware_2_round_trips() {
+        let content = round_trip(FIRMWARE_2);
+        assert_eq!(content.format_version(), 1);
+        assert_eq!(content.page_ids().len(), 3);
+        assert_eq!(content.page_redirections(), vec![Some(0), None, Some(1)]);
+        assert_eq!(content.margins, Some(180));
+        assert!(content.extra.contains_key("lastOpenedPage"));
+    }
+
+    #[test]
+    fn firmware_3_round_trips() {
+        let content = round_trip(FIRMWARE_3);
+        assert_eq!(content.format_version(), 2);
+        assert_eq!(
+            content.page_ids(),
+            vec![
+                "6f2cbd63-6f23-4e3b-9c38-0f7b1a2e1d01",
+                "0d1f8a2e-5a4c-4f0e-8f6e-7d3b9c2a1e02"
+            ]
+        );
+        assert_eq!(
+            content.page_template("0d1f8a2e-5a4c-4f0e-8f6e-7d3b9c2a1e02"),
+            Some("Blank")
+        );
+        // Firmware 3 doesn't write these, so neither do we.
+        assert_eq!(content.dummy_document, None);
+        assert_eq!(content.margins, None);
+        assert!(content.transform.is_none());
+    }
+
+    #[test]
+    fn missing_fields_stay_missing() {
+        let mut content = round_trip("{}");
+        assert_eq!(content.file_type, None);
+        assert_eq!(content.page_count, None);
+        content.add_page("new-page");
+        assert_eq!(content.page_count, None);
+        assert_eq!(
+            serde_json::to_value(&content).unwrap(),
+            serde_json::json!({ "pages": ["new-page"] })
+        );
+    }
+
+    #[test]
+    fn changes_are_stamped_after_everything_in_the_file() {
+        let mut content: Content = serde_json::from_str(FIRMWARE_3).unwrap();
+        content.insert_page(0, "new-page");
+        let c_pages = content.c_pages.as_ref().unwrap();
+        let new_page = &c_pages.pages[3];
+        assert_eq!(new_page.idx.as_ref().unwrap().timestamp, "1:3");
+        assert_eq!(content.page_ids()[0], "new-page");
+
+        content.set_page_template("new-page", "Blank");
+        let c_pages = content.c_pages.as_ref().unwrap();
+        assert_eq!(c_pages.pages[3].template.as_ref().unwrap().timestamp, "1:4");
+
+        // Timestamps from other authors count too.
+        content.c_pages.as_mut().unwrap().pages[0].template = Some(Timestamped {
+            timestamp: "2:10".to_string(),
+            value: "Blank".to_string(),
+        });
+        content.move_page(0, 2);
+        let c_pages = content.c_pages.as_ref().unwrap();
+        assert_eq!(c_pages.pages[3].idx.as_ref().unwrap().timestamp, "1:11");
+    }
+
+    #[test]
+    fn removed_pages_are_marked_deleted() {
+        let mut content: Content = serde_json::from_str(FIRMWARE_3).unwrap();
+        let removed = content.remove_page(0);
+        assert_eq!(
+            removed.as_deref(),
+            Some("6f2cbd63-6f23-4e3b-9c38-0f7b1a2e1d01")
+        );
+        assert_eq!(
+            content.page_ids(),
+            vec!["0d1f8a2e-5a4c-4f0e-8f6e-7d3b9c2a1e02"]
+        );
+        assert_eq!(content.page_count, Some(1));
+
+        let c_pages = content.c_pages.as_ref().unwrap();
+        assert_eq!(c_pages.pages.len(), 3);
+        let deleted = c_pages.pages[0].deleted.as_ref().unwrap();
+        assert_eq!((deleted.timestamp.as_str(), deleted.value), ("1:3", 1));
+        assert!(content.remove_page(1).is_none());
+    }
+
+    #[test]
+    fn legacy_edits_keep_the_redirections() {
+        let mut content: Content = serde_json::from_str(FIRMWARE_2).unwrap();
+        content.insert_page(0, "new-page");
+        assert_eq!(content.redirection_page_map, Some(vec![-1, 0, -1, 1]));
+        assert_eq!(
+            content.page_redirections(),
+            vec![None, Some(0), None, Some(1)]
+        );
+        assert_eq!(content.page_count, Some(4));
+
+        content.remove_page(1);
+        assert_eq!(content.page_redirections(), vec![None, None, Some(1)]);
+        content.move_page(2, 0);
+        assert_eq!(content.page_redirections(), vec![Some(1), None, None]);
+        assert_eq!(
+            content.page_ids(),
+            vec![
+                "b7e3c9d4-2c1a-4b8f-9e5d-1a6f3e8c2b03",
+                "new-page",
+                "0d1f8a2e-5a4c-4f0e-8f6e-7d3b9c2a1e02"
+            ]
+        );
+        assert_eq!(content.original_page_count, Some(2));
+        assert_eq!(content.remove_page(3), None);
+        assert_eq!(content.redirection_page_map, Some(vec![1, -1, -1]));
+    }
+
+    #[test]
+    fn legacy_pdfs_without_a_map_get_one() {
+        let json = r#"{"fileType": "pdf", "originalPageCount": 2, "pages": ["a", "b"]}"#;
+        let mut content: Content = serde_json::from_str(json).unwrap();
+        content.insert_page(1, "c");
+        assert_eq!(content.redirection_page_map, Some(vec![0, -1, 1]));
+
+        // A notebook has no original pages to redirect to.
+        let json = r#"{"fileType": "notebook", "pages": ["a", "b"]}"#;
+        let mut content: Content = serde_json::from_str(json).unwrap();
+        content.insert_page(1, "c");
+        content.move_page(0, 2);
+        content.remove_page(0);
+        assert_eq!(content.page_ids(), vec!["b", "a"]);
+        assert_eq!(content.redirection_page_map, None);
+    }
+
+    #[test]
+    fn pages_inserted_into_a_c_pages_pdf_are_blank() {
+        let mut content: Content = serde_json::from_str(FIRMWARE_3).unwrap();
+        let c_pages = content.c_pages.as_mut().unwrap();
+        c_pages.original.as_mut().unwrap().value = 2;
+        for (page, redir) in c_pages.pages.iter_mut().zip(&[0, 1, 1]) {
+            page.redir = Some(Timestamped {
+                timestamp: "1:2".to_string(),
+                value: *redir,
+            });
+        }
+        content.insert_page(1, "new-page");
+        assert_eq!(content.page_redirections(), vec![Some(0), None, Some(1)]);
+        content.move_page(1, 0);
+        assert_eq!(content.page_redirections(), vec![None, Some(0), Some(1)]);
+        content.remove_page(1);
+        assert_eq!(content.page_redirections(), vec![None, Some(1)]);
+    }
+
+    #[test]
+    fn edits_keep_unmodeled_fields() {
+        let mut content: Content = serde_json::from_str(FIRMWARE_3).unwrap();
+        content.add_page("new-page");
+        let value = serde_json::to_value(&content).unwrap();
+        assert_eq!(value["pageCount"], 3);
+        assert_eq!(value["zoomMode"], "bestFit");
+        assert!(value.get("margins").is_none());
+        assert_eq!(value["cPages"]["pages"][3]["id"], "new-page");
+        assert_eq!(value["cPages"]["uuids"][0]["second"], 1);
+    }
+}
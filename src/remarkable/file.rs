@@ -1,9 +1,11 @@
+use super::backend::Backend;
 use super::metadata::Metadata;
 use crate::{Error, Result};
 use std::path::{Path, PathBuf};
 
 #[derive(Debug)]
 pub struct File {
+    /// Path of the metadata file, relative to the root of the data directory.
     pub path: PathBuf,
     pub filedata: Result<FileData>,
 }
@@ -37,15 +39,15 @@ pub struct FileData {
 }
 
 impl FileData {
-    fn load(path: impl AsRef<Path>) -> Result<FileData> {
-        let metadata = Metadata::load(&path)?;
+    fn load(backend: &dyn Backend, path: impl AsRef<Path>) -> Result<FileData> {
+        let metadata = Metadata::load(backend, &path)?;
         Ok(FileData { metadata })
     }
 }
 
 impl File {
-    pub fn load(path: PathBuf) -> Result<File> {
-        let filedata = FileData::load(&path);
+    pub fn load(backend: &dyn Backend, path: PathBuf) -> Result<File> {
+        let filedata = FileData::load(backend, &path);
         Ok(File { path, filedata })
     }
 }
@@ -0,0 +1,708 @@
+//! Parser for the `.rm` files holding the strokes drawn on each page.
+//!
+//! Each page of a document has a `<page uuid>.rm` file in the document's `<uuid>/` directory.
+//! The format is reverse-engineered by third parties, and comes in two flavors:
+//!
+//! * versions 3 and 5: a fixed layout of layers, strokes, and points.
+//! * version 6: a sequence of tagged blocks describing a CRDT scene tree. Strokes are
+//!   "line items" whose parent is a group (layer) node.
+//!
+//! Both are parsed into the same `Page` type. Coordinates are in screen pixels with the origin
+//! at the top-left of the page, regardless of version.
+
+use super::backend::Backend;
+use crate::{Error, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+pub const LINES_EXTENSION: &str = "rm";
+
+/// Width of the device screen, in pixels.
+pub const SCREEN_WIDTH: f32 = 1404.0;
+
+/// Height of the device screen, in pixels.
+pub const SCREEN_HEIGHT: f32 = 1872.0;
+
+const HEADER_PREFIX: &[u8] = b"reMarkable .lines file, version=";
+const HEADER_LEN: usize = 43;
+
+// v6 block types.
+const TREE_NODE_BLOCK: u8 = 0x02;
+const SCENE_GROUP_ITEM_BLOCK: u8 = 0x04;
+const SCENE_LINE_ITEM_BLOCK: u8 = 0x05;
+
+// v6 tag types.
+const TAG_BYTE4: u8 = 0x4;
+const TAG_BYTE8: u8 = 0x8;
+const TAG_LENGTH4: u8 = 0xC;
+const TAG_ID: u8 = 0xF;
+
+// v6 item types found at the start of a scene item's value.
+const ITEM_TYPE_GROUP: u8 = 0x02;
+const ITEM_TYPE_LINE: u8 = 0x03;
+
+// The root of the v6 scene tree. Layers are the groups directly below it.
+const ROOT_ID: CrdtId = CrdtId(0, 1);
+
+/// The strokes on a single page.
+#[derive(Clone, Debug, Default)]
+pub struct Page {
+    /// The version of the file that was parsed.
+    pub version: u32,
+    pub layers: Vec<Layer>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Layer {
+    /// The layer's name. Only version 6 files store names.
+    pub name: Option<String>,
+    pub strokes: Vec<Stroke>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Stroke {
+    pub pen: Pen,
+    pub color: Color,
+    /// The base width (brush size) of the stroke.
+    pub width: f32,
+    pub points: Vec<Point>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+    pub speed: f32,
+    /// Direction (tilt) of the pen, in radians.
+    pub direction: f32,
+    pub width: f32,
+    /// Pen pressure, in the range [0.0..1.0].
+    pub pressure: f32,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Pen {
+    Brush,
+    PencilTilt,
+    Ballpoint,
+    Marker,
+    Fineliner,
+    Highlighter,
+    Eraser,
+    SharpPencil,
+    EraseArea,
+    BrushV2,
+    MechanicalPencilV2,
+    PencilV2,
+    BallpointV2,
+    MarkerV2,
+    FinelinerV2,
+    HighlighterV2,
+    Calligraphy,
+    Shader,
+    Unknown(u32),
+}
+
+impl From<u32> for Pen {
+    fn from(value: u32) -> Pen {
+        match value {
+            0 => Pen::Brush,
+            1 => Pen::PencilTilt,
+            2 => Pen::Ballpoint,
+            3 => Pen::Marker,
+            4 => Pen::Fineliner,
+            5 => Pen::Highlighter,
+            6 => Pen::Eraser,
+            7 => Pen::SharpPencil,
+            8 => Pen::EraseArea,
+            12 => Pen::BrushV2,
+            13 => Pen::MechanicalPencilV2,
+            14 => Pen::PencilV2,
+            15 => Pen::BallpointV2,
+            16 => Pen::MarkerV2,
+            17 => Pen::FinelinerV2,
+            18 => Pen::HighlighterV2,
+            21 => Pen::Calligraphy,
+            23 => Pen::Shader,
+            _ => Pen::Unknown(value),
+        }
+    }
+}
+
+impl Pen {
+    pub fn is_highlighter(&self) -> bool {
+        matches!(self, Pen::Highlighter | Pen::HighlighterV2)
+    }
+
+    pub fn is_eraser(&self) -> bool {
+        matches!(self, Pen::Eraser | Pen::EraseArea)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Color {
+    Black,
+    Grey,
+    White,
+    Yellow,
+    Green,
+    Pink,
+    Blue,
+    Red,
+    GreyOverlap,
+    Highlight,
+    GreenTwo,
+    Cyan,
+    Magenta,
+    YellowTwo,
+    Unknown(u32),
+}
+
+impl From<u32> for Color {
+    fn from(value: u32) -> Color {
+        match value {
+            0 => Color::Black,
+            1 => Color::Grey,
+            2 => Color::White,
+            3 => Color::Yellow,
+            4 => Color::Green,
+            5 => Color::Pink,
+            6 => Color::Blue,
+            7 => Color::Red,
+            8 => Color::GreyOverlap,
+            9 => Color::Highlight,
+            10 => Color::GreenTwo,
+            11 => Color::Cyan,
+            12 => Color::Magenta,
+            13 => Color::YellowTwo,
+            _ => Color::Unknown(value),
+        }
+    }
+}
+
+impl Page {
+    /// Loads the strokes for page `page_id` of document `doc_id` from `backend`.
+    pub fn load(
+        backend: &dyn Backend,
+        doc_id: impl AsRef<str>,
+        page_id: impl AsRef<str>,
+    ) -> Result<Page> {
+        let path = Path::new(doc_id.as_ref())
+            .join(page_id.as_ref())
+            .with_extension(LINES_EXTENSION);
+        Page::parse(&backend.read(&path)?)
+    }
+
+    /// Parses the contents of a `.rm` file.
+    pub fn parse(data: &[u8]) -> Result<Page> {
+        let version = parse_header(data)?;
+        let mut reader = Reader::new(&data[HEADER_LEN..]);
+        match version {
+            3 | 5 => parse_v5(&mut reader, version),
+            6 => parse_v6(&mut reader),
+            _ => Err(format_error(format!("unsupported version {}", version))),
+        }
+    }
+
+    /// Iterates over the strokes in all of the layers.
+    pub fn strokes(&self) -> impl Iterator<Item = &Stroke> {
+        self.layers.iter().flat_map(|l| l.strokes.iter())
+    }
+}
+
+fn format_error(msg: impl Into<String>) -> Error {
+    Error::LinesFormatError(msg.into())
+}
+
+fn parse_header(data: &[u8]) -> Result<u32> {
+    if data.len() < HEADER_LEN || !data.starts_with(HEADER_PREFIX) {
+        return Err(format_error("missing header"));
+    }
+    let version = String::from_utf8_lossy(&data[HEADER_PREFIX.len()..HEADER_LEN]);
+    version
+        .trim()
+        .parse()
+        .map_err(|_| format_error(format!("bad version '{}'", version.trim())))
+}
+
+fn parse_v5(reader: &mut Reader, version: u32) -> Result<Page> {
+    let mut page = Page {
+        version,
+        layers: Vec::default(),
+    };
+
+    let num_layers = reader.u32()?;
+    for _ in 0..num_layers {
+        let mut layer = Layer::default();
+        let num_strokes = reader.u32()?;
+        for _ in 0..num_strokes {
+            let pen = reader.u32()?;
+            let color = reader.u32()?;
+            let _unknown = reader.u32()?;
+            let width = reader.f32()?;
+            if version >= 5 {
+                let _unknown = reader.u32()?;
+            }
+
+            let num_points = reader.u32()? as usize;
+            reader.check_remaining(num_points, 24)?;
+            let mut points = Vec::with_capacity(num_points);
+            for _ in 0..num_points {
+                points.push(Point {
+                    x: reader.f32()?,
+                    y: reader.f32()?,
+                    speed: reader.f32()?,
+                    direction: reader.f32()?,
+                    width: reader.f32()?,
+                    pressure: reader.f32()?,
+                });
+            }
+
+            layer.strokes.push(Stroke {
+                pen: pen.into(),
+                color: color.into(),
+                width,
+                points,
+            });
+        }
+        page.layers.push(layer);
+    }
+
+    Ok(page)
+}
+
+fn parse_v6(reader: &mut Reader) -> Result<Page> {
+    let mut labels: HashMap<CrdtId, String> = HashMap::default();
+    let mut layer_ids: Vec<CrdtId> = Vec::default();
+    let mut lines: Vec<(CrdtId, Stroke)> = Vec::default();
+
+    while !reader.is_empty() {
+        let length = reader.u32()? as usize;
+        let _unknown = reader.u8()?;
+        let _min_version = reader.u8()?;
+        let current_version = reader.u8()?;
+        let block_type = reader.u8()?;
+        let mut block = Reader::new(reader.bytes(length)?);
+
+        match block_type {
+            TREE_NODE_BLOCK => {
+                let (node_id, label) = parse_tree_node(&mut block)?;
+                if let Some(label) = label {
+                    labels.insert(node_id, label);
+                }
+            }
+            SCENE_GROUP_ITEM_BLOCK => {
+                if let Some((parent_id, node_id)) = parse_group_item(&mut block)? {
+                    if parent_id == ROOT_ID && !layer_ids.contains(&node_id) {
+                        layer_ids.push(node_id);
+                    }
+                }
+            }
+            SCENE_LINE_ITEM_BLOCK => {
+                if let Some(line) = parse_line_item(&mut block, current_version)? {
+                    lines.push(line);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Lines may hang off groups that we never saw added to the root.
+    for (parent_id, _) in &lines {
+        if !layer_ids.contains(parent_id) {
+            layer_ids.push(*parent_id);
+        }
+    }
+
+    let mut layers: Vec<Layer> = layer_ids
+        .iter()
+        .map(|id| Layer {
+            name: labels.get(id).cloned(),
+            strokes: Vec::default(),
+        })
+        .collect();
+    for (parent_id, stroke) in lines {
+        // unwrap: every parent was added to layer_ids above.
+        let index = layer_ids.iter().position(|id| *id == parent_id).unwrap();
+        layers[index].strokes.push(stroke);
+    }
+
+    Ok(Page { version: 6, layers })
+}
+
+fn parse_tree_node(block: &mut Reader) -> Result<(CrdtId, Option<String>)> {
+    let node_id = block.tagged_id(1)?;
+    let label = if block.has_tag(2, TAG_LENGTH4) {
+        let mut lww = block.subblock(2)?;
+        let _timestamp = lww.tagged_id(1)?;
+        Some(lww.string(2)?)
+    } else {
+        None
+    };
+    Ok((node_id, label))
+}
+
+/// Returns `(parent_id, node_id)` for a group item, or `None` if it has been deleted.
+fn parse_group_item(block: &mut Reader) -> Result<Option<(CrdtId, CrdtId)>> {
+    let parent_id = block.tagged_id(1)?;
+    let _item_id = block.tagged_id(2)?;
+    let _left_id = block.tagged_id(3)?;
+    let _right_id = block.tagged_id(4)?;
+    let _deleted_length = block.tagged_u32(5)?;
+
+    if !block.has_tag(6, TAG_LENGTH4) {
+        return Ok(None);
+    }
+    let mut value = block.subblock(6)?;
+    if value.u8()? != ITEM_TYPE_GROUP {
+        return Err(format_error("group item without a group"));
+    }
+    Ok(Some((parent_id, value.tagged_id(2)?)))
+}
+
+/// Returns `(parent_id, stroke)` for a line item, or `None` if it has been deleted.
+fn parse_line_item(block: &mut Reader, version: u8) -> Result<Option<(CrdtId, Stroke)>> {
+    let parent_id = block.tagged_id(1)?;
+    let _item_id = block.tagged_id(2)?;
+    let _left_id = block.tagged_id(3)?;
+    let _right_id = block.tagged_id(4)?;
+    let _deleted_length = block.tagged_u32(5)?;
+
+    if !block.has_tag(6, TAG_LENGTH4) {
+        return Ok(None);
+    }
+    let mut value = block.subblock(6)?;
+    if value.u8()? != ITEM_TYPE_LINE {
+        return Err(format_error("line item without a line"));
+    }
+
+    let pen = value.tagged_u32(1)?;
+    let color = value.tagged_u32(2)?;
+    let thickness_scale = value.tagged_f64(3)?;
+    let _starting_length = value.tagged_f32(4)?;
+
+    let mut point_data = value.subblock(5)?;
+    let points = if version >= 2 {
+        parse_v6_points_compact(&mut point_data)?
+    } else {
+        parse_v6_points_full(&mut point_data)?
+    };
+
+    Ok(Some((
+        parent_id,
+        Stroke {
+            pen: pen.into(),
+            color: color.into(),
+            width: thickness_scale as f32,
+            points,
+        },
+    )))
+}
+
+// Version 6 puts the origin at the top-center of the page.
+fn v6_point(x: f32, y: f32, speed: f32, direction: f32, width: f32, pressure: f32) -> Point {
+    Point {
+        x: x + SCREEN_WIDTH / 2.0,
+        y,
+        speed,
+        direction,
+        width,
+        pressure,
+    }
+}
+
+fn parse_v6_points_full(reader: &mut Reader) -> Result<Vec<Point>> {
+    let mut points = Vec::with_capacity(reader.remaining() / 24);
+    while !reader.is_empty() {
+        let x = reader.f32()?;
+        let y = reader.f32()?;
+        let speed = reader.f32()?;
+        let direction = reader.f32()?;
+        let width = reader.f32()?;
+        let pressure = reader.f32()?;
+        points.push(v6_point(x, y, speed, direction, width, pressure));
+    }
+    Ok(points)
+}
+
+fn parse_v6_points_compact(reader: &mut Reader) -> Result<Vec<Point>> {
+    let mut points = Vec::with_capacity(reader.remaining() / 14);
+    while !reader.is_empty() {
+        let x = reader.f32()?;
+        let y = reader.f32()?;
+        let speed = f32::from(reader.u16()?) / 4.0;
+        let width = f32::from(reader.u16()?) / 4.0;
+        let direction = f32::from(reader.u8()?) * std::f32::consts::TAU / 255.0;
+        let pressure = f32::from(reader.u8()?) / 255.0;
+        points.push(v6_point(x, y, speed, direction, width, pressure));
+    }
+    Ok(points)
+}
+
+/// An id in the v6 CRDT: (author, counter).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct CrdtId(u8, u64);
+
+/// A little-endian cursor over a byte slice, with helpers for the v6 tagged values.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    fn check_remaining(&self, count: usize, size: usize) -> Result<()> {
+        if count.saturating_mul(size) > self.remaining() {
+            Err(format_error("unexpected end of data"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        self.check_remaining(len, 1)?;
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+
+    fn f64(&mut self) -> Result<f64> {
+        Ok(f64::from_le_bytes(self.array()?))
+    }
+
+    fn varuint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift >= 64 {
+                return Err(format_error("varuint too long"));
+            }
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+
+    fn has_tag(&self, index: u64, tag_type: u8) -> bool {
+        let mut peek = Reader {
+            data: self.data,
+            pos: self.pos,
+        };
+        peek.varuint()
+            .is_ok_and(|tag| tag == (index << 4) | u64::from(tag_type))
+    }
+
+    fn tag(&mut self, index: u64, tag_type: u8) -> Result<()> {
+        let tag = self.varuint()?;
+        if tag != (index << 4) | u64::from(tag_type) {
+            return Err(format_error(format!(
+                "expected tag {}/{:x}, found {}/{:x}",
+                index,
+                tag_type,
+                tag >> 4,
+                tag & 0xf
+            )));
+        }
+        Ok(())
+    }
+
+    fn tagged_id(&mut self, index: u64) -> Result<CrdtId> {
+        self.tag(index, TAG_ID)?;
+        Ok(CrdtId(self.u8()?, self.varuint()?))
+    }
+
+    fn tagged_u32(&mut self, index: u64) -> Result<u32> {
+        self.tag(index, TAG_BYTE4)?;
+        self.u32()
+    }
+
+    fn tagged_f32(&mut self, index: u64) -> Result<f32> {
+        self.tag(index, TAG_BYTE4)?;
+        self.f32()
+    }
+
+    fn tagged_f64(&mut self, index: u64) -> Result<f64> {
+        self.tag(index, TAG_BYTE8)?;
+        self.f64()
+    }
+
+    fn subblock(&mut self, index: u64) -> Result<Reader<'a>> {
+        self.tag(index, TAG_LENGTH4)?;
+        let length = self.u32()? as usize;
+        Ok(Reader::new(self.bytes(length)?))
+    }
+
+    fn string(&mut self, index: u64) -> Result<String> {
+        let mut block = self.subblock(index)?;
+        let length = block.varuint()? as usize;
+        let _is_ascii = block.u8()?;
+        Ok(String::from_utf8_lossy(block.bytes(length)?).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::TAU;
+
+    const V3: &[u8] = include_bytes!("../../tests/fixtures/lines/v3.rm");
+    const V5: &[u8] = include_bytes!("../../tests/fixtures/lines/v5.rm");
+    const V6: &[u8] = include_bytes!("../../tests/fixtures/lines/v6.rm");
+
+    fn point(x: f32, y: f32, speed: f32, direction: f32, width: f32, pressure: f32) -> Point {
+        Point {
+            x,
+            y,
+            speed,
+            direction,
+            width,
+            pressure,
+        }
+    }
+
+    fn assert_format_error(result: Result<Page>) {
+        match result {
+            Err(Error::LinesFormatError(_)) => {}
+            result => panic!("expected LinesFormatError, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn parses_version_3() {
+        let page = Page::parse(V3).unwrap();
+        assert_eq!(page.version, 3);
+        assert_eq!(page.layers.len(), 1);
+        assert_eq!(page.layers[0].name, None);
+
+        let stroke = &page.layers[0].strokes[0];
+        assert_eq!(stroke.pen, Pen::Ballpoint);
+        assert_eq!(stroke.color, Color::Black);
+        assert_eq!(stroke.width, 1.875);
+        assert_eq!(
+            stroke.points,
+            vec![
+                point(100.0, 200.0, 0.5, 1.5, 2.0, 0.25),
+                point(110.0, 220.0, 0.75, 1.5, 2.5, 0.5),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_version_5() {
+        let page = Page::parse(V5).unwrap();
+        assert_eq!(page.version, 5);
+        assert_eq!(page.layers.len(), 2);
+
+        let stroke = &page.layers[0].strokes[0];
+        assert_eq!(stroke.pen, Pen::FinelinerV2);
+        assert_eq!(stroke.color, Color::Grey);
+        assert_eq!(stroke.width, 2.0);
+        assert_eq!(
+            stroke.points,
+            vec![
+                point(10.0, 20.0, 1.0, 0.0, 2.0, 0.5),
+                point(30.0, 40.0, 2.0, 0.0, 2.0, 0.75),
+            ]
+        );
+
+        let stroke = &page.layers[1].strokes[0];
+        assert_eq!(stroke.pen, Pen::HighlighterV2);
+        assert!(stroke.pen.is_highlighter());
+        assert_eq!(stroke.width, 15.0);
+        assert_eq!(
+            stroke.points,
+            vec![point(700.0, 900.0, 0.0, 0.0, 15.0, 1.0)]
+        );
+    }
+
+    #[test]
+    fn parses_version_6() {
+        let page = Page::parse(V6).unwrap();
+        assert_eq!(page.version, 6);
+        assert_eq!(page.layers.len(), 1);
+        assert_eq!(page.layers[0].name.as_deref(), Some("Layer 1"));
+        // The deleted line is left out.
+        assert_eq!(page.strokes().count(), 2);
+
+        // Compact points, with x moved from the center of the page to the left.
+        let stroke = &page.layers[0].strokes[0];
+        assert_eq!(stroke.pen, Pen::BallpointV2);
+        assert_eq!(stroke.color, Color::Blue);
+        assert_eq!(stroke.width, 2.0);
+        assert_eq!(
+            stroke.points,
+            vec![
+                point(602.0, 50.0, 2.0, 0.0, 2.5, 1.0),
+                point(702.0, 60.0, 1.0, 51.0 * TAU / 255.0, 3.0, 128.0 / 255.0),
+            ]
+        );
+
+        // Full points, from an older block.
+        let stroke = &page.layers[0].strokes[1];
+        assert_eq!(stroke.pen, Pen::PencilV2);
+        assert_eq!(stroke.color, Color::Black);
+        assert_eq!(stroke.width, 1.5);
+        assert_eq!(stroke.points, vec![point(0.0, 0.0, 1.0, 0.5, 3.0, 0.75)]);
+    }
+
+    #[test]
+    fn truncated_files_are_errors() {
+        for data in &[V3, V5] {
+            for len in 0..data.len() {
+                assert_format_error(Page::parse(&data[..len]));
+            }
+        }
+        // A v6 file cut between blocks is just shorter, but one cut inside a block is an error.
+        for len in 0..V6.len() {
+            let _ = Page::parse(&V6[..len]);
+        }
+        assert_format_error(Page::parse(&V6[..V6.len() - 1]));
+    }
+
+    #[test]
+    fn unknown_headers_are_errors() {
+        assert_format_error(Page::parse(b""));
+        assert_format_error(Page::parse(b"not a lines file at all, not even close..."));
+        let mut data = V5.to_vec();
+        data[HEADER_PREFIX.len()] = b'4';
+        assert_format_error(Page::parse(&data));
+        data[HEADER_PREFIX.len()] = b'x';
+        assert_format_error(Page::parse(&data));
+    }
+}
@@ -0,0 +1,91 @@
+use super::backend::{Backend, Entry};
+use crate::{Error, Result};
+use log::trace;
+use std::fs::{create_dir_all, read_dir};
+use std::path::{Path, PathBuf};
+
+/// A `Backend` over a plain local directory, e.g., an extracted backup of the data directory.
+///
+/// There is no device attached, so `run_command` and the system file methods always fail.
+pub struct LocalBackend {
+    root: PathBuf,
+}
+
+impl LocalBackend {
+    pub fn new(root: impl AsRef<Path>) -> LocalBackend {
+        LocalBackend {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        self.root.as_path()
+    }
+}
+
+impl Backend for LocalBackend {
+    fn list(&self, path: &Path) -> Result<Vec<Entry>> {
+        let mut entries = Vec::default();
+        for item in read_dir(self.root.join(path))? {
+            let item = item?;
+            entries.push(Entry {
+                path: path.join(item.file_name()),
+                is_dir: item.file_type()?.is_dir(),
+            });
+        }
+        Ok(entries)
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        trace!("reading {:?}", path);
+        Ok(std::fs::read(self.root.join(path))?)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> Result<()> {
+        trace!("writing {:?}", path);
+        let full_path = self.root.join(path);
+        if let Some(parent) = full_path.parent() {
+            if !parent.exists() {
+                create_dir_all(parent)?;
+            }
+        }
+        Ok(std::fs::write(full_path, data)?)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        trace!("removing {:?}", path);
+        let full_path = self.root.join(path);
+        if full_path.is_dir() {
+            std::fs::remove_dir_all(full_path)?;
+        } else {
+            std::fs::remove_file(full_path)?;
+        }
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        trace!("renaming {:?} ==> {:?}", from, to);
+        Ok(std::fs::rename(self.root.join(from), self.root.join(to))?)
+    }
+
+    fn run_command(&self, program: &str, _args: &[&str]) -> Result<Vec<u8>> {
+        Err(Error::UnsupportedOperation(format!(
+            "running '{}' on a local directory",
+            program
+        )))
+    }
+
+    fn read_system_file(&self, path: &Path) -> Result<Vec<u8>> {
+        Err(Error::UnsupportedOperation(format!(
+            "reading {:?} from a local directory",
+            path
+        )))
+    }
+
+    fn write_system_file(&self, path: &Path, _data: &[u8]) -> Result<()> {
+        Err(Error::UnsupportedOperation(format!(
+            "writing {:?} to a local directory",
+            path
+        )))
+    }
+}
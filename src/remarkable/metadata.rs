@@ -1,25 +1,56 @@
+use super::backend::Backend;
+use super::TRASH_ID;
 use crate::Result;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+/// The `type` of folders.
+pub const COLLECTION_TYPE: &str = "CollectionType";
+/// The `type` of documents (notebooks, PDFs, and EPUBs).
+pub const DOCUMENT_TYPE: &str = "DocumentType";
+
+// Not used by xochitl. We keep the parent of trashed items here so that they can be restored.
+const ORIGINAL_PARENT_KEY: &str = "originalParent";
+
+/// The `.metadata` file for a document or folder.
+///
+/// Firmware 3 no longer writes `deleted`, `metadatamodified`, `modified`, `synced` or `version`,
+/// nor `lastOpenedPage` for folders, so they are optional, and are only written back if they
+/// were there to begin with (or were set).
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Metadata {
-    deleted: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deleted: Option<bool>,
     #[serde(rename = "lastModified")]
     last_modified: String,
-    #[serde(rename = "lastOpenedPage", default)]
-    last_opened_page: u32,
-    metadatamodified: bool,
-    modified: bool,
+    #[serde(
+        rename = "lastOpenedPage",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    last_opened_page: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadatamodified: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modified: Option<bool>,
 
     pub parent: String,
     pinned: bool,
-    synced: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    synced: Option<bool>,
     #[serde(rename = "type")]
     pub typ: String,
-    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version: Option<u32>,
     #[serde(rename = "visibleName")]
     pub visible_name: String,
+
+    // Fields from newer firmware (e.g., `lastOpened`, `createdTime`, `source`, `new`) that we
+    // don't model. They are kept so that saving a loaded file doesn't drop them.
+    #[serde(flatten)]
+    extra: Map<String, Value>,
 }
 
 impl Metadata {
@@ -31,32 +62,241 @@ impl Metadata {
         }
     }
 
-    pub fn load(path: impl AsRef<Path>) -> Result<Metadata> {
+    /// Loads the metadata for the item at `path` (relative to the root of `backend`).
+    pub fn load(backend: &dyn Backend, path: impl AsRef<Path>) -> Result<Metadata> {
         let md_path = path.as_ref().with_extension(super::METADATA_EXTENSION);
-        let file = std::fs::File::open(&md_path)?;
-        let metadata = serde_json::from_reader(file)?;
+        let metadata = serde_json::from_slice(&backend.read(&md_path)?)?;
 
         Ok(metadata)
     }
+
+    /// Saves the metadata for the item at `path` (relative to the root of `backend`).
+    ///
+    /// The data is written to a temporary file next to the document, then renamed into place,
+    /// so a failed write never leaves a truncated `.metadata` file behind.
+    pub fn save(&self, backend: &dyn Backend, path: impl AsRef<Path>) -> Result<()> {
+        let md_path = path.as_ref().with_extension(super::METADATA_EXTENSION);
+        let tmp_path = md_path.with_extension(format!("{}.tmp", super::METADATA_EXTENSION));
+
+        backend.write(&tmp_path, &serde_json::to_vec_pretty(self)?)?;
+        backend.rename(&tmp_path, &md_path)
+    }
+
+    pub fn is_folder(&self) -> bool {
+        self.typ == COLLECTION_TYPE
+    }
+
+    /// Records a change made here, so that xochitl (and the cloud sync) pick it up: sets
+    /// `lastModified`, and bumps the version and sets `metadatamodified` if the file has them.
+    pub fn mark_modified(&mut self) {
+        if let Some(version) = &mut self.version {
+            *version += 1;
+        }
+        if self.metadatamodified.is_some() {
+            self.metadatamodified = Some(true);
+        }
+        self.last_modified = millis_string(SystemTime::now());
+    }
+
+    /// Moves the item into the folder `parent`. Moving it to the trash remembers the folder it
+    /// was in, so that it can be restored there, and moving it out forgets it again.
+    pub fn move_to(&mut self, parent: impl AsRef<str>) {
+        let parent = parent.as_ref();
+        if parent == TRASH_ID && self.parent != TRASH_ID {
+            let original = std::mem::replace(&mut self.parent, parent.to_string());
+            self.set_original_parent(Some(&original));
+        } else if parent != TRASH_ID {
+            self.parent = parent.to_string();
+            self.set_original_parent(None);
+        }
+    }
+
+    /// The parent the item had before it was moved to the trash, if known.
+    pub fn original_parent(&self) -> Option<&str> {
+        self.extra.get(ORIGINAL_PARENT_KEY).and_then(|v| v.as_str())
+    }
+
+    pub fn set_original_parent(&mut self, parent: Option<&str>) {
+        match parent {
+            Some(parent) => self
+                .extra
+                .insert(ORIGINAL_PARENT_KEY.to_string(), parent.into()),
+            None => self.extra.remove(ORIGINAL_PARENT_KEY),
+        };
+    }
+
+    pub fn deleted(&self) -> bool {
+        self.deleted.unwrap_or(false)
+    }
+
+    pub fn set_deleted(&mut self, deleted: bool) {
+        set_flag(&mut self.deleted, deleted);
+    }
+
+    pub fn pinned(&self) -> bool {
+        self.pinned
+    }
+
+    pub fn set_pinned(&mut self, pinned: bool) {
+        self.pinned = pinned;
+    }
+
+    /// The modification time, or `None` if the stored value isn't a valid timestamp.
+    pub fn last_modified(&self) -> Option<SystemTime> {
+        self.last_modified
+            .parse()
+            .ok()
+            .map(|millis| UNIX_EPOCH + Duration::from_millis(millis))
+    }
+
+    pub fn set_last_modified(&mut self, time: SystemTime) {
+        self.last_modified = millis_string(time);
+    }
+
+    pub fn metadata_modified(&self) -> bool {
+        self.metadatamodified.unwrap_or(false)
+    }
+
+    pub fn set_metadata_modified(&mut self, modified: bool) {
+        set_flag(&mut self.metadatamodified, modified);
+    }
+
+    /// The version, or `None` for files from firmware that doesn't keep one.
+    pub fn version(&self) -> Option<u32> {
+        self.version
+    }
+
+    pub fn set_version(&mut self, version: u32) {
+        self.version = Some(version);
+    }
+}
+
+// Sets an optional flag. A missing flag already reads as false, so clearing one doesn't add it.
+fn set_flag(flag: &mut Option<bool>, value: bool) {
+    if value || flag.is_some() {
+        *flag = Some(value);
+    }
+}
+
+/// Formats `time` the way the device stores it: milliseconds since the epoch, as a string.
+fn millis_string(time: SystemTime) -> String {
+    // unwrap: we're not expecting times before 1970.
+    let n = time.duration_since(UNIX_EPOCH).unwrap();
+    format!("{}", n.as_millis())
 }
 
 impl Default for Metadata {
     fn default() -> Self {
-        let time = std::time::SystemTime::now();
-        let n = time.duration_since(std::time::UNIX_EPOCH).unwrap();
-        let last_modified = format!("{}", n.as_millis());
         Metadata {
-            deleted: false,
-            last_modified,
-            last_opened_page: 0,
-            metadatamodified: false,
-            modified: false,
+            deleted: Some(false),
+            last_modified: millis_string(SystemTime::now()),
+            last_opened_page: Some(0),
+            metadatamodified: Some(false),
+            modified: Some(false),
             parent: "".into(),
             pinned: false,
-            synced: false,
-            typ: "DocumentType".into(),
-            version: 2,
+            synced: Some(false),
+            typ: DOCUMENT_TYPE.into(),
+            version: Some(2),
             visible_name: "".into(),
+            extra: Default::default(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn firmware_3() -> Value {
+        json!({
+            "createdTime": "1704067200000",
+            "lastModified": "1704153600000",
+            "lastOpened": "1704153600000",
+            "lastOpenedPage": 2,
+            "parent": "",
+            "pinned": true,
+            "type": "DocumentType",
+            "visibleName": "Recent"
+        })
+    }
+
+    #[test]
+    fn firmware_2_round_trips() {
+        let value = json!({
+            "deleted": false,
+            "lastModified": "1609459200000",
+            "lastOpenedPage": 0,
+            "metadatamodified": true,
+            "modified": false,
+            "parent": "trash",
+            "pinned": false,
+            "synced": true,
+            "type": "CollectionType",
+            "version": 3,
+            "visibleName": "Work"
+        });
+        let metadata: Metadata = serde_json::from_value(value.clone()).unwrap();
+        assert!(metadata.is_folder());
+        assert!(metadata.metadata_modified());
+        assert_eq!(metadata.version(), Some(3));
+        assert_eq!(serde_json::to_value(&metadata).unwrap(), value);
+    }
+
+    #[test]
+    fn firmware_3_round_trips() {
+        let metadata: Metadata = serde_json::from_value(firmware_3()).unwrap();
+        assert_eq!(metadata.visible_name, "Recent");
+        assert!(!metadata.deleted());
+        assert!(metadata.pinned());
+        assert_eq!(metadata.version(), None);
+        assert_eq!(serde_json::to_value(&metadata).unwrap(), firmware_3());
+    }
+
+    #[test]
+    fn folders_without_a_last_opened_page_round_trip() {
+        let mut value = firmware_3();
+        value["type"] = COLLECTION_TYPE.into();
+        value.as_object_mut().unwrap().remove("lastOpenedPage");
+        let metadata: Metadata = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(metadata.last_opened_page, None);
+        assert_eq!(serde_json::to_value(&metadata).unwrap(), value);
+    }
+
+    #[test]
+    fn moving_to_the_trash_remembers_the_folder() {
+        let mut metadata = Metadata::with_name_and_parent("Item", "work");
+        metadata.move_to(TRASH_ID);
+        assert_eq!(metadata.parent, TRASH_ID);
+        assert_eq!(metadata.original_parent(), Some("work"));
+
+        // Moving within the trash keeps the original folder.
+        metadata.move_to(TRASH_ID);
+        assert_eq!(metadata.original_parent(), Some("work"));
+
+        metadata.move_to("notes");
+        assert_eq!(metadata.parent, "notes");
+        assert_eq!(metadata.original_parent(), None);
+        assert!(!serde_json::to_value(&metadata)
+            .unwrap()
+            .as_object()
+            .unwrap()
+            .contains_key(ORIGINAL_PARENT_KEY));
+    }
+
+    #[test]
+    fn changes_add_only_the_keys_they_need() {
+        let mut metadata: Metadata = serde_json::from_value(firmware_3()).unwrap();
+        metadata.mark_modified();
+        metadata.set_metadata_modified(false);
+        metadata.set_deleted(false);
+        let mut expected = firmware_3();
+        expected["lastModified"] = metadata.last_modified.clone().into();
+        assert_eq!(serde_json::to_value(&metadata).unwrap(), expected);
+
+        metadata.set_deleted(true);
+        expected["deleted"] = true.into();
+        assert_eq!(serde_json::to_value(&metadata).unwrap(), expected);
+    }
+}
@@ -0,0 +1,227 @@
+use super::backend::Backend;
+use super::content::Content;
+use super::templates::TemplateIndex;
+use crate::{Error, Result};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Template used for new pages when none is specified.
+pub const DEFAULT_TEMPLATE: &str = "Blank";
+
+/// The `.pagedata` file: the name of the template for each page, one per line, in page order.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Pagedata {
+    templates: Vec<String>,
+}
+
+impl Pagedata {
+    /// Creates pagedata for `count` pages, all using `template`.
+    pub fn with_pages(count: usize, template: impl AsRef<str>) -> Pagedata {
+        Pagedata {
+            templates: vec![template.as_ref().to_string(); count],
+        }
+    }
+
+    /// Loads the pagedata for the item at `path` (relative to the root of `backend`).
+    pub fn load(backend: &dyn Backend, path: impl AsRef<Path>) -> Result<Pagedata> {
+        let pd_path = path.as_ref().with_extension(super::PAGEDATA_EXTENSION);
+        String::from_utf8_lossy(&backend.read(&pd_path)?).parse()
+    }
+
+    /// Saves the pagedata for the item at `path` (relative to the root of `backend`).
+    pub fn save(&self, backend: &dyn Backend, path: impl AsRef<Path>) -> Result<()> {
+        let pd_path = path.as_ref().with_extension(super::PAGEDATA_EXTENSION);
+        backend.write(&pd_path, self.to_string().as_bytes())
+    }
+
+    pub fn templates(&self) -> &[String] {
+        &self.templates
+    }
+
+    pub fn len(&self) -> usize {
+        self.templates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+
+    pub fn set_template(&mut self, index: usize, template: impl AsRef<str>) {
+        self.templates[index] = template.as_ref().to_string();
+    }
+
+    /// Returns an error naming the first template that isn't installed on the device.
+    pub fn validate(&self, index: &TemplateIndex) -> Result<()> {
+        match self.templates.iter().find(|t| index.find(t).is_none()) {
+            Some(missing) => Err(Error::TemplateNotFound(missing.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for Pagedata {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Pagedata> {
+        Ok(Pagedata {
+            templates: s
+                .lines()
+                .map(|l| l.trim())
+                .filter(|l| !l.is_empty())
+                .map(|l| l.to_string())
+                .collect(),
+        })
+    }
+}
+
+impl fmt::Display for Pagedata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for template in &self.templates {
+            writeln!(f, "{}", template)?;
+        }
+        Ok(())
+    }
+}
+
+/// Page operations which keep `Content` and `Pagedata` in sync.
+///
+/// Documents from newer firmware also keep each page's template in `Content`, so that is
+/// updated, too.
+pub struct PageEditor<'a> {
+    content: &'a mut Content,
+    pagedata: &'a mut Pagedata,
+}
+
+impl<'a> PageEditor<'a> {
+    pub fn new(content: &'a mut Content, pagedata: &'a mut Pagedata) -> PageEditor<'a> {
+        PageEditor { content, pagedata }
+    }
+
+    pub fn add_page(&mut self, id: impl Into<String>, template: impl AsRef<str>) {
+        let index = self.content.page_ids().len();
+        self.insert_page(index, id, template);
+    }
+
+    pub fn insert_page(&mut self, index: usize, id: impl Into<String>, template: impl AsRef<str>) {
+        let id = id.into();
+        self.content.insert_page(index, &id);
+        self.content.set_page_template(&id, template.as_ref());
+        // Pagedata files may be shorter than the page list. Pad them out before inserting.
+        self.pad(index);
+        self.pagedata
+            .templates
+            .insert(index, template.as_ref().to_string());
+    }
+
+    pub fn remove_page(&mut self, index: usize) -> Option<String> {
+        let removed = self.content.remove_page(index);
+        if removed.is_some() && index < self.pagedata.templates.len() {
+            self.pagedata.templates.remove(index);
+        }
+        removed
+    }
+
+    pub fn move_page(&mut self, from: usize, to: usize) {
+        self.content.move_page(from, to);
+        self.pad(from.max(to) + 1);
+        let template = self.pagedata.templates.remove(from);
+        self.pagedata.templates.insert(to, template);
+    }
+
+    fn pad(&mut self, len: usize) {
+        while self.pagedata.templates.len() < len {
+            self.pagedata.templates.push(DEFAULT_TEMPLATE.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIRMWARE_2: &str = include_str!("../../tests/fixtures/content/firmware-2.content");
+    const FIRMWARE_3: &str = include_str!("../../tests/fixtures/content/firmware-3.content");
+
+    fn pagedata(templates: &[&str]) -> Pagedata {
+        templates.join("\n").parse().unwrap()
+    }
+
+    fn index(filenames: &[&str]) -> TemplateIndex {
+        let json = filenames
+            .iter()
+            .map(|f| format!(r#"{{"name": "{0}", "filename": "{0}", "iconCode": ""}}"#, f))
+            .collect::<Vec<_>>()
+            .join(",");
+        serde_json::from_str(&format!(r#"{{"templates": [{}]}}"#, json)).unwrap()
+    }
+
+    #[test]
+    fn parses_and_prints_one_template_per_line() {
+        let pagedata: Pagedata = "Blank\n\n  P Lines small \nBlank\n".parse().unwrap();
+        assert_eq!(pagedata.templates(), ["Blank", "P Lines small", "Blank"]);
+        assert_eq!(pagedata.to_string(), "Blank\nP Lines small\nBlank\n");
+    }
+
+    #[test]
+    fn validates_against_the_installed_templates() {
+        let index = index(&["Blank", "P Grid medium"]);
+        assert!(pagedata(&["Blank", "P Grid medium"])
+            .validate(&index)
+            .is_ok());
+        match pagedata(&["Blank", "Missing", "Other"]).validate(&index) {
+            Err(Error::TemplateNotFound(name)) => assert_eq!(name, "Missing"),
+            result => panic!("expected TemplateNotFound, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn edits_legacy_pages() {
+        let mut content: Content = serde_json::from_str(FIRMWARE_2).unwrap();
+        let mut pagedata = pagedata(&["Blank", "P Grid medium", "P Dots"]);
+        let mut editor = PageEditor::new(&mut content, &mut pagedata);
+        editor.insert_page(1, "new-page", "P Lines small");
+        editor.move_page(0, 3);
+        let removed = editor.remove_page(2);
+
+        assert_eq!(
+            removed.as_deref(),
+            Some("b7e3c9d4-2c1a-4b8f-9e5d-1a6f3e8c2b03")
+        );
+        assert_eq!(
+            content.page_ids(),
+            vec![
+                "new-page",
+                "0d1f8a2e-5a4c-4f0e-8f6e-7d3b9c2a1e02",
+                "6f2cbd63-6f23-4e3b-9c38-0f7b1a2e1d01"
+            ]
+        );
+        assert_eq!(content.page_redirections(), vec![None, None, Some(0)]);
+        assert_eq!(
+            pagedata.templates(),
+            ["P Lines small", "P Grid medium", "Blank"]
+        );
+    }
+
+    #[test]
+    fn edits_c_pages() {
+        let mut content: Content = serde_json::from_str(FIRMWARE_3).unwrap();
+        // Files from newer firmware may have no pagedata at all.
+        let mut pagedata = Pagedata::default();
+        let mut editor = PageEditor::new(&mut content, &mut pagedata);
+        editor.add_page("new-page", "P Lines small");
+        editor.move_page(2, 0);
+        assert_eq!(
+            editor.remove_page(1).as_deref(),
+            Some("6f2cbd63-6f23-4e3b-9c38-0f7b1a2e1d01")
+        );
+        assert_eq!(editor.remove_page(2), None);
+
+        assert_eq!(
+            content.page_ids(),
+            vec!["new-page", "0d1f8a2e-5a4c-4f0e-8f6e-7d3b9c2a1e02"]
+        );
+        assert_eq!(content.page_template("new-page"), Some("P Lines small"));
+        assert_eq!(pagedata.templates(), ["P Lines small", DEFAULT_TEMPLATE]);
+    }
+}
@@ -0,0 +1,256 @@
+use super::backend::{command_line, Backend, Entry};
+use crate::{Error, Result};
+use log::{debug, trace, warn};
+use ssh2::{CheckResult, KnownHostFileKind, Session, Sftp};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+
+const SSH_PORT: u16 = 22;
+const DEFAULT_IDENTITIES: &[&str] = &["id_ed25519", "id_ecdsa", "id_rsa"];
+
+/// A `Backend` which talks SFTP directly over an SSH session. No `sshfs`, FUSE, or mount point
+/// is required.
+pub struct SftpBackend {
+    session: Session,
+    sftp: Sftp,
+
+    // The absolute path to the data directory on the device.
+    root: PathBuf,
+}
+
+impl SftpBackend {
+    /// Opens an SSH session to `host` (optionally `host:port`) and starts SFTP.
+    ///
+    /// Authentication tries `identity`, if supplied, then the ssh agent, then the default
+    /// identities in `~/.ssh`. `data_dir` is relative to the ssh user's home directory.
+    pub fn connect(
+        user: impl AsRef<str>,
+        host: impl AsRef<str>,
+        identity: Option<impl AsRef<Path>>,
+        data_dir: impl AsRef<Path>,
+    ) -> Result<SftpBackend> {
+        let (hostname, port) = split_host_port(host.as_ref())?;
+        debug!("opening ssh session to {}:{}", hostname, port);
+
+        let mut session = Session::new()?;
+        session.set_tcp_stream(TcpStream::connect((hostname, port))?);
+        session.handshake()?;
+        check_known_host(&session, hostname, port)?;
+        authenticate(&session, user.as_ref(), identity)?;
+
+        let sftp = session.sftp()?;
+        let root = sftp.realpath(data_dir.as_ref())?;
+        debug!("sftp data directory is {:?}", root);
+
+        Ok(SftpBackend {
+            session,
+            sftp,
+            root,
+        })
+    }
+
+    fn create_parent_dirs(&self, path: &Path) -> Result<()> {
+        let mut missing = Vec::default();
+        for ancestor in path.ancestors().skip(1) {
+            if ancestor.as_os_str().is_empty() || self.sftp.stat(ancestor).is_ok() {
+                break;
+            }
+            missing.push(ancestor);
+        }
+        for dir in missing.iter().rev() {
+            trace!("creating directory {:?}", dir);
+            self.sftp.mkdir(dir, 0o755)?;
+        }
+        Ok(())
+    }
+
+    fn remove_full_path(&self, full_path: &Path) -> Result<()> {
+        if self.sftp.stat(full_path)?.is_dir() {
+            for (child, _) in self.read_dir(full_path)? {
+                self.remove_full_path(&child)?;
+            }
+            self.sftp.rmdir(full_path)?;
+        } else {
+            self.sftp.unlink(full_path)?;
+        }
+        Ok(())
+    }
+
+    fn read_dir(&self, full_path: &Path) -> Result<Vec<(PathBuf, ssh2::FileStat)>> {
+        Ok(self
+            .sftp
+            .readdir(full_path)?
+            .into_iter()
+            .filter(|(p, _)| {
+                p.file_name()
+                    .is_some_and(|name| name != "." && name != "..")
+            })
+            .collect())
+    }
+}
+
+impl Backend for SftpBackend {
+    fn list(&self, path: &Path) -> Result<Vec<Entry>> {
+        let mut entries = Vec::default();
+        for (child, stat) in self.read_dir(&self.root.join(path))? {
+            if let Some(name) = child.file_name() {
+                entries.push(Entry {
+                    path: path.join(name),
+                    is_dir: stat.is_dir(),
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        trace!("reading {:?}", path);
+        let mut data = Vec::default();
+        self.sftp
+            .open(self.root.join(path))?
+            .read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> Result<()> {
+        trace!("writing {:?}", path);
+        let full_path = self.root.join(path);
+        self.create_parent_dirs(&full_path)?;
+        self.sftp.create(&full_path)?.write_all(data)?;
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        trace!("removing {:?}", path);
+        self.remove_full_path(&self.root.join(path))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        trace!("renaming {:?} ==> {:?}", from, to);
+        let (from, to) = (self.root.join(from), self.root.join(to));
+        // SFTP version 3, which is all that libssh2 speaks, can't rename over an existing file
+        // (OpenSSH refuses, whatever the flags say), and libssh2 can't send the
+        // posix-rename@openssh.com extension. `mv` on the device is a plain rename(2), which
+        // replaces the file atomically.
+        match self.sftp.rename(&from, &to, None) {
+            Err(_) if self.sftp.lstat(&to).is_ok_and(|stat| !stat.is_dir()) => {
+                debug!("replacing {:?}", to);
+                self.run_command(
+                    "mv",
+                    &["-f", "--", &from.to_string_lossy(), &to.to_string_lossy()],
+                )?;
+            }
+            result => result?,
+        }
+        Ok(())
+    }
+
+    fn run_command(&self, program: &str, args: &[&str]) -> Result<Vec<u8>> {
+        debug!("running '{}' over ssh", program);
+        let command = command_line(program, args);
+        let mut channel = self.session.channel_session()?;
+        channel.exec(&command)?;
+        let mut output = Vec::default();
+        channel.read_to_end(&mut output)?;
+        let mut errors = String::default();
+        channel.stderr().read_to_string(&mut errors)?;
+        channel.wait_close()?;
+        if channel.exit_status()? != 0 {
+            return Err(Error::CommandFailed(command, errors.trim().to_string()));
+        }
+        Ok(output)
+    }
+
+    fn read_system_file(&self, path: &Path) -> Result<Vec<u8>> {
+        trace!("reading system file {:?}", path);
+        let mut data = Vec::default();
+        self.sftp.open(path)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn write_system_file(&self, path: &Path, data: &[u8]) -> Result<()> {
+        trace!("writing system file {:?}", path);
+        self.sftp.create(path)?.write_all(data)?;
+        Ok(())
+    }
+}
+
+fn split_host_port(host: &str) -> Result<(&str, u16)> {
+    match host.rsplit_once(':') {
+        Some((hostname, port)) => port
+            .parse()
+            .map(|port| (hostname, port))
+            .map_err(|_| Error::BadArgsError(format!("bad port in host '{}'", host))),
+        None => Ok((host, SSH_PORT)),
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(PathBuf::from)
+}
+
+/// Verifies the host key against `~/.ssh/known_hosts`. Unknown hosts are allowed (with a
+/// warning), but a mismatched key is an error.
+fn check_known_host(session: &Session, hostname: &str, port: u16) -> Result<()> {
+    let (key, _) = session
+        .host_key()
+        .ok_or_else(|| Error::SshAuthError("server sent no host key".to_string()))?;
+
+    let mut known_hosts = session.known_hosts()?;
+    if let Some(file) = home_dir().map(|h| h.join(".ssh/known_hosts")) {
+        if file.exists() {
+            known_hosts.read_file(&file, KnownHostFileKind::OpenSSH)?;
+        }
+    }
+
+    match known_hosts.check_port(hostname, port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::NotFound => {
+            warn!("host {} is not in known_hosts", hostname);
+            Ok(())
+        }
+        CheckResult::Mismatch => Err(Error::SshAuthError(format!(
+            "host key for {} does not match known_hosts",
+            hostname
+        ))),
+        CheckResult::Failure => Err(Error::SshAuthError(format!(
+            "failed to check host key for {}",
+            hostname
+        ))),
+    }
+}
+
+fn authenticate(session: &Session, user: &str, identity: Option<impl AsRef<Path>>) -> Result<()> {
+    if let Some(identity) = identity {
+        debug!("trying identity {:?}", identity.as_ref());
+        if session
+            .userauth_pubkey_file(user, None, identity.as_ref(), None)
+            .is_ok()
+        {
+            return Ok(());
+        }
+    }
+
+    debug!("trying ssh agent");
+    if session.userauth_agent(user).is_ok() {
+        return Ok(());
+    }
+
+    if let Some(ssh_dir) = home_dir().map(|h| h.join(".ssh")) {
+        for name in DEFAULT_IDENTITIES {
+            let key = ssh_dir.join(name);
+            if key.exists() {
+                debug!("trying identity {:?}", key);
+                if session.userauth_pubkey_file(user, None, &key, None).is_ok() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    Err(Error::SshAuthError(format!(
+        "no usable key or agent identity for {}",
+        user
+    )))
+}
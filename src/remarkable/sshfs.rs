@@ -1,9 +1,13 @@
+use super::backend::{command_line, shell_quote, Backend, Entry};
+use super::local::LocalBackend;
 use crate::{Error, Result};
 use log::{debug, error};
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Output, Stdio};
 
 const SSHFS_COMMAND: &str = "sshfs";
+const SSH_COMMAND: &str = "ssh";
 
 pub struct SshFsMount {
     host: String,
@@ -55,6 +59,132 @@ impl Drop for SshFsMount {
     }
 }
 
+/// A `Backend` which accesses the device's data directory through an `sshfs` mount, and runs
+/// commands with `ssh`.
+pub struct SshFsBackend {
+    user: String,
+    host: String,
+
+    // The data directory, as seen through the mount point.
+    local: LocalBackend,
+
+    // When dropped, it umounts the mount point.
+    mount: SshFsMount,
+}
+
+impl SshFsBackend {
+    /// Mounts the device at `mount_point`. `data_dir` is relative to the ssh user's home directory.
+    pub fn connect(
+        user: impl AsRef<str>,
+        host: impl AsRef<str>,
+        mount_point: impl AsRef<Path>,
+        data_dir: impl AsRef<Path>,
+    ) -> Result<SshFsBackend> {
+        let mut mount = SshFsMount::new(&user, &host, &mount_point);
+        mount.mount()?;
+
+        Ok(SshFsBackend {
+            user: user.as_ref().to_string(),
+            host: host.as_ref().to_string(),
+            local: LocalBackend::new(mount_point.as_ref().join(data_dir)),
+            mount,
+        })
+    }
+
+    pub fn mount_point(&self) -> &Path {
+        self.mount.mount_point()
+    }
+
+    fn ssh_command(&self) -> Command {
+        let mut command = Command::new(SSH_COMMAND);
+        command.arg(format!("{}@{}", self.user, self.host));
+        command
+    }
+}
+
+impl Backend for SshFsBackend {
+    fn list(&self, path: &Path) -> Result<Vec<Entry>> {
+        self.local.list(path)
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        self.local.read(path)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> Result<()> {
+        self.local.write(path, data)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        self.local.remove(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.local.rename(from, to)
+    }
+
+    fn run_command(&self, program: &str, args: &[&str]) -> Result<Vec<u8>> {
+        run_ssh_command(&self.user, &self.host, program, args)
+    }
+
+    // The mount only reaches the home directory, so system files go through ssh.
+    fn read_system_file(&self, path: &Path) -> Result<Vec<u8>> {
+        debug!("reading system file {:?}", path);
+        let command = format!("cat {}", quote_path(path));
+        let output = self.ssh_command().arg(&command).output()?;
+        command_output(command, output)
+    }
+
+    fn write_system_file(&self, path: &Path, data: &[u8]) -> Result<()> {
+        debug!("writing system file {:?}", path);
+        let command = format!("cat > {}", quote_path(path));
+        let mut child = self
+            .ssh_command()
+            .arg(&command)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // unwrap: stdin was piped above.
+        child.stdin.take().unwrap().write_all(data)?;
+        let output = child.wait_with_output()?;
+        command_output(command, output).map(|_| ())
+    }
+}
+
+/// Quotes `path` for the remote shell.
+fn quote_path(path: &Path) -> String {
+    shell_quote(&path.to_string_lossy())
+}
+
+/// Runs `program` on `host` via the `ssh` binary, returning its standard output.
+pub fn run_ssh_command(
+    user: impl AsRef<str>,
+    host: impl AsRef<str>,
+    program: &str,
+    args: &[&str],
+) -> Result<Vec<u8>> {
+    debug!("running '{}' on {}", program, host.as_ref());
+    // ssh hands the command line to the remote shell, so every word has to be quoted.
+    let command = command_line(program, args);
+    let output = Command::new(SSH_COMMAND)
+        .arg(format!("{}@{}", user.as_ref(), host.as_ref()))
+        .arg(&command)
+        .output()?;
+    command_output(command, output)
+}
+
+/// The standard output of the finished `command`, or an error with its standard error if it
+/// failed.
+fn command_output(command: String, output: Output) -> Result<Vec<u8>> {
+    if !output.status.success() {
+        return Err(Error::CommandFailed(
+            command,
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(output.stdout)
+}
+
 fn mount_sshdir(
     user: impl AsRef<str>,
     host: impl AsRef<str>,
@@ -67,12 +197,12 @@ fn mount_sshdir(
     }
 
     debug!("creating mount point at {}", mount_path.to_string_lossy());
-    std::fs::create_dir(&mount_path)?;
+    std::fs::create_dir(mount_path)?;
 
     debug!("mounting {}@{}", user.as_ref(), host.as_ref());
     Command::new(SSHFS_COMMAND)
-        .arg(&format!("{}@{}:", user.as_ref(), host.as_ref()))
-        .arg(&mount_path)
+        .arg(format!("{}@{}:", user.as_ref(), host.as_ref()))
+        .arg(mount_path)
         .output()?;
     debug!("mounted.");
     // TODO: should we check this output.
@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Directory on the device holding the template images and their index.
+pub const TEMPLATES_DIR: &str = "/usr/share/remarkable/templates";
+
+/// Name of the template index file in `TEMPLATES_DIR`.
+pub const TEMPLATES_INDEX: &str = "templates.json";
+
+/// Name of the copy of the original template index, made before it is first changed.
+pub const TEMPLATES_BACKUP: &str = "templates.json.bak";
+
+/// The contents of `templates.json`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TemplateIndex {
+    pub templates: Vec<Template>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Template {
+    /// The name shown in the template picker.
+    pub name: String,
+
+    /// The file name, without extension, of the template image. This is the name used in
+    /// `.pagedata` files.
+    pub filename: String,
+
+    #[serde(rename = "iconCode")]
+    pub icon_code: String,
+
+    #[serde(default)]
+    pub categories: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub landscape: Option<bool>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl TemplateIndex {
+    pub fn find(&self, filename: impl AsRef<str>) -> Option<&Template> {
+        self.templates
+            .iter()
+            .find(|t| t.filename == filename.as_ref())
+    }
+
+    /// Removes the template with the file name `filename`, returning it if it was there.
+    pub fn remove(&mut self, filename: impl AsRef<str>) -> Option<Template> {
+        let index = self
+            .templates
+            .iter()
+            .position(|t| t.filename == filename.as_ref())?;
+        Some(self.templates.remove(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn index() -> Value {
+        json!({
+            "templates": [
+                {
+                    "name": "Blank",
+                    "filename": "Blank",
+                    "iconCode": "\u{e9fe}",
+                    "categories": ["Creative", "Lines", "Grids", "Life/organize"]
+                },
+                {
+                    "name": "Grid medium",
+                    "filename": "P Grid medium",
+                    "iconCode": "\u{e99a}",
+                    "categories": ["Grids"],
+                    "landscape": true,
+                    "orientation": "landscape"
+                }
+            ],
+            "version": 2
+        })
+    }
+
+    #[test]
+    fn round_trips_with_unknown_fields() {
+        let parsed: TemplateIndex = serde_json::from_value(index()).unwrap();
+        assert_eq!(parsed.templates.len(), 2);
+        assert_eq!(parsed.extra["version"], 2);
+        let grid = &parsed.templates[1];
+        assert_eq!(grid.landscape, Some(true));
+        assert_eq!(grid.extra["orientation"], "landscape");
+        assert_eq!(parsed.templates[0].landscape, None);
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), index());
+    }
+
+    #[test]
+    fn categories_are_optional() {
+        let parsed: TemplateIndex = serde_json::from_value(json!({
+            "templates": [{"name": "Dots", "filename": "Dots", "iconCode": "x"}]
+        }))
+        .unwrap();
+        assert!(parsed.templates[0].categories.is_empty());
+    }
+
+    #[test]
+    fn finds_and_removes_by_file_name() {
+        let mut parsed: TemplateIndex = serde_json::from_value(index()).unwrap();
+        assert_eq!(parsed.find("P Grid medium").unwrap().name, "Grid medium");
+        // Templates are known by their file names, not the names in the picker.
+        assert!(parsed.find("Grid medium").is_none());
+
+        assert_eq!(parsed.remove("Blank").unwrap().name, "Blank");
+        assert!(parsed.remove("Blank").is_none());
+        assert!(parsed.find("Blank").is_none());
+        assert_eq!(parsed.templates.len(), 1);
+    }
+}
@@ -0,0 +1,287 @@
+use super::{File, Metadata};
+use crate::{Error, Result};
+use std::collections::{HashMap, HashSet};
+
+/// Id of the root folder. Top-level items have it as their parent.
+pub const ROOT_ID: &str = "";
+
+/// Parent of the items in the trash. In paths, the trash is the top-level folder `trash`.
+pub const TRASH_ID: &str = "trash";
+
+/// A document or folder in a `Tree`.
+#[derive(Clone, Debug)]
+pub struct Node {
+    id: String,
+    metadata: Metadata,
+}
+
+impl Node {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn name(&self) -> &str {
+        &self.metadata.visible_name
+    }
+
+    pub fn parent_id(&self) -> &str {
+        &self.metadata.parent
+    }
+
+    pub fn is_folder(&self) -> bool {
+        self.metadata.is_folder()
+    }
+}
+
+/// The folder hierarchy of the documents and folders in the data directory.
+///
+/// Items are linked through the `parent` of their metadata. The root folder (`ROOT_ID`) and
+/// the trash (`TRASH_ID`) aren't items themselves, but can be used wherever a folder id is
+/// expected. Deleted items and items whose metadata couldn't be loaded are left out.
+///
+/// Paths are slash-separated lists of visible names, such as `Work/Papers/foo`. Names that
+/// contain a `/` can't be reached by path.
+#[derive(Clone, Debug, Default)]
+pub struct Tree {
+    nodes: HashMap<String, Node>,
+
+    // Ids of the children of each parent, sorted by name.
+    children: HashMap<String, Vec<String>>,
+}
+
+impl Tree {
+    pub fn new(files: &[File]) -> Tree {
+        let mut tree = Tree::default();
+        for file in files {
+            if let Ok(file_data) = &file.filedata {
+                if file_data.metadata.deleted() {
+                    continue;
+                }
+                let node = Node {
+                    id: file.id(),
+                    metadata: file_data.metadata.clone(),
+                };
+                tree.children
+                    .entry(node.parent_id().to_string())
+                    .or_default()
+                    .push(node.id.clone());
+                tree.nodes.insert(node.id.clone(), node);
+            }
+        }
+
+        let nodes = &tree.nodes;
+        for ids in tree.children.values_mut() {
+            ids.sort_by(|id1, id2| (nodes[id1].name(), id1).cmp(&(nodes[id2].name(), id2)));
+        }
+        tree
+    }
+
+    pub fn get(&self, id: impl AsRef<str>) -> Option<&Node> {
+        self.nodes.get(id.as_ref())
+    }
+
+    /// All of the items, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// True if `id` is a folder, the root, or the trash.
+    pub fn is_folder(&self, id: impl AsRef<str>) -> bool {
+        let id = id.as_ref();
+        id == ROOT_ID || id == TRASH_ID || self.get(id).is_some_and(|n| n.is_folder())
+    }
+
+    /// The parent of `id`, or `None` if it's at the top level, in the trash, or an orphan.
+    pub fn parent(&self, id: impl AsRef<str>) -> Option<&Node> {
+        self.get(id).and_then(|n| self.get(n.parent_id()))
+    }
+
+    /// The items directly inside the folder `id`, sorted by name.
+    pub fn children(&self, id: impl AsRef<str>) -> impl Iterator<Item = &Node> {
+        self.children
+            .get(id.as_ref())
+            .into_iter()
+            .flatten()
+            .map(move |id| &self.nodes[id])
+    }
+
+    /// The folders containing `id`, innermost first. Stops early at a cycle or missing parent.
+    pub fn ancestors(&self, id: impl AsRef<str>) -> Ancestors<'_> {
+        Ancestors {
+            tree: self,
+            current: self.get(id),
+            visited: HashSet::default(),
+        }
+    }
+
+    /// All of the items inside the folder `id`, depth first, with their depth below `id`
+    /// (starting at 0). Each folder comes before its contents.
+    pub fn walk(&self, id: impl AsRef<str>) -> Walk<'_> {
+        let mut walk = Walk {
+            tree: self,
+            stack: Vec::default(),
+            visited: HashSet::default(),
+        };
+        walk.visited.insert(id.as_ref().to_string());
+        walk.push_children(id.as_ref(), 0);
+        walk
+    }
+
+    /// Items whose parent doesn't exist, was deleted, or isn't a folder.
+    pub fn orphans(&self) -> Vec<&Node> {
+        let mut orphans = self
+            .iter()
+            .filter(|n| n.parent_id() != ROOT_ID && n.parent_id() != TRASH_ID)
+            .filter(|n| !self.get(n.parent_id()).is_some_and(|p| p.is_folder()))
+            .collect::<Vec<_>>();
+        orphans.sort_by_key(|n| n.id());
+        orphans
+    }
+
+    /// Items that are their own ancestor. They can't be reached from the root.
+    pub fn cycles(&self) -> Vec<&Node> {
+        let mut cycles = self
+            .iter()
+            .filter(|n| {
+                let mut visited = HashSet::new();
+                let mut current = Some(*n);
+                while let Some(node) = current {
+                    if !visited.insert(node.id()) {
+                        return node.id() == n.id();
+                    }
+                    current = self.get(node.parent_id());
+                }
+                false
+            })
+            .collect::<Vec<_>>();
+        cycles.sort_by_key(|n| n.id());
+        cycles
+    }
+
+    /// Finds the id of the item at `path`. A path with a single component may also be an id.
+    ///
+    /// The empty path (or `/`) is the root folder, and `trash` is the trash.
+    pub fn resolve(&self, path: impl AsRef<str>) -> Result<String> {
+        let path = path.as_ref();
+        let trimmed = path.trim_matches('/');
+        if self.nodes.contains_key(trimmed) {
+            return Ok(trimmed.to_string());
+        }
+
+        let mut current = ROOT_ID.to_string();
+        for (i, name) in trimmed.split('/').filter(|n| !n.is_empty()).enumerate() {
+            let mut matches = self
+                .children(&current)
+                .filter(|n| n.name() == name)
+                .map(|n| n.id().to_string())
+                .collect::<Vec<_>>();
+            if i == 0 && name == TRASH_ID {
+                matches.push(TRASH_ID.to_string());
+            }
+            current = match matches.len() {
+                0 => return Err(Error::PathNotFound(path.to_string())),
+                1 => matches.remove(0),
+                _ => return Err(Error::AmbiguousPath(path.to_string(), matches.join(", "))),
+            };
+        }
+        Ok(current)
+    }
+
+    /// Finds all of the items named `name`, anywhere in the hierarchy.
+    pub fn find_named(&self, name: impl AsRef<str>) -> Vec<&Node> {
+        let mut found = self
+            .iter()
+            .filter(|n| n.name() == name.as_ref())
+            .collect::<Vec<_>>();
+        found.sort_by_key(|n| n.id());
+        found
+    }
+
+    /// Builds the path of the item with id `id`.
+    pub fn path_of(&self, id: impl AsRef<str>) -> Result<String> {
+        let mut names = Vec::new();
+        let mut visited = HashSet::new();
+        let mut current = id.as_ref();
+        while current != ROOT_ID {
+            if current == TRASH_ID {
+                names.push(TRASH_ID);
+                break;
+            }
+            if !visited.insert(current) {
+                return Err(Error::FolderCycle(current.to_string()));
+            }
+            let node = self
+                .get(current)
+                .ok_or_else(|| Error::FolderNotFound(current.to_string()))?;
+            names.push(node.name());
+            current = node.parent_id();
+        }
+        names.reverse();
+        Ok(names.join("/"))
+    }
+}
+
+/// Iterator returned by `Tree::ancestors`.
+pub struct Ancestors<'a> {
+    tree: &'a Tree,
+    current: Option<&'a Node>,
+    visited: HashSet<&'a str>,
+}
+
+impl<'a> Iterator for Ancestors<'a> {
+    type Item = &'a Node;
+
+    fn next(&mut self) -> Option<&'a Node> {
+        let current = self.current.take()?;
+        self.visited.insert(current.id());
+        let parent = self.tree.get(current.parent_id())?;
+        if self.visited.contains(parent.id()) {
+            return None;
+        }
+        self.current = Some(parent);
+        Some(parent)
+    }
+}
+
+/// Iterator returned by `Tree::walk`.
+pub struct Walk<'a> {
+    tree: &'a Tree,
+    stack: Vec<(usize, &'a Node)>,
+    visited: HashSet<String>,
+}
+
+impl<'a> Walk<'a> {
+    fn push_children(&mut self, id: &str, depth: usize) {
+        let tree = self.tree;
+        let children = tree.children(id).collect::<Vec<_>>();
+        // Reversed, so that the first child is popped first.
+        for child in children.into_iter().rev() {
+            self.stack.push((depth, child));
+        }
+    }
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = (usize, &'a Node);
+
+    fn next(&mut self) -> Option<(usize, &'a Node)> {
+        let (depth, node) = self.stack.pop()?;
+        // Items in a cycle are skipped the second time they come up.
+        if node.is_folder() && self.visited.insert(node.id().to_string()) {
+            self.push_children(node.id(), depth + 1);
+        }
+        Some((depth, node))
+    }
+}
@@ -0,0 +1,470 @@
+use crate::remarkable::lines::{Color, Page, Pen, Point, Stroke, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::{Error, Result};
+use printpdf::image::{DynamicImage, GenericImageView, RgbaImage};
+use std::fmt::Write;
+
+mod pdf;
+pub use pdf::overlay_pdf;
+
+/// Resolution of the device screen. Page coordinates are in pixels at this resolution.
+pub const SCREEN_DPI: f32 = 226.0;
+
+// Highlighters are see-through. This is roughly what the device shows.
+const HIGHLIGHTER_OPACITY: f32 = 0.35;
+
+/// How a single stroke should be drawn.
+struct Style {
+    rgb: (u8, u8, u8),
+    opacity: f32,
+
+    // If true, the stroke is drawn as one path of constant width. Otherwise, each segment is
+    // drawn separately so that the width and opacity can follow the pen pressure.
+    whole: bool,
+}
+
+fn rgb(color: Color) -> (u8, u8, u8) {
+    match color {
+        Color::Black | Color::Unknown(_) => (0, 0, 0),
+        Color::Grey | Color::GreyOverlap => (125, 125, 125),
+        Color::White => (255, 255, 255),
+        Color::Yellow | Color::Highlight => (255, 237, 117),
+        Color::Green => (161, 216, 125),
+        Color::Pink => (255, 140, 200),
+        Color::Blue => (0, 98, 204),
+        Color::Red => (217, 7, 7),
+        Color::GreenTwo => (0, 170, 0),
+        Color::Cyan => (0, 190, 230),
+        Color::Magenta => (200, 0, 200),
+        Color::YellowTwo => (250, 230, 0),
+    }
+}
+
+fn style(stroke: &Stroke) -> Option<Style> {
+    match stroke.pen {
+        // Area erasers have already been applied to the other strokes.
+        Pen::EraseArea => None,
+        Pen::Eraser => Some(Style {
+            rgb: (255, 255, 255),
+            opacity: 1.0,
+            whole: false,
+        }),
+        pen if pen.is_highlighter() => Some(Style {
+            rgb: rgb(stroke.color),
+            opacity: HIGHLIGHTER_OPACITY,
+            whole: true,
+        }),
+        _ => Some(Style {
+            rgb: rgb(stroke.color),
+            opacity: 1.0,
+            whole: false,
+        }),
+    }
+}
+
+fn point_width(stroke: &Stroke, point: &Point) -> f32 {
+    if point.width > 0.0 {
+        point.width
+    } else {
+        stroke.width
+    }
+}
+
+/// Pencils and brushes get lighter as the pressure drops.
+fn point_opacity(stroke: &Stroke, style: &Style, point: &Point) -> f32 {
+    match stroke.pen {
+        Pen::PencilTilt
+        | Pen::SharpPencil
+        | Pen::PencilV2
+        | Pen::MechanicalPencilV2
+        | Pen::Brush
+        | Pen::BrushV2 => style.opacity * (0.4 + 0.6 * point.pressure.clamp(0.0, 1.0)),
+        _ => style.opacity,
+    }
+}
+
+fn average_width(stroke: &Stroke) -> f32 {
+    if stroke.points.is_empty() {
+        stroke.width
+    } else {
+        stroke
+            .points
+            .iter()
+            .map(|p| point_width(stroke, p))
+            .sum::<f32>()
+            / stroke.points.len() as f32
+    }
+}
+
+/// Size in pixels of a page rendered at `dpi`.
+pub fn page_size(dpi: f32) -> (u32, u32) {
+    let scale = dpi / SCREEN_DPI;
+    (
+        (SCREEN_WIDTH * scale).round() as u32,
+        (SCREEN_HEIGHT * scale).round() as u32,
+    )
+}
+
+/// Renders `page` as an SVG document sized for `dpi`.
+///
+/// If present, `background` should be PNG data. It is embedded and stretched over the page.
+pub fn page_to_svg(page: &Page, dpi: f32, background: Option<&[u8]>) -> String {
+    let (width, height) = page_size(dpi);
+    let mut svg = String::default();
+
+    // unwrap: writing to a String can't fail.
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}">"#,
+        width, height, SCREEN_WIDTH, SCREEN_HEIGHT
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        r#"<rect width="{}" height="{}" fill="white"/>"#,
+        SCREEN_WIDTH, SCREEN_HEIGHT
+    )
+    .unwrap();
+    if let Some(png) = background {
+        writeln!(
+            svg,
+            r#"<image width="{}" height="{}" preserveAspectRatio="none" href="data:image/png;base64,{}"/>"#,
+            SCREEN_WIDTH,
+            SCREEN_HEIGHT,
+            base64::encode(png)
+        )
+        .unwrap();
+    }
+
+    for (i, layer) in page.layers.iter().enumerate() {
+        writeln!(
+            svg,
+            r#"<g id="layer{}" fill="none" stroke-linecap="round" stroke-linejoin="round">"#,
+            i + 1
+        )
+        .unwrap();
+        for stroke in &layer.strokes {
+            if let Some(style) = style(stroke) {
+                write_svg_stroke(&mut svg, stroke, &style);
+            }
+        }
+        writeln!(svg, "</g>").unwrap();
+    }
+
+    writeln!(svg, "</svg>").unwrap();
+    svg
+}
+
+fn write_svg_stroke(svg: &mut String, stroke: &Stroke, style: &Style) {
+    let (r, g, b) = style.rgb;
+    if style.whole {
+        let points = stroke
+            .points
+            .iter()
+            .map(|p| format!("{:.2},{:.2}", p.x, p.y))
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(
+            svg,
+            r#"<polyline points="{}" stroke="rgb({},{},{})" stroke-opacity="{:.2}" stroke-width="{:.2}"/>"#,
+            points,
+            r,
+            g,
+            b,
+            style.opacity,
+            average_width(stroke)
+        )
+        .unwrap();
+    } else {
+        for pair in stroke.points.windows(2) {
+            writeln!(
+                svg,
+                r#"<line x1="{:.2}" y1="{:.2}" x2="{:.2}" y2="{:.2}" stroke="rgb({},{},{})" stroke-opacity="{:.2}" stroke-width="{:.2}"/>"#,
+                pair[0].x,
+                pair[0].y,
+                pair[1].x,
+                pair[1].y,
+                r,
+                g,
+                b,
+                point_opacity(stroke, style, &pair[1]),
+                point_width(stroke, &pair[1])
+            )
+            .unwrap();
+        }
+    }
+}
+
+/// Rasterizes `page` at `dpi`. If present, `background` is stretched over the page first.
+pub fn page_to_image(
+    page: &Page,
+    dpi: f32,
+    background: Option<&DynamicImage>,
+) -> Result<DynamicImage> {
+    let (width, height) = page_size(dpi);
+    let mut pixmap = match background {
+        Some(image) => background_pixmap(image, width, height)?,
+        None => {
+            let mut pixmap = new_pixmap(width, height)?;
+            pixmap.fill(tiny_skia::Color::WHITE);
+            pixmap
+        }
+    };
+
+    let scale = dpi / SCREEN_DPI;
+    let transform = tiny_skia::Transform::from_scale(scale, scale);
+    for stroke in page.strokes() {
+        if let Some(style) = style(stroke) {
+            draw_stroke(&mut pixmap, stroke, &style, transform);
+        }
+    }
+
+    // The pixmap is opaque, so premultiplied and straight alpha are the same.
+    RgbaImage::from_raw(width, height, pixmap.take())
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| Error::RenderError("bad pixmap size".to_string()))
+}
+
+fn new_pixmap(width: u32, height: u32) -> Result<tiny_skia::Pixmap> {
+    tiny_skia::Pixmap::new(width, height)
+        .ok_or_else(|| Error::RenderError(format!("bad page size {}x{}", width, height)))
+}
+
+/// Creates a pixmap from `image`, resized to fit and flattened onto white.
+fn background_pixmap(image: &DynamicImage, width: u32, height: u32) -> Result<tiny_skia::Pixmap> {
+    let resized = if image.dimensions() == (width, height) {
+        image.to_rgba8()
+    } else {
+        image
+            .resize_exact(
+                width,
+                height,
+                printpdf::image::imageops::FilterType::Triangle,
+            )
+            .to_rgba8()
+    };
+
+    let mut pixmap = new_pixmap(width, height)?;
+    for (dst, src) in pixmap.data_mut().chunks_exact_mut(4).zip(resized.pixels()) {
+        let alpha = u32::from(src[3]);
+        for c in 0..3 {
+            dst[c] = ((u32::from(src[c]) * alpha + 255 * (255 - alpha)) / 255) as u8;
+        }
+        dst[3] = 255;
+    }
+    Ok(pixmap)
+}
+
+fn draw_stroke(
+    pixmap: &mut tiny_skia::Pixmap,
+    stroke: &Stroke,
+    style: &Style,
+    transform: tiny_skia::Transform,
+) {
+    let (r, g, b) = style.rgb;
+    let mut paint = tiny_skia::Paint {
+        anti_alias: true,
+        ..Default::default()
+    };
+
+    let draw =
+        |pixmap: &mut tiny_skia::Pixmap, paint: &tiny_skia::Paint, points: &[Point], width: f32| {
+            let mut builder = tiny_skia::PathBuilder::new();
+            builder.move_to(points[0].x, points[0].y);
+            for point in &points[1..] {
+                builder.line_to(point.x, point.y);
+            }
+            if let Some(path) = builder.finish() {
+                let line = tiny_skia::Stroke {
+                    width,
+                    line_cap: tiny_skia::LineCap::Round,
+                    line_join: tiny_skia::LineJoin::Round,
+                    ..Default::default()
+                };
+                pixmap.stroke_path(&path, paint, &line, transform, None);
+            }
+        };
+
+    if style.whole {
+        if stroke.points.len() > 1 {
+            paint.set_color_rgba8(r, g, b, (style.opacity * 255.0) as u8);
+            draw(pixmap, &paint, &stroke.points, average_width(stroke));
+        }
+    } else {
+        for pair in stroke.points.windows(2) {
+            let opacity = point_opacity(stroke, style, &pair[1]);
+            paint.set_color_rgba8(r, g, b, (opacity * 255.0) as u8);
+            draw(pixmap, &paint, pair, point_width(stroke, &pair[1]));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remarkable::lines::Layer;
+
+    // Points are (x, y, width, pressure).
+    fn stroke(pen: Pen, color: Color, points: &[(f32, f32, f32, f32)]) -> Stroke {
+        Stroke {
+            pen,
+            color,
+            width: 2.0,
+            points: points
+                .iter()
+                .map(|&(x, y, width, pressure)| Point {
+                    x,
+                    y,
+                    speed: 0.0,
+                    direction: 0.0,
+                    width,
+                    pressure,
+                })
+                .collect(),
+        }
+    }
+
+    fn page(strokes: Vec<Stroke>) -> Page {
+        Page {
+            version: 6,
+            layers: vec![Layer {
+                name: None,
+                strokes,
+            }],
+        }
+    }
+
+    // Rendered at the screen's resolution, so page and image pixels are the same.
+    fn render(page: &Page) -> RgbaImage {
+        page_to_image(page, SCREEN_DPI, None).unwrap().to_rgba8()
+    }
+
+    fn rgb_at(image: &RgbaImage, x: u32, y: u32) -> [u8; 3] {
+        let p = image.get_pixel(x, y);
+        [p[0], p[1], p[2]]
+    }
+
+    #[test]
+    fn writes_svg() {
+        let page = page(vec![
+            stroke(
+                Pen::Fineliner,
+                Color::Black,
+                &[(10.0, 20.0, 2.0, 0.5), (30.0, 40.0, 3.0, 0.5)],
+            ),
+            stroke(
+                Pen::Highlighter,
+                Color::Highlight,
+                &[(0.0, 0.0, 10.0, 1.0), (5.0, 0.0, 20.0, 1.0)],
+            ),
+            stroke(
+                Pen::EraseArea,
+                Color::Black,
+                &[(0.0, 0.0, 10.0, 1.0), (5.0, 0.0, 10.0, 1.0)],
+            ),
+        ]);
+        let svg = page_to_svg(&page, SCREEN_DPI / 2.0, None);
+        assert_eq!(
+            svg,
+            concat!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="702" height="936" viewBox="0 0 1404 1872">"#,
+                "\n",
+                r#"<rect width="1404" height="1872" fill="white"/>"#,
+                "\n",
+                r#"<g id="layer1" fill="none" stroke-linecap="round" stroke-linejoin="round">"#,
+                "\n",
+                r#"<line x1="10.00" y1="20.00" x2="30.00" y2="40.00" stroke="rgb(0,0,0)" stroke-opacity="1.00" stroke-width="3.00"/>"#,
+                "\n",
+                r#"<polyline points="0.00,0.00 5.00,0.00" stroke="rgb(255,237,117)" stroke-opacity="0.35" stroke-width="15.00"/>"#,
+                "\n",
+                "</g>\n",
+                "</svg>\n",
+            )
+        );
+    }
+
+    #[test]
+    fn embeds_the_background_in_svg() {
+        let svg = page_to_svg(&Page::default(), SCREEN_DPI, Some(b"png"));
+        assert!(svg.contains(r#"href="data:image/png;base64,cG5n""#));
+    }
+
+    #[test]
+    fn highlighters_are_see_through_and_even() {
+        // Back over itself, which mustn't darken the overlap.
+        let page = page(vec![stroke(
+            Pen::HighlighterV2,
+            Color::Yellow,
+            &[
+                (100.0, 100.0, 20.0, 1.0),
+                (300.0, 100.0, 20.0, 1.0),
+                (200.0, 100.0, 20.0, 1.0),
+            ],
+        )]);
+        let image = render(&page);
+        // 35% of (255, 237, 117) over white.
+        assert_eq!(rgb_at(&image, 150, 100), [255, 249, 207]);
+        assert_eq!(rgb_at(&image, 250, 100), [255, 249, 207]);
+        assert_eq!(rgb_at(&image, 150, 120), [255, 255, 255]);
+    }
+
+    #[test]
+    fn width_follows_each_point() {
+        let page = page(vec![stroke(
+            Pen::Fineliner,
+            Color::Black,
+            &[
+                (100.0, 500.0, 4.0, 0.2),
+                (300.0, 500.0, 4.0, 0.2),
+                (500.0, 500.0, 30.0, 1.0),
+            ],
+        )]);
+        let image = render(&page);
+        assert_eq!(rgb_at(&image, 200, 500), [0, 0, 0]);
+        assert_eq!(rgb_at(&image, 200, 510), [255, 255, 255]);
+        assert_eq!(rgb_at(&image, 400, 510), [0, 0, 0]);
+    }
+
+    #[test]
+    fn pencils_lighten_with_pressure() {
+        let page = page(vec![
+            stroke(
+                Pen::PencilV2,
+                Color::Black,
+                &[(100.0, 300.0, 10.0, 1.0), (300.0, 300.0, 10.0, 1.0)],
+            ),
+            stroke(
+                Pen::PencilV2,
+                Color::Black,
+                &[(100.0, 400.0, 10.0, 0.0), (300.0, 400.0, 10.0, 0.0)],
+            ),
+        ]);
+        let image = render(&page);
+        assert_eq!(rgb_at(&image, 200, 300), [0, 0, 0]);
+        // 40% black.
+        assert_eq!(rgb_at(&image, 200, 400), [153, 153, 153]);
+    }
+
+    #[test]
+    fn erasers_leave_the_page_white() {
+        let line = |pen, y| {
+            stroke(
+                pen,
+                Color::Black,
+                &[(100.0, y, 20.0, 1.0), (300.0, y, 20.0, 1.0)],
+            )
+        };
+        let page = page(vec![
+            line(Pen::Fineliner, 700.0),
+            line(Pen::Eraser, 700.0),
+            line(Pen::Fineliner, 800.0),
+            // Area erasers were applied when the file was written.
+            line(Pen::EraseArea, 800.0),
+            line(Pen::Eraser, 900.0),
+        ]);
+        let image = render(&page);
+        assert_eq!(rgb_at(&image, 200, 700), [255, 255, 255]);
+        assert_eq!(rgb_at(&image, 200, 800), [0, 0, 0]);
+        assert_eq!(rgb_at(&image, 200, 900), [255, 255, 255]);
+        assert!(page_to_svg(&page, SCREEN_DPI, None).contains(r#"stroke="rgb(255,255,255)""#));
+    }
+}
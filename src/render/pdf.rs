@@ -0,0 +1,490 @@
+use super::{average_width, point_opacity, point_width, style, Style, SCREEN_DPI};
+use crate::remarkable::lines::{Page, Stroke, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::remarkable::{Content, Transform};
+use crate::{Error, Result};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::Write;
+
+// Page attributes that a page may inherit from its ancestors in the page tree.
+const INHERITABLE: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+// PDF points per inch.
+const POINTS_PER_INCH: f64 = 72.0;
+
+/// Draws the strokes of `pages` over the pages of `original`, returning the new PDF.
+///
+/// `pages` holds the strokes of each page of `content`, in display order. The pages of the
+/// output follow that order, so reordered and deleted pages match the device. Pages that were
+/// inserted on the device become blank pages the size of the page before them.
+pub fn overlay_pdf(original: &[u8], content: &Content, pages: &[Page]) -> Result<Vec<u8>> {
+    let mut doc = Document::load_mem(original)?;
+    // Flatten the pages up front, since the same page may be shown more than once.
+    let originals = doc
+        .get_pages()
+        .into_values()
+        .map(|id| Ok((id, flattened_page(&doc, id)?)))
+        .collect::<Result<Vec<_>>>()?;
+    let root_id = doc.catalog()?.get(b"Pages")?.as_reference()?;
+    let transform = content.transform.clone().unwrap_or_default();
+
+    let mut media_box = screen_media_box();
+    let mut used = HashSet::new();
+    let mut kids = Vec::new();
+    for (page, redirection) in pages.iter().zip(content.page_redirections()) {
+        let original = redirection.and_then(|r| originals.get(r));
+        let original_id = original.map(|(id, _)| *id);
+        let mut dict = match original {
+            Some((_, dict)) => dict.clone(),
+            None => dictionary! {
+                "Type" => Object::Name(b"Page".to_vec()),
+                "MediaBox" => media_box.iter().map(|v| Object::Real(*v)).collect::<Vec<_>>(),
+                "Resources" => Dictionary::new(),
+            },
+        };
+        media_box = page_box(&doc, &dict, b"MediaBox")?;
+        let visible_box = page_box(&doc, &dict, b"CropBox").unwrap_or(media_box);
+        let matrix = screen_matrix(&visible_box, page_rotation(&doc, &dict)?);
+
+        let (strokes, opacities) = page_strokes(page, &matrix, &transform);
+        if !strokes.is_empty() {
+            add_opacities(&doc, &mut dict, &opacities)?;
+            let mut contents = vec![Object::Reference(
+                doc.add_object(Stream::new(Dictionary::new(), b"q\n".to_vec())),
+            )];
+            match dict.get(b"Contents") {
+                Ok(Object::Array(streams)) => contents.extend(streams.iter().cloned()),
+                Ok(stream) => contents.push(stream.clone()),
+                Err(_) => (),
+            }
+            contents.push(Object::Reference(
+                doc.add_object(Stream::new(Dictionary::new(), strokes.into_bytes())),
+            ));
+            dict.set("Contents", contents);
+        }
+        dict.set("Parent", root_id);
+
+        // A page can only appear once in the page tree, so copies need an object of their own.
+        let page_id = match original_id {
+            Some(id) if used.insert(id) => {
+                *doc.get_object_mut(id)? = Object::Dictionary(dict);
+                id
+            }
+            _ => doc.add_object(dict),
+        };
+        kids.push(Object::Reference(page_id));
+    }
+
+    let root = doc.get_object_mut(root_id)?.as_dict_mut()?;
+    root.set("Count", kids.len() as i64);
+    root.set("Kids", kids);
+
+    doc.prune_objects();
+    doc.compress();
+    let mut pdf = Vec::new();
+    doc.save_to(&mut pdf)?;
+    Ok(pdf)
+}
+
+/// The size of the device screen, in points.
+fn screen_media_box() -> [f64; 4] {
+    let scale = POINTS_PER_INCH / f64::from(SCREEN_DPI);
+    [
+        0.0,
+        0.0,
+        f64::from(SCREEN_WIDTH) * scale,
+        f64::from(SCREEN_HEIGHT) * scale,
+    ]
+}
+
+/// Copies the page dictionary at `id`, including any attributes it inherits.
+fn flattened_page(doc: &Document, id: ObjectId) -> Result<Dictionary> {
+    let mut dict = doc.get_dictionary(id)?.clone();
+    let mut parent = dict.get(b"Parent").and_then(Object::as_reference).ok();
+    // The visited set guards against malformed files whose page tree has a cycle.
+    let mut visited = BTreeSet::new();
+    while let Some(parent_id) = parent.filter(|p| visited.insert(*p)) {
+        let parent_dict = doc.get_dictionary(parent_id)?;
+        for key in INHERITABLE.iter() {
+            if !dict.has(key) {
+                if let Ok(value) = parent_dict.get(key) {
+                    dict.set(key.to_vec(), value.clone());
+                }
+            }
+        }
+        parent = parent_dict
+            .get(b"Parent")
+            .and_then(Object::as_reference)
+            .ok();
+    }
+    Ok(dict)
+}
+
+/// Reads the rectangle `key` of a page as `[left, bottom, right, top]`. Rectangles without an
+/// area are errors, since nothing could be drawn on them.
+fn page_box(doc: &Document, dict: &Dictionary, key: &[u8]) -> Result<[f64; 4]> {
+    let bad_box =
+        || Error::RenderError(format!("bad {} in PDF page", String::from_utf8_lossy(key)));
+    let (_, rect) = doc.dereference(dict.get(key)?)?;
+    let mut values = [0.0; 4];
+    let items = rect.as_array()?;
+    if items.len() != 4 {
+        return Err(bad_box());
+    }
+    for (value, item) in values.iter_mut().zip(items) {
+        *value = match doc.dereference(item)?.1 {
+            Object::Integer(i) => *i as f64,
+            Object::Real(r) => *r,
+            _ => return Err(lopdf::Error::Type.into()),
+        };
+    }
+    let [x0, y0, x1, y1] = values;
+    let (width, height) = ((x1 - x0).abs(), (y1 - y0).abs());
+    if !(width > 0.0 && height > 0.0 && width.is_finite() && height.is_finite()) {
+        return Err(bad_box());
+    }
+    Ok([x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)])
+}
+
+/// Reads the `Rotate` of a page: how far it is turned clockwise when shown, as 0, 90, 180, or
+/// 270 degrees.
+fn page_rotation(doc: &Document, dict: &Dictionary) -> Result<i64> {
+    let rotate = match dict.get(b"Rotate") {
+        Ok(rotate) => doc.dereference(rotate)?.1.as_i64()?,
+        Err(_) => 0,
+    };
+    if rotate % 90 != 0 {
+        return Err(Error::RenderError(format!(
+            "bad Rotate {} in PDF page",
+            rotate
+        )));
+    }
+    Ok(rotate.rem_euclid(360))
+}
+
+/// The matrix taking screen pixels to the user space of a page with the visible box
+/// `visible_box`, turned `rotation` degrees when shown.
+///
+/// The device fits the page, as shown, to the screen, centered horizontally and aligned to the
+/// top.
+fn screen_matrix(visible_box: &[f64; 4], rotation: i64) -> [f64; 6] {
+    let [x0, y0, x1, y1] = *visible_box;
+    let (width, height) = match rotation {
+        90 | 270 => (y1 - y0, x1 - x0),
+        _ => (x1 - x0, y1 - y0),
+    };
+    let scale = (f64::from(SCREEN_WIDTH) / width).min(f64::from(SCREEN_HEIGHT) / height);
+    let k = 1.0 / scale;
+    // The left margin on the screen, in points.
+    let margin = (f64::from(SCREEN_WIDTH) / scale - width) / 2.0;
+
+    // Each maps the top left of the screen to the corner of the page shown there, with the
+    // screen's x and y along the page's edges from that corner.
+    match rotation {
+        90 => [0.0, k, k, 0.0, x0, y0 - margin],
+        180 => [-k, 0.0, 0.0, k, x1 + margin, y0],
+        270 => [0.0, -k, -k, 0.0, x1, y1 + margin],
+        _ => [k, 0.0, 0.0, -k, x0 - margin, y1],
+    }
+}
+
+/// Writes the strokes of `page` as a content stream that closes the `q` written before the
+/// original content. Also returns the stroke opacities used, in percent.
+fn page_strokes(page: &Page, matrix: &[f64; 6], transform: &Transform) -> (String, BTreeSet<u32>) {
+    let mut ops = String::default();
+    let mut opacities = BTreeSet::new();
+    let strokes = page
+        .strokes()
+        .filter_map(|s| style(s).map(|style| (s, style)))
+        .filter(|(s, _)| s.points.len() > 1)
+        .collect::<Vec<_>>();
+    if strokes.is_empty() {
+        return (ops, opacities);
+    }
+
+    // unwrap: writing to a String can't fail.
+    writeln!(ops, "Q\nq").unwrap();
+    let [a, b, c, d, e, f] = *matrix;
+    writeln!(
+        ops,
+        "{:.6} {:.6} {:.6} {:.6} {:.4} {:.4} cm",
+        a, b, c, d, e, f
+    )
+    .unwrap();
+    writeln!(
+        ops,
+        "{} {} {} {} {} {} cm",
+        transform.m11, transform.m12, transform.m21, transform.m22, transform.m31, transform.m32
+    )
+    .unwrap();
+    writeln!(ops, "1 J 1 j").unwrap();
+    for (stroke, style) in strokes {
+        write_pdf_stroke(&mut ops, &mut opacities, stroke, &style);
+    }
+    writeln!(ops, "Q").unwrap();
+    (ops, opacities)
+}
+
+fn write_pdf_stroke(
+    ops: &mut String,
+    opacities: &mut BTreeSet<u32>,
+    stroke: &Stroke,
+    style: &Style,
+) {
+    let (r, g, b) = style.rgb;
+    writeln!(
+        ops,
+        "{:.3} {:.3} {:.3} RG",
+        f32::from(r) / 255.0,
+        f32::from(g) / 255.0,
+        f32::from(b) / 255.0
+    )
+    .unwrap();
+
+    let mut set_opacity = |ops: &mut String, opacity: f32| {
+        let percent = (opacity * 100.0).round() as u32;
+        opacities.insert(percent);
+        writeln!(ops, "/{} gs", opacity_name(percent)).unwrap();
+    };
+
+    if style.whole {
+        set_opacity(ops, style.opacity);
+        writeln!(ops, "{:.2} w", average_width(stroke)).unwrap();
+        writeln!(ops, "{:.2} {:.2} m", stroke.points[0].x, stroke.points[0].y).unwrap();
+        for point in &stroke.points[1..] {
+            writeln!(ops, "{:.2} {:.2} l", point.x, point.y).unwrap();
+        }
+        writeln!(ops, "S").unwrap();
+    } else {
+        for pair in stroke.points.windows(2) {
+            set_opacity(ops, point_opacity(stroke, style, &pair[1]));
+            writeln!(
+                ops,
+                "{:.2} w {:.2} {:.2} m {:.2} {:.2} l S",
+                point_width(stroke, &pair[1]),
+                pair[0].x,
+                pair[0].y,
+                pair[1].x,
+                pair[1].y
+            )
+            .unwrap();
+        }
+    }
+}
+
+fn opacity_name(percent: u32) -> String {
+    format!("RmOpacity{}", percent)
+}
+
+/// Adds a graphics state for each opacity in `opacities` to the resources of the page.
+fn add_opacities(doc: &Document, dict: &mut Dictionary, opacities: &BTreeSet<u32>) -> Result<()> {
+    // The resources may be shared with other pages, so the page gets its own copy.
+    let mut resources = match dict.get(b"Resources") {
+        Ok(resources) => doc.dereference(resources)?.1.as_dict()?.clone(),
+        Err(_) => Dictionary::new(),
+    };
+    let mut states = match resources.get(b"ExtGState") {
+        Ok(states) => doc.dereference(states)?.1.as_dict()?.clone(),
+        Err(_) => Dictionary::new(),
+    };
+    for percent in opacities {
+        let opacity = *percent as f32 / 100.0;
+        states.set(
+            opacity_name(*percent),
+            dictionary! {
+                "Type" => Object::Name(b"ExtGState".to_vec()),
+                "CA" => opacity,
+                "ca" => opacity,
+            },
+        );
+    }
+    resources.set("ExtGState", states);
+    dict.set("Resources", resources);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remarkable::lines::{Color, Layer, Pen, Point};
+
+    // A PDF with a portrait page, and a landscape page that is turned to be shown upright.
+    fn original_pdf(second_box: [i64; 4]) -> Vec<u8> {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let mut kids = Vec::new();
+        for (i, (media_box, rotate)) in [([0, 0, 612, 792], 0), (second_box, 90)].iter().enumerate()
+        {
+            let content = Stream::new(Dictionary::new(), format!("% page {}\n", i).into_bytes());
+            let content_id = doc.add_object(content);
+            let page_id = doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "MediaBox" => media_box.iter().map(|v| Object::Integer(*v)).collect::<Vec<_>>(),
+                "Rotate" => *rotate,
+                "Contents" => content_id,
+            });
+            kids.push(Object::Reference(page_id));
+        }
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => kids,
+                "Count" => 2,
+            }),
+        );
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        doc.trailer.set("Root", catalog_id);
+        let mut pdf = Vec::new();
+        doc.save_to(&mut pdf).unwrap();
+        pdf
+    }
+
+    fn page_with_a_stroke() -> Page {
+        let point = |x, y| Point {
+            x,
+            y,
+            speed: 0.0,
+            direction: 0.0,
+            width: 2.0,
+            pressure: 1.0,
+        };
+        Page {
+            version: 6,
+            layers: vec![Layer {
+                name: None,
+                strokes: vec![Stroke {
+                    pen: Pen::Fineliner,
+                    color: Color::Black,
+                    width: 2.0,
+                    points: vec![point(300.0, 300.0), point(600.0, 300.0)],
+                }],
+            }],
+        }
+    }
+
+    // Shows the second page, then an inserted page, then the first page.
+    fn content() -> Content {
+        serde_json::from_str(
+            r#"{
+                "fileType": "pdf",
+                "originalPageCount": 2,
+                "pages": ["a", "b", "c"],
+                "redirectionPageMap": [1, -1, 0]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn apply(matrix: &[f64; 6], (x, y): (f64, f64)) -> (f64, f64) {
+        let [a, b, c, d, e, f] = *matrix;
+        (a * x + c * y + e, b * x + d * y + f)
+    }
+
+    fn assert_near((x1, y1): (f64, f64), (x2, y2): (f64, f64)) {
+        assert!(
+            (x1 - x2).abs() < 1e-9 && (y1 - y2).abs() < 1e-9,
+            "({}, {}) != ({}, {})",
+            x1,
+            y1,
+            x2,
+            y2
+        );
+    }
+
+    #[test]
+    fn maps_the_screen_onto_turned_pages() {
+        // A 200x100 page. Upright, it fills the width of the screen. On its side, it fills the
+        // height, 936 pixels wide with 234 pixels on either side.
+        let visible_box = [10.0, 20.0, 210.0, 120.0];
+        let cases = [
+            (0, (0.0, 0.0), (10.0, 120.0), (1404.0, 702.0), (210.0, 20.0)),
+            (
+                90,
+                (234.0, 0.0),
+                (10.0, 20.0),
+                (1170.0, 1872.0),
+                (210.0, 120.0),
+            ),
+            (
+                180,
+                (0.0, 0.0),
+                (210.0, 20.0),
+                (1404.0, 702.0),
+                (10.0, 120.0),
+            ),
+            (
+                270,
+                (234.0, 0.0),
+                (210.0, 120.0),
+                (1170.0, 1872.0),
+                (10.0, 20.0),
+            ),
+        ];
+        for (rotation, top_left, top_left_corner, bottom_right, bottom_right_corner) in &cases {
+            let matrix = screen_matrix(&visible_box, *rotation);
+            assert_near(apply(&matrix, *top_left), *top_left_corner);
+            assert_near(apply(&matrix, *bottom_right), *bottom_right_corner);
+        }
+    }
+
+    #[test]
+    fn reads_rotations() {
+        let doc = Document::new();
+        let rotation = |rotate: i64| page_rotation(&doc, &dictionary! { "Rotate" => rotate });
+        assert_eq!(rotation(-90).unwrap(), 270);
+        assert_eq!(rotation(450).unwrap(), 90);
+        assert_eq!(page_rotation(&doc, &Dictionary::new()).unwrap(), 0);
+        assert!(matches!(rotation(45), Err(Error::RenderError(_))));
+    }
+
+    #[test]
+    fn follows_the_pages_of_the_content() {
+        let pages = vec![page_with_a_stroke(), Page::default(), Page::default()];
+        let pdf = overlay_pdf(&original_pdf([0, 0, 200, 100]), &content(), &pages).unwrap();
+
+        let doc = Document::load_mem(&pdf).unwrap();
+        let page_ids = doc.get_pages().into_values().collect::<Vec<_>>();
+        assert_eq!(page_ids.len(), 3);
+        let page = |i: usize| flattened_page(&doc, page_ids[i]).unwrap();
+        let text =
+            |i: usize| String::from_utf8(doc.get_page_content(page_ids[i]).unwrap()).unwrap();
+
+        // The second page, with the stroke drawn across the turned page.
+        assert_eq!(
+            page_box(&doc, &page(0), b"MediaBox").unwrap(),
+            [0.0, 0.0, 200.0, 100.0]
+        );
+        assert_eq!(page_rotation(&doc, &page(0)).unwrap(), 90);
+        assert!(text(0).contains("% page 1"));
+        assert!(text(0).contains("0.000000 0.106838 0.106838 0.000000 0.0000 -25.0000 cm"));
+        assert!(text(0).contains("2.00 w 300.00 300.00 m 600.00 300.00 l S"));
+
+        // The inserted page is blank, and the size of the page before it.
+        assert_eq!(
+            page_box(&doc, &page(1), b"MediaBox").unwrap(),
+            [0.0, 0.0, 200.0, 100.0]
+        );
+        assert_eq!(text(1), "");
+
+        // The first page, without strokes.
+        assert_eq!(
+            page_box(&doc, &page(2), b"MediaBox").unwrap(),
+            [0.0, 0.0, 612.0, 792.0]
+        );
+        assert_eq!(text(2), "% page 0\n");
+    }
+
+    #[test]
+    fn pages_without_an_area_are_errors() {
+        let pages = vec![page_with_a_stroke(), Page::default(), Page::default()];
+        match overlay_pdf(&original_pdf([0, 0, 200, 0]), &content(), &pages) {
+            Err(Error::RenderError(msg)) => assert_eq!(msg, "bad MediaBox in PDF page"),
+            result => panic!("expected RenderError, got {:?}", result.map(|_| ())),
+        }
+    }
+}
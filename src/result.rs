@@ -1,3 +1,5 @@
+use ssh2::ErrorCode;
+use std::io::ErrorKind;
 use std::path::PathBuf;
 use thiserror::Error;
 
@@ -6,35 +8,103 @@ pub enum Error {
     #[error("Alpha value, {0}, out of range, [0..100]")]
     AlphaRangeError(u8),
 
+    #[error("The path, {0}, matches more than one item: {1}")]
+    AmbiguousPath(String, String),
+
     #[error("Bad combination of arguments: {0}")]
     BadArgsError(String),
 
+    #[error("The command, {0}, failed on the Remarkable: {1}")]
+    CommandFailed(String, String),
+
+    #[error("The directory, '{0}', does not exist.")]
+    DirNotFound(PathBuf),
+
+    #[error("The document, {0}, was not found on the Remarkable")]
+    DocumentNotFound(String),
+
+    #[error("Bad EPUB file: {0}")]
+    EpubFormatError(String),
+
     #[error("The file at {0} failed to load")]
     FileFailedToLoad(PathBuf),
 
+    #[error("The folder hierarchy has a cycle at {0}")]
+    FolderCycle(String),
+
     #[error("The folder, {0}, was not found on the Remarkable")]
     FolderNotFound(String),
 
+    #[error("Bad .rm lines file: {0}")]
+    LinesFormatError(String),
+
     #[error("The mount point, '{0}', already exists.")]
     MountPointExistsErr(PathBuf),
 
+    #[error("The path, {0}, already exists on the Remarkable")]
+    PathExists(String),
+
+    #[error("The path, {0}, was not found on the Remarkable")]
+    PathNotFound(String),
+
+    #[error("Failed to render: {0}")]
+    RenderError(String),
+
+    #[error("SSH authentication failed: {0}")]
+    SshAuthError(String),
+
+    #[error("The template, {0}, is already installed on the Remarkable")]
+    TemplateExists(String),
+
+    #[error("The template, {0}, is not installed on the Remarkable")]
+    TemplateNotFound(String),
+
+    #[error("Unsupported operation: {0}")]
+    UnsupportedOperation(String),
+
     #[error("ImageError: {0}")]
     ImageError(#[from] printpdf::image::ImageError),
 
     #[error("IOError: {0}")]
     IOError(#[from] std::io::Error),
 
+    #[error("LoPdfError: {0}")]
+    LoPdfError(#[from] lopdf::Error),
+
     #[error("PrintPdfError: {0}")]
     PrintPdfError(#[from] printpdf::Error),
 
     #[error("SerdeJsonError: {0}")]
     SerdeJsonError(#[from] serde_json::Error),
 
+    #[error("SshError: {0}")]
+    SshError(#[from] ssh2::Error),
+
     #[error("StripPrefixError: {0}")]
     StripPrefixError(#[from] std::path::StripPrefixError),
 
     #[error("WalkDirError: {0}")]
     WalkDirError(#[from] walkdir::Error),
+
+    #[error("XmlError: {0}")]
+    XmlError(#[from] roxmltree::Error),
+
+    #[error("ZipError: {0}")]
+    ZipError(#[from] zip::result::ZipError),
+}
+
+impl Error {
+    /// Whether the error says that a file or directory doesn't exist, from whichever backend.
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            Error::IOError(err) => err.kind() == ErrorKind::NotFound,
+            // LIBSSH2_FX_NO_SUCH_FILE, from SFTP.
+            Error::SshError(err) => matches!(err.code(), ErrorCode::SFTP(2)),
+            // From `cat` and the like, run over ssh.
+            Error::CommandFailed(_, stderr) => stderr.contains("No such file or directory"),
+            _ => false,
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -1,4 +1,13 @@
 pub mod copier;
+pub mod empty_trash;
+pub mod export;
+pub mod import;
 pub mod ipdf;
 pub mod ls;
+pub mod mkdir;
+pub mod mv;
+pub mod pull;
 pub mod restart;
+pub mod restore;
+pub mod rm;
+pub mod template;
@@ -1,4 +1,4 @@
-use crate::remarkable::Connection;
+use crate::remarkable::{Backend, Connection};
 use crate::Result;
 use argh::FromArgs;
 use log::{info, trace};
@@ -16,13 +16,13 @@ pub struct CopierArgs {
     #[argh(positional)]
     src: PathBuf,
 
-    /// the destination directory
+    /// the destination directory, relative to the data directory
     #[argh(positional)]
     dest: Option<PathBuf>,
 }
 
-pub fn copy(conn: &Connection, args: CopierArgs) -> Result<()> {
-    copy_fn(conn, args.src, args.dest)?;
+pub fn copy(conn: &Connection, args: &CopierArgs) -> Result<()> {
+    copy_fn(conn, &args.src, args.dest.as_ref())?;
 
     if args.restart {
         conn.restart()?;
@@ -35,18 +35,16 @@ pub fn copy_fn(
     src: impl AsRef<Path>,
     dst: Option<impl AsRef<Path>>,
 ) -> Result<()> {
-    // TODO: can we avoid this call unless necessary?
-    let data_dir = conn.data_dir();
     let dst = dst
         .as_ref()
         .map(|d| d.as_ref())
-        .unwrap_or_else(|| data_dir.as_path());
+        .unwrap_or_else(|| Path::new(""));
 
-    copy_helper(src, dst)?;
+    copy_helper(conn.backend(), src, dst)?;
     Ok(())
 }
 
-fn copy_helper(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<()> {
+fn copy_helper(backend: &dyn Backend, src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<()> {
     info!("Copying from {:?} to {:?}", src.as_ref(), dst.as_ref());
 
     let mut files = Vec::default();
@@ -58,13 +56,7 @@ fn copy_helper(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<()> {
 
         trace!("copying {:?} ==> {:?}", path, dest_filename);
 
-        if let Some(dest_parent) = dest_filename.parent() {
-            if !dest_parent.exists() {
-                std::fs::create_dir_all(dest_parent)?;
-            }
-        }
-
-        std::fs::copy(path, dest_filename)?;
+        backend.write(&dest_filename, &std::fs::read(path)?)?;
     }
 
     Ok(())
@@ -0,0 +1,37 @@
+use super::rm::purge;
+use crate::remarkable::Connection;
+use crate::{Result, TRASH_ID};
+use argh::FromArgs;
+
+#[derive(FromArgs, Debug)]
+/// permanently delete everything in the trash
+#[argh(subcommand, name = "empty-trash")]
+pub struct EmptyTrashArgs {
+    /// don't ask for confirmation before deleting.
+    #[argh(switch, short = 'y')]
+    yes: bool,
+
+    /// if present, restart the Remarkable app before quitting.
+    #[argh(switch, short = 'r')]
+    restart: bool,
+}
+
+pub fn empty_trash(conn: &Connection, args: &EmptyTrashArgs) -> Result<()> {
+    let tree = conn.tree()?;
+    let ids = tree
+        .children(TRASH_ID)
+        .map(|n| n.id().to_string())
+        .collect::<Vec<_>>();
+    if ids.is_empty() {
+        println!("The trash is empty.");
+        return Ok(());
+    }
+
+    if purge(conn, &tree, &ids, args.yes)? {
+        conn.refresh();
+        if args.restart {
+            conn.restart()?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,202 @@
+use crate::remarkable::lines::{Page, LINES_EXTENSION};
+use crate::remarkable::{Connection, Content, Metadata, Pagedata, DEFAULT_TEMPLATE, TEMPLATES_DIR};
+use crate::render::{overlay_pdf, page_to_image, page_to_svg, SCREEN_DPI};
+use crate::{Error, Result};
+use argh::FromArgs;
+use log::{info, trace, warn};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_DEST_DIR: &str = "./export";
+const PDF_EXTENSION: &str = "pdf";
+
+#[derive(FromArgs, Debug)]
+/// export the pages of a document as SVG and PNG files, or as an annotated PDF
+#[argh(subcommand, name = "export")]
+pub struct ExportArgs {
+    /// resolution of the exported pages, in dots per inch. Defaults to the screen's 226.
+    #[argh(option, default = "SCREEN_DPI")]
+    dpi: f32,
+
+    /// write SVG files. If none of --svg, --png, or --pdf is present, SVG and PNG are written.
+    #[argh(switch)]
+    svg: bool,
+
+    /// write PNG files. If none of --svg, --png, or --pdf is present, SVG and PNG are written.
+    #[argh(switch)]
+    png: bool,
+
+    /// write a copy of the document's PDF with the strokes drawn over its pages.
+    #[argh(switch)]
+    pdf: bool,
+
+    /// if present, draw each page's template behind the strokes.
+    #[argh(switch, short = 't')]
+    template: bool,
+
+    /// local directory holding the template images. If missing, they are read from the device.
+    #[argh(option)]
+    template_dir: Option<PathBuf>,
+
+    /// directory for output files
+    #[argh(option, short = 'o', default = "DEFAULT_DEST_DIR.into()")]
+    dest_dir: PathBuf,
+
+    /// path (e.g., Work/Papers/foo), name, or id of the document to export
+    #[argh(positional)]
+    document: String,
+}
+
+impl ExportArgs {
+    fn write_svg(&self) -> bool {
+        self.svg || !(self.png || self.pdf)
+    }
+
+    fn write_png(&self) -> bool {
+        self.png || !(self.svg || self.pdf)
+    }
+}
+
+pub fn export(conn: &Connection, args: &ExportArgs) -> Result<()> {
+    let doc_id = conn.find_document(&args.document)?;
+    let backend = conn.backend();
+    let metadata = Metadata::load(backend, &doc_id)?;
+    let content = Content::load(backend, &doc_id)?;
+    // Newer documents don't have pagedata; their templates are in the content.
+    let pagedata = Pagedata::load(backend, &doc_id).unwrap_or_default();
+    let stroke_files = stroke_files(conn, &doc_id)?;
+    if args.pdf && content.file_type.as_deref() != Some(PDF_EXTENSION) {
+        return Err(Error::UnsupportedOperation(format!(
+            "exporting a '{}' document as PDF",
+            content.file_type.as_deref().unwrap_or_default()
+        )));
+    }
+
+    if !args.dest_dir.exists() {
+        std::fs::create_dir_all(&args.dest_dir)?;
+    }
+
+    let base_name = metadata.visible_name.replace('/', "_");
+    let mut templates = TemplateCache::new(conn, args.template_dir.as_deref());
+    info!(
+        "exporting {} pages of '{}'",
+        content.page_ids().len(),
+        metadata.visible_name
+    );
+    let mut pages = Vec::default();
+    for (i, page_id) in content.page_ids().into_iter().enumerate() {
+        trace!("exporting page {}: {}", i + 1, page_id);
+        let page = if stroke_files.contains(page_id) {
+            Page::load(backend, &doc_id, page_id)?
+        } else {
+            // Pages that have never been written on have no strokes file.
+            Page::default()
+        };
+
+        let background = if args.template {
+            let template = content
+                .page_template(page_id)
+                .or_else(|| pagedata.templates().get(i).map(|t| t.as_str()));
+            match template {
+                Some(template) => templates.get(template),
+                None => None,
+            }
+        } else {
+            None
+        };
+
+        let out_base = format!("{}-{:03}", base_name, i + 1);
+        if args.write_svg() {
+            let svg = page_to_svg(&page, args.dpi, background);
+            std::fs::write(args.dest_dir.join(format!("{}.svg", out_base)), svg)?;
+        }
+        if args.write_png() {
+            let background_image = background
+                .map(printpdf::image::load_from_memory)
+                .transpose()?;
+            let image = page_to_image(&page, args.dpi, background_image.as_ref())?;
+            let mut file = BufWriter::new(File::create(
+                args.dest_dir.join(format!("{}.png", out_base)),
+            )?);
+            image.write_to(&mut file, printpdf::image::ImageOutputFormat::Png)?;
+        }
+        if args.pdf {
+            pages.push(page);
+        }
+    }
+
+    if args.pdf {
+        let original = backend.read(&Path::new(&doc_id).with_extension(PDF_EXTENSION))?;
+        let pdf = overlay_pdf(&original, &content, &pages)?;
+        std::fs::write(args.dest_dir.join(format!("{}.pdf", base_name)), pdf)?;
+    }
+
+    Ok(())
+}
+
+/// The page ids with a strokes file in the document's directory.
+fn stroke_files(conn: &Connection, doc_id: &str) -> Result<HashSet<String>> {
+    // A document that has never been written on may not have a directory.
+    let entries = match conn.backend().list(Path::new(doc_id)) {
+        Err(err) if err.is_not_found() => Vec::default(),
+        result => result?,
+    };
+    Ok(entries
+        .into_iter()
+        .filter(|e| e.path.extension().is_some_and(|ext| ext == LINES_EXTENSION))
+        .filter_map(|e| {
+            e.path
+                .file_stem()
+                .map(|fs| fs.to_string_lossy().into_owned())
+        })
+        .collect())
+}
+
+/// Loads template images on demand, reading each one only once.
+struct TemplateCache<'a> {
+    conn: &'a Connection,
+    template_dir: Option<&'a Path>,
+    images: HashMap<String, Option<Vec<u8>>>,
+}
+
+impl<'a> TemplateCache<'a> {
+    fn new(conn: &'a Connection, template_dir: Option<&'a Path>) -> TemplateCache<'a> {
+        TemplateCache {
+            conn,
+            template_dir,
+            images: Default::default(),
+        }
+    }
+
+    /// Returns the PNG data for `name`, or `None` if it's blank or can't be loaded.
+    fn get(&mut self, name: &str) -> Option<&[u8]> {
+        if name == DEFAULT_TEMPLATE {
+            return None;
+        }
+        let conn = self.conn;
+        let template_dir = self.template_dir;
+        self.images
+            .entry(name.to_string())
+            .or_insert_with(|| match load_template(conn, template_dir, name) {
+                Ok(data) => Some(data),
+                Err(err) => {
+                    warn!("couldn't load template '{}': {}", name, err);
+                    None
+                }
+            })
+            .as_deref()
+    }
+}
+
+fn load_template(conn: &Connection, template_dir: Option<&Path>, name: &str) -> Result<Vec<u8>> {
+    let file_name = Path::new(name).with_extension("png");
+    match template_dir {
+        Some(dir) => Ok(std::fs::read(dir.join(file_name))?),
+        None => {
+            let path = Path::new(TEMPLATES_DIR).join(file_name);
+            conn.backend().read_system_file(&path)
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use crate::epub::Epub;
+use crate::remarkable::{create_bare_fs, new_uuid, Connection, LocalBackend, TemplateIndex};
+use crate::{Content, Error, Metadata, PageEditor, Pagedata, Result, DEFAULT_TEMPLATE};
+use argh::FromArgs;
+use log::{error, info};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_DEST_DIR: &str = "./rem";
+const EPUB_EXTENSION: &str = "epub";
+const PDF_EXTENSION: &str = "pdf";
+const TEXT_ALIGNMENTS: &[&str] = &["justify", "left"];
+
+#[derive(FromArgs, Debug)]
+/// import PDF and EPUB files as Remarkable documents
+#[argh(subcommand, name = "import")]
+pub struct ImportArgs {
+    /// files to import
+    #[argh(positional)]
+    file_names: Vec<PathBuf>,
+
+    /// if present, the documents will be put in this folder on the Remarkable, given as a path
+    /// of visible names (e.g., Work/Papers)
+    #[argh(option, short = 'p')]
+    parent: Option<String>,
+
+    /// directory for output files
+    #[argh(option, short = 'o', default = "DEFAULT_DEST_DIR.into()")]
+    dest_dir: PathBuf,
+
+    /// the font for reflowed text (e.g., "EB Garamond" or "Noto Sans")
+    #[argh(option)]
+    font_name: Option<String>,
+
+    /// the line spacing for reflowed text, in percent (e.g., 100, 150, or 200)
+    #[argh(option)]
+    line_height: Option<i32>,
+
+    /// the page margins, in pixels
+    #[argh(option)]
+    margins: Option<u32>,
+
+    /// the alignment of reflowed text: left or justify
+    #[argh(option)]
+    text_alignment: Option<String>,
+
+    /// the size of reflowed text, relative to the normal size (e.g., 1.2)
+    #[argh(option)]
+    text_scale: Option<f64>,
+
+    /// if present, copy all of the files to the Remarkable device.
+    #[argh(switch, short = 'c')]
+    copy: bool,
+
+    /// if present, restart the Remarkable app before quitting.
+    #[argh(switch, short = 'r')]
+    restart: bool,
+}
+
+impl ImportArgs {
+    /// Sets the layout options that were given on `content`, leaving the rest as they are.
+    fn apply_layout(&self, content: &mut Content) {
+        if let Some(font_name) = &self.font_name {
+            content.font_name = Some(font_name.clone());
+        }
+        if let Some(line_height) = self.line_height {
+            content.line_height = Some(line_height);
+        }
+        if let Some(margins) = self.margins {
+            content.margins = Some(margins);
+        }
+        if let Some(text_alignment) = &self.text_alignment {
+            content.text_alignment = Some(text_alignment.clone());
+        }
+        if let Some(text_scale) = self.text_scale {
+            content.text_scale = Some(text_scale);
+        }
+    }
+}
+
+pub fn import(conn: &Connection, args: &ImportArgs) -> Result<()> {
+    if let Some(alignment) = &args.text_alignment {
+        if !TEXT_ALIGNMENTS.contains(&alignment.as_str()) {
+            return Err(Error::BadArgsError(format!(
+                "text alignment must be one of {}",
+                TEXT_ALIGNMENTS.join(", ")
+            )));
+        }
+    }
+    if args.text_scale.is_some_and(|scale| scale <= 0.0) {
+        return Err(Error::BadArgsError(
+            "text scale must be greater than zero".to_string(),
+        ));
+    }
+
+    let parent_id = args
+        .parent
+        .as_ref()
+        .map(|p| conn.find_folder(p))
+        .transpose()?;
+    let templates = super::template::installed_templates(conn)?;
+
+    if !args.dest_dir.exists() {
+        std::fs::create_dir_all(&args.dest_dir)?;
+    }
+    info!("importing {} files", args.file_names.len());
+    for file_name in &args.file_names {
+        if let Err(err) = import_file(file_name, parent_id.as_deref(), templates.as_ref(), args) {
+            error!("{:?}: {}", file_name, err);
+        }
+    }
+
+    if args.copy {
+        use super::copier;
+        info!("Copying imported files to Remarkable device.");
+        copier::copy_fn(conn, &args.dest_dir, None::<&Path>)?;
+    }
+    if args.restart {
+        conn.restart()?;
+    }
+    Ok(())
+}
+
+fn import_file(
+    path: &Path,
+    parent: Option<&str>,
+    templates: Option<&TemplateIndex>,
+    args: &ImportArgs,
+) -> Result<()> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let (mut content, pagedata, name) = match extension.as_str() {
+        EPUB_EXTENSION => epub_content(path)?,
+        PDF_EXTENSION => pdf_content(path)?,
+        _ => {
+            return Err(Error::UnsupportedOperation(format!(
+                "importing '.{}' files",
+                extension
+            )))
+        }
+    };
+    args.apply_layout(&mut content);
+    if let Some(templates) = templates {
+        pagedata.validate(templates)?;
+    }
+
+    let dest_dir = &args.dest_dir;
+    let id = new_uuid();
+    info!("importing {:?} as '{}' ({})", path, name, id);
+    create_bare_fs(&id, dest_dir)?;
+    std::fs::copy(path, dest_dir.join(&id).with_extension(&extension))?;
+
+    let backend = LocalBackend::new(dest_dir);
+    content.save(&backend, &id)?;
+    pagedata.save(&backend, &id)?;
+    Metadata::with_name_and_parent(name, parent.unwrap_or_default()).save(&backend, &id)?;
+    Ok(())
+}
+
+/// The content and pagedata for the EPUB at `path`, and its title.
+///
+/// The Remarkable lays out the pages itself when the book is first opened, so there are none yet.
+fn epub_content(path: &Path) -> Result<(Content, Pagedata, String)> {
+    let title = Epub::open(path)?.title()?;
+    let content = Content {
+        file_type: Some(EPUB_EXTENSION.into()),
+        ..Default::default()
+    };
+    let title = title.unwrap_or_else(|| file_stem(path));
+    Ok((content, Pagedata::default(), title))
+}
+
+/// The content and pagedata for the PDF at `path`, with a new page for each of its pages, and
+/// its title.
+fn pdf_content(path: &Path) -> Result<(Content, Pagedata, String)> {
+    let pdf = lopdf::Document::load(path)?;
+    let page_count = pdf.get_pages().len();
+    if page_count == 0 {
+        return Err(Error::FileFailedToLoad(path.to_path_buf()));
+    }
+
+    let mut content = Content {
+        file_type: Some(PDF_EXTENSION.into()),
+        ..Default::default()
+    };
+    let mut pagedata = Pagedata::default();
+    let mut editor = PageEditor::new(&mut content, &mut pagedata);
+    for _ in 0..page_count {
+        editor.add_page(new_uuid(), DEFAULT_TEMPLATE);
+    }
+    // Each page shows the page of the PDF with the same number.
+    content.original_page_count = Some(page_count as i32);
+    content.redirection_page_map = Some((0..page_count as i32).collect());
+    Ok((content, pagedata, file_stem(path)))
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|f| f.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "<null>".to_string())
+}
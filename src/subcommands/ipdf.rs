@@ -1,19 +1,26 @@
-use crate::imgtools::{process_image, ColorTransform};
+use crate::imgtools::{
+    layout_image, parse_steps, process_image, split_image, ColorTransform, Dither, Layout,
+    PageSize, Scaling, Step,
+};
 use crate::remarkable::Connection;
-use crate::remarkable::{create_bare_fs, Content, Metadata, METADATA_EXTENSION};
+use crate::remarkable::{
+    create_bare_fs, new_uuid, Content, LocalBackend, Metadata, PageEditor, Pagedata, TemplateIndex,
+    DEFAULT_TEMPLATE,
+};
 use crate::{Error, Result};
 use argh::FromArgs;
 use log::{error, info, trace};
+use printpdf::image::GenericImageView;
 use printpdf::*;
 use std::borrow::Cow;
-use std::ffi::OsString;
+use std::cmp::Ordering;
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::BufWriter;
 use std::path::{Path, PathBuf};
-use uuid::Uuid;
 
 const DEFAULT_DEST_DIR: &str = "./rem";
 const DPI: f64 = 300.0;
+const DEFAULT_OVERLAP: u8 = 5;
 
 #[derive(FromArgs, Debug)]
 /// convert images to Remarkable pdfs with a thumbnail
@@ -31,11 +38,63 @@ pub struct IPdfArgs {
     #[argh(switch, short = 'b')]
     to_bw: bool,
 
-    /// file names to convert to Remarkable FDF files
+    /// how to convert to black & white, implying -b: floyd-steinberg (the default),
+    /// threshold[=N] (darker than N, 128 by default, is black), otsu (a threshold picked from
+    /// the image), adaptive (for uneven scans), bayer, atkinson, or gray16 (the screen's 16
+    /// grays)
+    #[argh(option, short = 'd')]
+    dither: Option<Dither>,
+
+    /// clean up the images with these steps, in order, given as repeated options or separated
+    /// by commas: levels (stretch the contrast), gamma=G (lighten for G > 1, darken for G < 1),
+    /// unsharp[=SIGMA[:THRESHOLD]] (sharpen), despeckle, whiten (even out the paper), deskew,
+    /// page (flatten a photo of a sheet of paper), and crop (cut off borders)
+    #[argh(option, short = 'e')]
+    enhance: Vec<String>,
+
+    /// file names to convert to Remarkable FDF files. Directories are replaced by the images
+    /// in them, in natural order (page2 before page10).
     #[argh(positional)]
     file_names: Vec<String>,
 
-    /// if present, generated files will be put in the specified folder on the Remarkable
+    /// put each image on a page the size of the device's screen: remarkable (1404x1872) or
+    /// paper-pro (1620x2160). Without this, pages are the size of their images. Defaults to
+    /// remarkable if any of the other layout options are used.
+    #[argh(option)]
+    page_size: Option<PageSize>,
+
+    /// how images are scaled to the page: fit (all of the image is shown, the default), fill
+    /// (the page is covered, and the rest is cropped), or center (images are only shrunk)
+    #[argh(option)]
+    fit: Option<Scaling>,
+
+    /// white space to leave around the image on each side of the page, in pixels
+    #[argh(option)]
+    margin: Option<u32>,
+
+    /// turn landscape images a quarter turn so that they fill more of the page
+    #[argh(switch)]
+    rotate: bool,
+
+    /// cut images that are too tall (or too wide) for a page into page-sized pieces, one page
+    /// each, like a long screenshot or a receipt.
+    #[argh(switch)]
+    split: bool,
+
+    /// how much neighboring pieces from --split overlap, in percent of a page, range [0-50].
+    #[argh(option, default = "DEFAULT_OVERLAP")]
+    overlap: u8,
+
+    /// combine all of the images into a single document, one page per image.
+    #[argh(switch, short = 'm')]
+    combine: bool,
+
+    /// name of the combined document. Defaults to the name of the first file or directory.
+    #[argh(option, short = 'n')]
+    name: Option<String>,
+
+    /// if present, generated files will be put in this folder on the Remarkable, given as a path
+    /// of visible names (e.g., Work/Papers)
     #[argh(option, short = 'p')]
     parent: Option<String>,
 
@@ -58,59 +117,105 @@ impl IPdfArgs {
             return Err(Error::AlphaRangeError(self.alpha));
         }
 
-        if self.to_bw && self.to_gray {
+        if (self.to_bw || self.dither.is_some()) && self.to_gray {
             return Err(Error::BadArgsError(
-                "--to_bw and --to_gray may not be used together.".to_string(),
+                "--to_bw (or --dither) and --to_gray may not be used together.".to_string(),
             ));
         }
 
+        if self.overlap > 50 {
+            return Err(Error::BadArgsError(format!(
+                "overlap of {}% is out of range, [0..50]",
+                self.overlap
+            )));
+        }
+
+        self.steps()?;
         Ok(())
     }
 
+    fn steps(&self) -> Result<Vec<Step>> {
+        let mut steps = Vec::default();
+        for spec in &self.enhance {
+            steps.extend(parse_steps(spec)?);
+        }
+        Ok(steps)
+    }
+
     fn color_transform(&self) -> ColorTransform {
-        if self.to_bw {
-            ColorTransform::ToBlackAndWhite
+        if self.to_bw || self.dither.is_some() {
+            ColorTransform::ToBlackAndWhite(self.dither.unwrap_or(Dither::FloydSteinberg))
         } else if self.to_gray {
             ColorTransform::ToGrayscale
         } else {
             ColorTransform::None
         }
     }
-}
 
-pub fn ipdf(conn: &Connection, opt: IPdfArgs) -> Result<()> {
-    opt.verify()?;
+    /// How much --split pieces overlap, or None if images aren't split.
+    fn split(&self) -> Option<f64> {
+        if self.split {
+            Some(f64::from(self.overlap) / 100.0)
+        } else {
+            None
+        }
+    }
 
-    let should_print = opt.file_names.len() > 1;
-    info!(
-        "converting {} files for Remarkable device",
-        opt.file_names.len()
-    );
-    for file in &opt.file_names {
-        if should_print {
-            let base_fn = PathBuf::from(&file)
-                .file_name()
-                .map(OsString::from)
-                .unwrap_or_else(|| OsString::from(""));
-            trace!("Processing: {}", base_fn.to_string_lossy());
+    fn layout(&self) -> Option<Layout> {
+        if self.page_size.is_none() && self.fit.is_none() && self.margin.is_none() && !self.rotate {
+            return None;
         }
+        Some(Layout {
+            page: self.page_size.unwrap_or(PageSize::REMARKABLE),
+            scaling: self.fit.unwrap_or(Scaling::Fit),
+            margin: self.margin.unwrap_or_default(),
+            rotate: self.rotate,
+        })
+    }
+}
 
-        let parent_id = opt
-            .parent
-            .as_ref()
-            .map(|p| conn.find_folder(p))
-            .transpose()?;
-
-        if let Err(err) = ipdf_func(
-            file,
-            opt.color_transform(),
-            opt.alpha,
-            parent_id,
-            &opt.dest_dir,
-        ) {
-            error!("{}", err);
+pub fn ipdf(conn: &Connection, opt: &IPdfArgs) -> Result<()> {
+    opt.verify()?;
+
+    let parent_id = opt
+        .parent
+        .as_ref()
+        .map(|p| conn.find_folder(p))
+        .transpose()?;
+    let images = expand_file_names(&opt.file_names)?;
+    let templates = super::template::installed_templates(conn)?;
+
+    if opt.combine {
+        let name = match &opt.name {
+            Some(name) => name.clone(),
+            None => opt
+                .file_names
+                .first()
+                .map(|f| file_name(Path::new(f)).into_owned())
+                .unwrap_or_default(),
+        };
+        info!(
+            "combining {} images into '{}' for Remarkable device",
+            images.len(),
+            name
+        );
+        ipdf_func(&images, &name, opt, parent_id.as_ref(), templates.as_ref())?;
+    } else {
+        info!("converting {} files for Remarkable device", images.len());
+        for image in &images {
+            trace!("Processing: {}", file_name(image));
+            if let Err(err) = ipdf_func(
+                std::slice::from_ref(image),
+                &file_name(image),
+                opt,
+                parent_id.as_ref(),
+                templates.as_ref(),
+            ) {
+                error!("{}", err);
+            }
         }
     }
+
     if opt.copy {
         use super::copier;
         info!("Copying converted files to Remarkable device.");
@@ -122,76 +227,201 @@ pub fn ipdf(conn: &Connection, opt: IPdfArgs) -> Result<()> {
     Ok(())
 }
 
+fn file_name(path: &Path) -> Cow<'_, str> {
+    path.file_name()
+        .map(|f| f.to_string_lossy())
+        .unwrap_or(Cow::Borrowed("<null>"))
+}
+
+/// Replaces each directory in `file_names` with the images in it, sorted naturally.
+fn expand_file_names(file_names: &[String]) -> Result<Vec<PathBuf>> {
+    let mut images = Vec::default();
+    for file_name in file_names {
+        let path = PathBuf::from(file_name);
+        if path.is_dir() {
+            let mut dir_images = Vec::default();
+            for entry in std::fs::read_dir(&path)? {
+                let entry_path = entry?.path();
+                if entry_path.is_file() && image::ImageFormat::from_path(&entry_path).is_ok() {
+                    dir_images.push(entry_path);
+                }
+            }
+            dir_images.sort_by(|p1, p2| natural_cmp(&p1.to_string_lossy(), &p2.to_string_lossy()));
+            images.extend(dir_images);
+        } else {
+            images.push(path);
+        }
+    }
+    Ok(images)
+}
+
+/// Compares strings so that runs of digits are ordered by their value, e.g., `page2` comes
+/// before `page10`.
+fn natural_cmp(s1: &str, s2: &str) -> Ordering {
+    let mut chars1 = s1.chars().peekable();
+    let mut chars2 = s2.chars().peekable();
+    loop {
+        match (chars1.peek(), chars2.peek()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(c1), Some(c2)) if c1.is_ascii_digit() && c2.is_ascii_digit() => {
+                let n1 = take_digits(&mut chars1);
+                let n2 = take_digits(&mut chars2);
+                // Compare by value first (ignoring leading zeros), then by length.
+                let v1 = n1.trim_start_matches('0');
+                let v2 = n2.trim_start_matches('0');
+                let ordering = v1
+                    .len()
+                    .cmp(&v2.len())
+                    .then_with(|| v1.cmp(v2))
+                    .then_with(|| n1.len().cmp(&n2.len()));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(c1), Some(c2)) => {
+                let ordering = c1.cmp(c2);
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                chars1.next();
+                chars2.next();
+            }
+        }
+    }
+}
+
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut digits = String::default();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        digits.push(c);
+    }
+    digits
+}
+
+/// Creates a document named `name` in the output directory of `opt` with one page for each of
+/// `imgs`.
+///
+/// With a layout, every page is the size of its page, otherwise each page is the size of its
+/// image. With --split, each image is first cut into pieces, and every piece gets a page. The
+/// page templates are checked against `templates`, if given.
 fn ipdf_func(
-    img: impl AsRef<Path>,
-    color_transform: ColorTransform,
-    alpha: u8,
+    imgs: &[PathBuf],
+    name: &str,
+    opt: &IPdfArgs,
     parent: Option<impl AsRef<str>>,
-    output_dir: impl AsRef<Path>,
+    templates: Option<&TemplateIndex>,
 ) -> Result<()> {
+    let color_transform = opt.color_transform();
+    let alpha = opt.alpha;
+    let layout = opt.layout();
+    let layout = layout.as_ref();
+    let steps = opt.steps()?;
+    let output_dir = Path::new(&opt.dest_dir);
     if alpha > 100 {
         return Err(Error::AlphaRangeError(alpha));
     }
+    if imgs.is_empty() {
+        return Err(Error::BadArgsError("no images to convert".to_string()));
+    }
 
-    if !output_dir.as_ref().exists() {
-        std::fs::create_dir(&output_dir)?;
+    if !output_dir.exists() {
+        std::fs::create_dir(output_dir)?;
     }
     let uu = new_uuid();
-    let base = create_bare_fs(&uu, &output_dir)?;
+    let base = create_bare_fs(&uu, output_dir)?;
 
-    let image = open_image(img.as_ref())?;
-    let processed_image = process_image(&image, color_transform, alpha)?;
+    let mut content = Content::default();
+    let mut pagedata = Pagedata::default();
+    let mut editor = PageEditor::new(&mut content, &mut pagedata);
+    let mut images = Vec::default();
+    for img in imgs {
+        trace!("adding page: {}", img.to_string_lossy());
+        let mut image = open_image(img)?;
+        // Enhancing comes before cutting and laying out, since it may crop or turn the image.
+        // The conversion comes after, so that dithering happens at the page's resolution.
+        let enhanced = match process_image(&image, &steps, ColorTransform::None, 100)? {
+            Cow::Owned(enhanced) => Some(enhanced),
+            Cow::Borrowed(_) => None,
+        };
+        if let Some(enhanced) = enhanced {
+            image = enhanced;
+        }
+        let pieces = match opt.split() {
+            Some(overlap) => {
+                let page = layout.map_or(PageSize::REMARKABLE, |l| l.page);
+                // Turning has to come first, so that the pieces are cut across the right side.
+                let turn = layout.is_some_and(|l| l.rotate) && image.width() > image.height();
+                let image = if turn { image.rotate90() } else { image };
+                let pieces = split_image(&image, &page, overlap);
+                trace!("split into {} pieces", pieces.len());
+                pieces
+            }
+            None => vec![image],
+        };
+
+        for mut image in pieces {
+            if let Some(layout) = layout {
+                image = layout_image(&image, layout)?;
+            }
+            let processed_image = process_image(&image, &[], color_transform, alpha)?;
+
+            let page_uuid = new_uuid();
+            editor.add_page(&page_uuid, DEFAULT_TEMPLATE);
+            let small_image = resize_image(&image, 362, 512);
+            write_thumbnail(&small_image, &base, &page_uuid)?;
+
+            images.push(processed_image.into_owned());
+        }
+    }
 
-    let pdf = create_pdf(&img.as_ref().to_string_lossy(), &processed_image);
+    if let Some(templates) = templates {
+        pagedata.validate(templates)?;
+    }
+
+    let dpi = layout.map_or(DPI, |l| l.page.dpi);
+    let pdf = create_pdf(name, &images, dpi);
     let outfile = base.with_extension("pdf");
     pdf.save(&mut BufWriter::new(File::create(outfile)?))?;
 
-    let content_file = File::create(base.with_extension("content"))?;
-    let mut content = Content::default();
-    let page_uuid = new_uuid();
-    content.add_page(&page_uuid);
-    serde_json::to_writer(content_file, &content)?;
-
-    create_metadata_file(
-        &img.as_ref()
-            .file_name()
-            .map(|f| f.to_string_lossy())
-            .unwrap_or(Cow::Borrowed("<null>")),
-        &base,
-        parent,
-    )?;
-    create_pagedata_file(&base)?;
-
-    let small_image = resize_image(&image, 362, 512);
-    write_thumbnail(&small_image, base, &page_uuid)?;
+    let backend = LocalBackend::new(output_dir);
+    content.save(&backend, &uu)?;
+    let parent = parent.as_ref().map_or("", |p| p.as_ref());
+    Metadata::with_name_and_parent(name, parent).save(&backend, &uu)?;
+    pagedata.save(&backend, &uu)?;
 
     Ok(())
 }
 
-fn new_uuid() -> String {
-    let uu = Uuid::new_v4();
-    uu.to_hyphenated()
-        .encode_lower(&mut Uuid::encode_buffer())
-        .to_string()
-}
-
 fn open_image(path: &Path) -> Result<image::DynamicImage> {
     Ok(printpdf::image::io::Reader::open(path)?.decode()?)
 }
 
-fn create_pdf(doc_name: &str, img_view: &image::DynamicImage) -> PdfDocumentReference {
-    let pdf_image = Image::from_dynamic_image(img_view);
-    let (doc, page, layer) = PdfDocument::new(
-        doc_name,
-        pdf_image.image.width.into_pt(DPI).into(),
-        pdf_image.image.height.into_pt(DPI).into(),
-        "Layer 1",
-    );
-
-    let current_layer = doc.get_page(page).get_layer(layer);
-    pdf_image.add_to_layer(current_layer, None, None, None, None, None, Some(DPI));
+/// Creates a PDF with one page for each of `images`, each page sized to fit its image at `dpi`.
+fn create_pdf(doc_name: &str, images: &[image::DynamicImage], dpi: f64) -> PdfDocumentReference {
+    let pdf_images = images.iter().map(Image::from_dynamic_image);
+    let mut doc = None;
+    for pdf_image in pdf_images {
+        let width = pdf_image.image.width.into_pt(dpi).into();
+        let height = pdf_image.image.height.into_pt(dpi).into();
+        let current_layer = match &doc {
+            None => {
+                let (new_doc, page, layer) = PdfDocument::new(doc_name, width, height, "Layer 1");
+                let current_layer = new_doc.get_page(page).get_layer(layer);
+                doc = Some(new_doc);
+                current_layer
+            }
+            Some(doc) => {
+                let (page, layer) = doc.add_page(width, height, "Layer 1");
+                doc.get_page(page).get_layer(layer)
+            }
+        };
+        pdf_image.add_to_layer(current_layer, None, None, None, None, None, Some(dpi));
+    }
 
-    doc
+    // unwrap: ipdf_func makes sure that there is at least one image.
+    doc.unwrap()
 }
 
 fn resize_image(image: &image::DynamicImage, width: u32, height: u32) -> image::DynamicImage {
@@ -212,26 +442,3 @@ fn write_thumbnail(
 
     Ok(())
 }
-
-fn create_metadata_file(
-    file_name: impl AsRef<str>,
-    base: impl AsRef<Path>,
-    parent: Option<impl AsRef<str>>,
-) -> Result<()> {
-    let metadata_file = File::create(base.as_ref().with_extension(METADATA_EXTENSION))?;
-    let metadata = Metadata::with_name_and_parent(
-        file_name,
-        parent.as_ref().map(|p| p.as_ref()).unwrap_or(""),
-    );
-
-    serde_json::to_writer(metadata_file, &metadata)?;
-    Ok(())
-}
-
-fn create_pagedata_file(base: impl AsRef<Path>) -> Result<()> {
-    // Right now, there is only ever one file.
-    let mut pagedata_file = File::create(base.as_ref().with_extension("pagedata"))?;
-    writeln!(pagedata_file, "Blank")?;
-
-    Ok(())
-}
@@ -1,52 +1,43 @@
 use crate::remarkable::Connection;
-use crate::File;
-use crate::Result;
+use crate::{Result, ROOT_ID};
 use argh::FromArgs;
-use std::collections::HashMap;
 
 #[derive(FromArgs, Debug)]
 /// list files on Remarkable
 #[argh(subcommand, name = "ls")]
-pub struct LsArgs {}
-
-pub fn ls(conn: &Connection, _: LsArgs) -> Result<()> {
-    ls_func(conn)
+pub struct LsArgs {
+    /// folder to list, as a path of visible names (e.g., Work/Papers). Defaults to the top level.
+    #[argh(positional, default = "String::new()")]
+    path: String,
 }
 
-fn ls_func(conn: &Connection) -> Result<()> {
-    let files = conn.files()?;
+pub fn ls(conn: &Connection, args: &LsArgs) -> Result<()> {
+    let tree = conn.tree()?;
+    let parent = conn.find_folder(&args.path)?;
 
-    let mut file_hash: HashMap<String, Vec<&File>> = Default::default();
-    for file in files.iter() {
-        if let Ok(file_data) = file.filedata.as_ref() {
-            let v = file_hash
-                .entry(file_data.metadata.parent.clone())
-                .or_default();
-            v.push(file);
+    for (depth, node) in tree.walk(&parent) {
+        let prefix = "   ".repeat(depth);
+        if node.is_folder() {
+            println!("{}{}/", prefix, node.name());
+        } else {
+            println!("{}{}", prefix, node.name());
         }
     }
 
-    ls_helper(&file_hash, "", "");
-
-    Ok(())
-}
-
-fn ls_helper(file_hash: &HashMap<String, Vec<&File>>, parent: &str, prefix: &str) {
-    if let Some(curr_vec) = file_hash.get(parent) {
-        let mut sorted_vec = curr_vec.iter().collect::<Vec<_>>();
-        // unwrap: just rewrite this.
-        sorted_vec.sort_by(|f1, f2| f1.visible_name().unwrap().cmp(f2.visible_name().unwrap()));
-        for file in sorted_vec {
-            if let Ok(file_data) = file.filedata.as_ref() {
-                if file_data.metadata.typ == "CollectionType" {
-                    println!("{}{}/", prefix, file_data.metadata.visible_name);
-                    ls_helper(file_hash, &file.id(), &format!("   {}", prefix));
-                } else {
-                    println!("{}{}", prefix, file_data.metadata.visible_name);
-                }
-            }
+    // Items that can't be reached from the top level would otherwise go unnoticed.
+    if parent == ROOT_ID {
+        for node in tree.orphans() {
+            eprintln!(
+                "ORPHANED: {} ({}) is in the missing folder {}",
+                node.name(),
+                node.id(),
+                node.parent_id()
+            );
+        }
+        for node in tree.cycles() {
+            eprintln!("IN A FOLDER CYCLE: {} ({})", node.name(), node.id());
         }
-    } else {
-        eprintln!("EXPECTED TO FIND: {}", parent);
     }
+
+    Ok(())
 }
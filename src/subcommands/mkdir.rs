@@ -0,0 +1,94 @@
+use crate::remarkable::{new_uuid, Connection, CONTENT_EXTENSION};
+use crate::{Error, Metadata, Result, COLLECTION_TYPE, ROOT_ID, TRASH_ID};
+use argh::FromArgs;
+use log::{debug, info};
+use std::path::Path;
+
+// Folders have no pages or settings; xochitl only keeps their tags in the content.
+const FOLDER_CONTENT: &str = "{\n    \"tags\": [\n    ]\n}\n";
+
+#[derive(FromArgs, Debug)]
+/// create folders on the Remarkable
+#[argh(subcommand, name = "mkdir")]
+pub struct MkdirArgs {
+    /// create any missing parent folders, and don't fail if the folder already exists.
+    #[argh(switch, short = 'p')]
+    parents: bool,
+
+    /// if present, restart the Remarkable app before quitting.
+    #[argh(switch, short = 'r')]
+    restart: bool,
+
+    /// paths of the folders to create, as visible names (e.g., Work/Papers)
+    #[argh(positional)]
+    paths: Vec<String>,
+}
+
+pub fn mkdir(conn: &Connection, args: &MkdirArgs) -> Result<()> {
+    for path in &args.paths {
+        mkdir_path(conn, path, args.parents)?;
+        // The next path may be inside this one.
+        conn.refresh();
+    }
+
+    if args.restart {
+        conn.restart()?;
+    }
+    Ok(())
+}
+
+fn mkdir_path(conn: &Connection, path: &str, parents: bool) -> Result<()> {
+    let tree = conn.tree()?;
+    let names = path
+        .split('/')
+        .filter(|n| !n.is_empty())
+        .collect::<Vec<_>>();
+    if names.is_empty() {
+        return Err(Error::BadArgsError("a folder name is required".to_string()));
+    }
+    if names[0] == TRASH_ID {
+        return Err(Error::BadArgsError(
+            "folders can't be created in the trash".to_string(),
+        ));
+    }
+
+    let mut parent = ROOT_ID.to_string();
+    for (i, name) in names.iter().enumerate() {
+        let prefix = names[..=i].join("/");
+        let last = i == names.len() - 1;
+
+        let mut existing = tree
+            .children(&parent)
+            .filter(|n| n.name() == *name)
+            .map(|n| n.id().to_string())
+            .collect::<Vec<_>>();
+        parent = match existing.len() {
+            0 if last || parents => create_folder(conn, name, &parent)?,
+            0 => return Err(Error::FolderNotFound(prefix)),
+            1 if (last && !parents) || !tree.is_folder(&existing[0]) => {
+                return Err(Error::PathExists(prefix))
+            }
+            1 => existing.remove(0),
+            _ => return Err(Error::AmbiguousPath(prefix, existing.join(", "))),
+        };
+    }
+    Ok(())
+}
+
+/// Writes the files for a new folder named `name` in the folder `parent`, returning its id.
+fn create_folder(conn: &Connection, name: &str, parent: &str) -> Result<String> {
+    let id = new_uuid();
+    info!("creating folder '{}' ({})", name, id);
+
+    let mut metadata = Metadata::with_name_and_parent(name, parent);
+    metadata.typ = COLLECTION_TYPE.to_string();
+    debug!("metadata: {:?}", metadata);
+
+    let backend = conn.backend();
+    backend.write(
+        &Path::new(&id).with_extension(CONTENT_EXTENSION),
+        FOLDER_CONTENT.as_bytes(),
+    )?;
+    metadata.save(backend, &id)?;
+    Ok(id)
+}
@@ -0,0 +1,120 @@
+use crate::remarkable::Connection;
+use crate::{Error, Metadata, Result, Tree};
+use argh::FromArgs;
+use log::info;
+use std::collections::HashSet;
+
+#[derive(FromArgs, Debug)]
+/// move or rename documents and folders on the Remarkable
+#[argh(subcommand, name = "mv")]
+pub struct MvArgs {
+    /// if present, restart the Remarkable app before quitting.
+    #[argh(switch, short = 'r')]
+    restart: bool,
+
+    /// the items to move, then where to put them, as paths of visible names (e.g., Work/Papers).
+    /// With one item, a destination that isn't an existing folder is its new path. With more,
+    /// the destination must be a folder.
+    #[argh(positional)]
+    paths: Vec<String>,
+}
+
+/// A single item's move.
+struct Move {
+    id: String,
+    parent: String,
+    name: String,
+}
+
+pub fn mv(conn: &Connection, args: &MvArgs) -> Result<()> {
+    let (dest, sources) = match args.paths.split_last() {
+        Some((dest, sources)) if !sources.is_empty() => (dest, sources),
+        _ => {
+            return Err(Error::BadArgsError(
+                "mv needs at least one item and a destination".to_string(),
+            ))
+        }
+    };
+
+    // Everything is checked before anything is changed, so a bad item doesn't leave a batch
+    // half done.
+    let tree = conn.tree()?;
+    let moves = plan_moves(&tree, sources, dest)?;
+    for m in &moves {
+        info!(
+            "moving '{}' to '{}'",
+            tree.path_of(&m.id)?,
+            join_path(&tree.path_of(&m.parent)?, &m.name)
+        );
+        let backend = conn.backend();
+        let mut metadata = Metadata::load(backend, &m.id)?;
+        // Items moved to the trash can be restored like those removed with `rm`.
+        metadata.move_to(&m.parent);
+        metadata.visible_name = m.name.clone();
+        metadata.mark_modified();
+        metadata.save(backend, &m.id)?;
+    }
+    conn.refresh();
+
+    if args.restart {
+        conn.restart()?;
+    }
+    Ok(())
+}
+
+fn plan_moves(tree: &Tree, sources: &[String], dest: &str) -> Result<Vec<Move>> {
+    // The destination is either a folder to move into, or (for one item) a new path.
+    let (parent, new_name) = match tree.resolve(dest) {
+        Ok(id) if tree.is_folder(&id) => (id, None),
+        Ok(_) if sources.len() == 1 => return Err(Error::PathExists(dest.to_string())),
+        Err(Error::PathNotFound(_)) if sources.len() == 1 && !dest.ends_with('/') => {
+            let (parent_path, name) = dest.rsplit_once('/').unwrap_or(("", dest));
+            let parent = tree.resolve(parent_path)?;
+            if !tree.is_folder(&parent) {
+                return Err(Error::FolderNotFound(parent_path.to_string()));
+            }
+            (parent, Some(name.to_string()))
+        }
+        Ok(_) | Err(Error::PathNotFound(_)) => return Err(Error::FolderNotFound(dest.to_string())),
+        Err(err) => return Err(err),
+    };
+
+    let mut moves = Vec::default();
+    let mut taken = HashSet::new();
+    for source in sources {
+        let id = tree.resolve(source)?;
+        let node = tree
+            .get(&id)
+            .ok_or_else(|| Error::BadArgsError(format!("'{}' can't be moved", source)))?;
+        if id == parent || tree.ancestors(&parent).any(|a| a.id() == id) {
+            return Err(Error::BadArgsError(format!(
+                "'{}' can't be moved into itself",
+                source
+            )));
+        }
+
+        let name = new_name.clone().unwrap_or_else(|| node.name().to_string());
+        let exists = tree
+            .children(&parent)
+            .any(|c| c.name() == name && c.id() != id);
+        if exists || !taken.insert(name.clone()) {
+            let path = join_path(&tree.path_of(&parent)?, &name);
+            return Err(Error::PathExists(path));
+        }
+
+        moves.push(Move {
+            id,
+            parent: parent.clone(),
+            name,
+        });
+    }
+    Ok(moves)
+}
+
+fn join_path(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
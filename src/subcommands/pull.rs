@@ -0,0 +1,174 @@
+use crate::remarkable::{list_artifacts, Backend, Connection, Entry};
+use crate::{Result, Tree, ROOT_ID};
+use argh::FromArgs;
+use log::{info, trace};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_DEST_DIR: &str = ".";
+
+#[derive(FromArgs, Debug)]
+/// download documents and folders from the Remarkable
+#[argh(subcommand, name = "pull")]
+pub struct PullArgs {
+    /// directory for output files
+    #[argh(option, short = 'o', default = "DEFAULT_DEST_DIR.into()")]
+    dest_dir: PathBuf,
+
+    /// the document or folder to download, as a path of visible names (e.g., Work/Papers/foo)
+    /// or an id. Folders are downloaded with everything in them.
+    #[argh(positional)]
+    path: String,
+}
+
+/// Copies all of the files for the item at `path` into `dest_dir`.
+///
+/// The files are copied unchanged, so the xochitl layout is kept for each item. Folders become
+/// local directories named after them, holding the files of the items inside. Names are made
+/// safe to use as directory names, and folders with the same name in the same folder get
+/// numbered.
+pub fn pull(conn: &Connection, args: &PullArgs) -> Result<()> {
+    let tree = conn.tree()?;
+    let id = conn.resolve(&args.path)?;
+    let artifacts = list_artifacts(conn.backend())?;
+
+    if !args.dest_dir.exists() {
+        std::fs::create_dir_all(&args.dest_dir)?;
+    }
+    pull_item(conn.backend(), &tree, &artifacts, &id, &args.dest_dir)
+}
+
+fn pull_item(
+    backend: &dyn Backend,
+    tree: &Tree,
+    artifacts: &HashMap<String, Vec<Entry>>,
+    id: &str,
+    dest: &Path,
+) -> Result<()> {
+    let name = tree.get(id).map(|n| n.name()).unwrap_or(id);
+    pull_artifacts(backend, artifacts, name, id, dest)?;
+    if !tree.is_folder(id) {
+        return Ok(());
+    }
+
+    // The local directory for each level of folders below `id`.
+    // The root folder has no name, so its contents go straight into `dest`.
+    let mut used = HashSet::new();
+    let top = if id == ROOT_ID {
+        dest.to_path_buf()
+    } else {
+        create_folder_dir(dest, name, &mut used)?
+    };
+    let mut dirs = vec![top];
+    for (depth, node) in tree.walk(id) {
+        dirs.truncate(depth + 1);
+        let dir = &dirs[depth];
+        pull_artifacts(backend, artifacts, node.name(), node.id(), dir)?;
+        if node.is_folder() {
+            let folder_dir = create_folder_dir(dir, node.name(), &mut used)?;
+            dirs.push(folder_dir);
+        }
+    }
+    Ok(())
+}
+
+/// Creates the directory for the folder `name` in `parent`, unless it already exists. `used`
+/// holds the directories created so far, which aren't used again.
+fn create_folder_dir(parent: &Path, name: &str, used: &mut HashSet<PathBuf>) -> Result<PathBuf> {
+    let dir = unique_dir(parent, &dir_name(name), used);
+    if !dir.exists() {
+        std::fs::create_dir(&dir)?;
+    }
+    Ok(dir)
+}
+
+/// `name`, made safe to use as a single directory name: no slashes, and no leading dots, so
+/// that it can't be `.` or `..`, or hidden.
+fn dir_name(name: &str) -> String {
+    let name = name.replace('/', "_");
+    let dots = name.len() - name.trim_start_matches('.').len();
+    let name = format!("{}{}", "_".repeat(dots), &name[dots..]);
+    if name.is_empty() {
+        "_".to_string()
+    } else {
+        name
+    }
+}
+
+/// `parent/name`, or `parent/name (2)` and so on if that is in `used`. The result is added to
+/// `used`.
+fn unique_dir(parent: &Path, name: &str, used: &mut HashSet<PathBuf>) -> PathBuf {
+    let mut dir = parent.join(name);
+    let mut n = 1;
+    while used.contains(&dir) {
+        n += 1;
+        dir = parent.join(format!("{} ({})", name, n));
+    }
+    used.insert(dir.clone());
+    dir
+}
+
+fn pull_artifacts(
+    backend: &dyn Backend,
+    artifacts: &HashMap<String, Vec<Entry>>,
+    name: &str,
+    id: &str,
+    dest: &Path,
+) -> Result<()> {
+    info!("pulling '{}' ({}) into {:?}", name, id, dest);
+    for entry in artifacts.get(id).into_iter().flatten() {
+        pull_entry(backend, entry, dest)?;
+    }
+    Ok(())
+}
+
+/// Copies the file or directory (recursively) at `entry` into `dest`.
+fn pull_entry(backend: &dyn Backend, entry: &Entry, dest: &Path) -> Result<()> {
+    let dest_path = dest.join(entry.path.file_name().unwrap_or_default());
+    trace!("copying {:?} ==> {:?}", entry.path, dest_path);
+    if entry.is_dir {
+        if !dest_path.exists() {
+            std::fs::create_dir(&dest_path)?;
+        }
+        for child in backend.list(&entry.path)? {
+            pull_entry(backend, &child, &dest_path)?;
+        }
+    } else {
+        std::fs::write(dest_path, backend.read(&entry.path)?)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn makes_safe_directory_names() {
+        assert_eq!(dir_name("Work"), "Work");
+        assert_eq!(dir_name("a/b"), "a_b");
+        assert_eq!(dir_name("."), "_");
+        assert_eq!(dir_name(".."), "__");
+        assert_eq!(dir_name("../x"), "___x");
+        assert_eq!(dir_name(".hidden"), "_hidden");
+        assert_eq!(dir_name("v1.0"), "v1.0");
+        assert_eq!(dir_name(""), "_");
+    }
+
+    #[test]
+    fn numbers_folders_with_the_same_name() {
+        let mut used = HashSet::new();
+        let parent = Path::new("out");
+        assert_eq!(unique_dir(parent, "Notes", &mut used), parent.join("Notes"));
+        assert_eq!(
+            unique_dir(parent, "Notes", &mut used),
+            parent.join("Notes (2)")
+        );
+        assert_eq!(
+            unique_dir(parent, "Notes", &mut used),
+            parent.join("Notes (3)")
+        );
+        let other = parent.join("Notes");
+        assert_eq!(unique_dir(&other, "Notes", &mut used), other.join("Notes"));
+    }
+}
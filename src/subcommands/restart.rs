@@ -7,6 +7,6 @@ use argh::FromArgs;
 #[argh(subcommand, name = "restart")]
 pub struct RestartArgs {}
 
-pub fn restart(conn: &Connection, _: RestartArgs) -> Result<()> {
+pub fn restart(conn: &Connection, _: &RestartArgs) -> Result<()> {
     conn.restart()
 }
@@ -0,0 +1,71 @@
+use crate::remarkable::Connection;
+use crate::{Error, Metadata, Result, ROOT_ID, TRASH_ID};
+use argh::FromArgs;
+use log::{info, warn};
+
+#[derive(FromArgs, Debug)]
+/// move documents and folders out of the trash, back to where they were
+#[argh(subcommand, name = "restore")]
+pub struct RestoreArgs {
+    /// if present, restart the Remarkable app before quitting.
+    #[argh(switch, short = 'r')]
+    restart: bool,
+
+    /// the items to restore, by name (e.g., foo or trash/foo) or id
+    #[argh(positional)]
+    paths: Vec<String>,
+}
+
+pub fn restore(conn: &Connection, args: &RestoreArgs) -> Result<()> {
+    let tree = conn.tree()?;
+    let in_trash = |id: &str| {
+        tree.path_of(id)
+            .map_or(true, |path| path.split('/').next() == Some(TRASH_ID))
+    };
+    let mut ids = Vec::default();
+    for path in &args.paths {
+        // Everything that can be restored is in the trash, so that part is optional.
+        let id = match tree.resolve(path) {
+            Err(Error::PathNotFound(_)) => tree.resolve(format!("{}/{}", TRASH_ID, path))?,
+            result => result?,
+        };
+        if tree.get(&id).is_none_or(|n| n.parent_id() != TRASH_ID) {
+            return Err(Error::BadArgsError(format!(
+                "'{}' is not in the trash",
+                path
+            )));
+        }
+        ids.push(id);
+    }
+
+    let backend = conn.backend();
+    for id in &ids {
+        let mut metadata = Metadata::load(backend, id)?;
+        // The original folder may have been deleted or trashed itself since.
+        let parent = match metadata.original_parent() {
+            Some(parent) if tree.is_folder(parent) && !in_trash(parent) => parent.to_string(),
+            _ => {
+                warn!(
+                    "the folder of '{}' is gone or in the trash, restoring to the top level",
+                    metadata.visible_name
+                );
+                ROOT_ID.to_string()
+            }
+        };
+
+        info!(
+            "restoring '{}' to '{}'",
+            metadata.visible_name,
+            tree.path_of(&parent)?
+        );
+        metadata.move_to(&parent);
+        metadata.mark_modified();
+        metadata.save(backend, id)?;
+    }
+    conn.refresh();
+
+    if args.restart {
+        conn.restart()?;
+    }
+    Ok(())
+}
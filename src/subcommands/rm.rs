@@ -0,0 +1,138 @@
+use crate::remarkable::{list_artifacts, Connection, METADATA_EXTENSION};
+use crate::{Error, Metadata, Result, Tree, TRASH_ID};
+use argh::FromArgs;
+use log::{info, trace, warn};
+use std::collections::HashSet;
+use std::io::Write;
+
+#[derive(FromArgs, Debug)]
+/// move documents and folders to the trash, or delete them permanently
+#[argh(subcommand, name = "rm")]
+pub struct RmArgs {
+    /// delete the items, and everything in them, permanently instead of moving them to the trash.
+    #[argh(switch)]
+    purge: bool,
+
+    /// don't ask for confirmation before deleting with --purge.
+    #[argh(switch, short = 'y')]
+    yes: bool,
+
+    /// if present, restart the Remarkable app before quitting.
+    #[argh(switch, short = 'r')]
+    restart: bool,
+
+    /// the items to remove, as paths of visible names (e.g., Work/Papers/foo)
+    #[argh(positional)]
+    paths: Vec<String>,
+}
+
+pub fn rm(conn: &Connection, args: &RmArgs) -> Result<()> {
+    if args.paths.is_empty() {
+        return Err(Error::BadArgsError(
+            "rm needs at least one item".to_string(),
+        ));
+    }
+
+    let tree = conn.tree()?;
+    let mut ids = Vec::default();
+    for path in &args.paths {
+        let id = tree.resolve(path)?;
+        if tree.get(&id).is_none() {
+            return Err(Error::BadArgsError(format!("'{}' can't be removed", path)));
+        }
+        ids.push(id);
+    }
+
+    if args.purge {
+        purge(conn, &tree, &ids, args.yes)?;
+    } else {
+        for id in &ids {
+            trash(conn, &tree, id)?;
+        }
+    }
+    conn.refresh();
+
+    if args.restart {
+        conn.restart()?;
+    }
+    Ok(())
+}
+
+/// Moves the item `id` to the trash, remembering where it was.
+fn trash(conn: &Connection, tree: &Tree, id: &str) -> Result<()> {
+    // Orphans and items in folder cycles have no path, but can still be trashed.
+    let path = tree.path_of(id).unwrap_or_else(|_| id.to_string());
+    if path.split('/').next() == Some(TRASH_ID) {
+        warn!("'{}' is already in the trash", path);
+        return Ok(());
+    }
+    info!("moving '{}' to the trash", path);
+
+    let backend = conn.backend();
+    let mut metadata = Metadata::load(backend, id)?;
+    metadata.move_to(TRASH_ID);
+    metadata.mark_modified();
+    metadata.save(backend, id)
+}
+
+/// Permanently deletes every file of the items `ids` and, for folders, of everything in them.
+///
+/// Unless `yes` is set, the items are listed and the user is asked first. Returns false if
+/// the user said no.
+pub(crate) fn purge(conn: &Connection, tree: &Tree, ids: &[String], yes: bool) -> Result<bool> {
+    // Parents come before their contents here.
+    let mut doomed = Vec::default();
+    let mut seen = HashSet::new();
+    for id in ids {
+        let contents = tree.walk(id).map(|(_, n)| n.id().to_string());
+        for id in std::iter::once(id.clone()).chain(contents) {
+            if seen.insert(id.clone()) {
+                doomed.push(id);
+            }
+        }
+    }
+
+    let folders = doomed.iter().filter(|id| tree.is_folder(id)).count();
+    println!("These items will be deleted permanently:");
+    for id in &doomed {
+        println!("   {}", tree.path_of(id).unwrap_or_else(|_| id.clone()));
+    }
+    println!(
+        "{} documents and {} folders in total.",
+        doomed.len() - folders,
+        folders
+    );
+    if !yes && !confirm("Delete them?")? {
+        println!("Nothing was deleted.");
+        return Ok(false);
+    }
+
+    let backend = conn.backend();
+    let artifacts = list_artifacts(backend)?;
+    // Contents go before their folders, and the metadata of each item goes last, so that an
+    // interrupted purge never leaves files that xochitl can't place.
+    for id in doomed.iter().rev() {
+        info!("deleting {}", id);
+        let mut entries = artifacts.get(id).into_iter().flatten().collect::<Vec<_>>();
+        entries.sort_by_key(|e| {
+            e.path
+                .extension()
+                .is_some_and(|ext| ext == METADATA_EXTENSION)
+        });
+        for entry in entries {
+            trace!("removing {:?}", entry.path);
+            backend.remove(&entry.path)?;
+        }
+    }
+    Ok(true)
+}
+
+/// Asks the user a yes or no question on the terminal. Anything but yes is no.
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::default();
+    std::io::stdin().read_line(&mut answer)?;
+    let answer = answer.trim().to_lowercase();
+    Ok(answer == "y" || answer == "yes")
+}
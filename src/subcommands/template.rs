@@ -0,0 +1,460 @@
+use crate::imgtools::{render_template, PageSize, TemplateStyle};
+use crate::remarkable::{Connection, Template, TemplateIndex, TEMPLATES_DIR};
+use crate::{Error, Result};
+use argh::FromArgs;
+use log::info;
+use printpdf::image::{DynamicImage, ImageOutputFormat};
+use std::path::Path;
+
+/// The icon of the device's own Blank template, used when none is given.
+const DEFAULT_ICON: &str = "\u{e9fe}";
+
+/// The smallest spacing, in millimeters, that a template may have.
+const MIN_SPACING: f64 = 1.0;
+
+#[derive(FromArgs, Debug)]
+/// generate page templates and install them on the Remarkable, or remove them
+#[argh(subcommand, name = "template")]
+pub struct TemplateArgs {
+    #[argh(subcommand)]
+    command: TemplateCommand,
+}
+
+#[derive(FromArgs, Debug)]
+#[argh(subcommand)]
+enum TemplateCommand {
+    Add(AddArgs),
+    Rm(RmArgs),
+}
+
+#[derive(FromArgs, Debug)]
+/// generate a template at the device's resolution and add it to the template picker
+#[argh(subcommand, name = "add")]
+struct AddArgs {
+    /// the kind of template: grid, dots, lines, cornell (Cornell notes), or music (staves)
+    #[argh(option, short = 's')]
+    style: TemplateStyle,
+
+    /// space between the lines or dots, in millimeters (for music, between the lines of a
+    /// staff). Defaults to 5 for grid and dots, 7 for lines and cornell, and 2.5 for music.
+    #[argh(option)]
+    spacing: Option<f64>,
+
+    /// the screen to draw the template for: remarkable (1404x1872, the default) or paper-pro
+    /// (1620x2160)
+    #[argh(option, default = "PageSize::REMARKABLE")]
+    page_size: PageSize,
+
+    /// make a template for writing with the device turned sideways.
+    #[argh(switch)]
+    landscape: bool,
+
+    /// name of the image file on the device, without an extension. Defaults to the name.
+    #[argh(option, short = 'f')]
+    filename: Option<String>,
+
+    /// the icon shown in the template picker, a character of the device's icon font.
+    #[argh(option)]
+    icon: Option<String>,
+
+    /// a category of the template picker to show the template in (repeat for more than one).
+    /// Defaults to Grids, Lines or Creative, by style.
+    #[argh(option)]
+    category: Vec<String>,
+
+    /// also write the template image to this local file.
+    #[argh(option, short = 'o')]
+    output: Option<String>,
+
+    /// if present, restart the Remarkable app before quitting.
+    #[argh(switch, short = 'r')]
+    restart: bool,
+
+    /// the name shown in the template picker
+    #[argh(positional)]
+    name: String,
+}
+
+#[derive(FromArgs, Debug)]
+/// remove templates from the template picker
+#[argh(subcommand, name = "rm")]
+struct RmArgs {
+    /// delete the template images from the device too. Without this, only the entries in the
+    /// template index are removed.
+    #[argh(switch)]
+    purge: bool,
+
+    /// if present, restart the Remarkable app before quitting.
+    #[argh(switch, short = 'r')]
+    restart: bool,
+
+    /// the file names (without extensions) of the templates to remove
+    #[argh(positional)]
+    filenames: Vec<String>,
+}
+
+impl AddArgs {
+    fn verify(&self) -> Result<()> {
+        if self.spacing() < MIN_SPACING {
+            return Err(Error::BadArgsError(format!(
+                "spacing of {}mm is too small, the least is {}mm",
+                self.spacing(),
+                MIN_SPACING
+            )));
+        }
+        let filename = self.filename();
+        if filename.is_empty() || filename.contains('/') {
+            return Err(Error::BadArgsError(format!(
+                "'{}' can't be the file name of a template",
+                filename
+            )));
+        }
+        Ok(())
+    }
+
+    fn spacing(&self) -> f64 {
+        self.spacing.unwrap_or_else(|| self.style.default_spacing())
+    }
+
+    fn filename(&self) -> &str {
+        self.filename.as_deref().unwrap_or(&self.name)
+    }
+
+    fn categories(&self) -> Vec<String> {
+        if self.category.is_empty() {
+            vec![self.style.category().to_string()]
+        } else {
+            self.category.clone()
+        }
+    }
+}
+
+/// The index of the templates installed on the device, or `None` if the connection can't reach
+/// it (a `--local` directory), for checking the templates of new documents against.
+pub(crate) fn installed_templates(conn: &Connection) -> Result<Option<TemplateIndex>> {
+    match conn.templates() {
+        Ok(index) => Ok(Some(index)),
+        Err(Error::UnsupportedOperation(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn template(conn: &Connection, args: &TemplateArgs) -> Result<()> {
+    match &args.command {
+        TemplateCommand::Add(args) => add(conn, args),
+        TemplateCommand::Rm(args) => rm(conn, args),
+    }
+}
+
+fn add(conn: &Connection, args: &AddArgs) -> Result<()> {
+    args.verify()?;
+    let filename = args.filename();
+    let mut index = conn.templates()?;
+    if index.find(filename).is_some() {
+        return Err(Error::TemplateExists(filename.to_string()));
+    }
+
+    let image = render_template(args.style, &args.page_size, args.landscape, args.spacing());
+    let mut png = Vec::default();
+    DynamicImage::ImageLuma8(image).write_to(&mut png, ImageOutputFormat::Png)?;
+    if let Some(output) = &args.output {
+        std::fs::write(output, &png)?;
+    }
+
+    // The image goes first, so that the index never names a missing file.
+    let image_path = Path::new(TEMPLATES_DIR).join(format!("{}.png", filename));
+    info!("installing template '{}' as {:?}", args.name, image_path);
+    conn.backend().write_system_file(&image_path, &png)?;
+    index.templates.push(Template {
+        name: args.name.clone(),
+        filename: filename.to_string(),
+        icon_code: args.icon.as_deref().unwrap_or(DEFAULT_ICON).to_string(),
+        categories: args.categories(),
+        landscape: if args.landscape { Some(true) } else { None },
+        extra: Default::default(),
+    });
+    conn.save_templates(&index)?;
+
+    if args.restart {
+        conn.restart()?;
+    }
+    Ok(())
+}
+
+fn rm(conn: &Connection, args: &RmArgs) -> Result<()> {
+    if args.filenames.is_empty() {
+        return Err(Error::BadArgsError(
+            "template rm needs at least one template".to_string(),
+        ));
+    }
+
+    let mut index = conn.templates()?;
+    for filename in &args.filenames {
+        let template = index
+            .remove(filename)
+            .ok_or_else(|| Error::TemplateNotFound(filename.to_string()))?;
+        info!("removing template '{}'", template.name);
+    }
+    conn.save_templates(&index)?;
+
+    if args.purge {
+        let mut paths = Vec::default();
+        for filename in &args.filenames {
+            for extension in &["png", "svg"] {
+                let path = Path::new(TEMPLATES_DIR).join(format!("{}.{}", filename, extension));
+                paths.push(path.to_string_lossy().into_owned());
+            }
+        }
+        // Not every template has both images, so missing files are fine, but other failures
+        // aren't.
+        let mut rm_args = vec!["-f", "--"];
+        rm_args.extend(paths.iter().map(|p| p.as_str()));
+        conn.backend().run_command("rm", &rm_args)?;
+    }
+
+    if args.restart {
+        conn.restart()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remarkable::{Backend, Entry};
+    use printpdf::image::GenericImageView;
+    use serde_json::{json, Value};
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+    use std::io::ErrorKind;
+    use std::path::PathBuf;
+    use std::rc::Rc;
+
+    /// The system files of a device, and the commands run on it. Shared with the `Connection`
+    /// that owns the backend, so the tests can look inside.
+    #[derive(Clone, Default)]
+    struct Device {
+        files: Rc<RefCell<BTreeMap<PathBuf, Vec<u8>>>>,
+        commands: Rc<RefCell<Vec<String>>>,
+        /// A file that can't be read for a reason other than not being there.
+        unreadable: Option<PathBuf>,
+    }
+
+    impl Device {
+        fn with_index(index: &Value) -> Device {
+            let device = Device::default();
+            device.files.borrow_mut().insert(
+                templates_path("templates.json"),
+                serde_json::to_vec(index).unwrap(),
+            );
+            device
+        }
+
+        fn file(&self, name: &str) -> Option<Vec<u8>> {
+            self.files.borrow().get(&templates_path(name)).cloned()
+        }
+
+        fn index(&self) -> Value {
+            serde_json::from_slice(&self.file("templates.json").unwrap()).unwrap()
+        }
+    }
+
+    impl Backend for Device {
+        fn list(&self, _path: &Path) -> Result<Vec<Entry>> {
+            unimplemented!("templates don't use the data directory")
+        }
+
+        fn read(&self, _path: &Path) -> Result<Vec<u8>> {
+            unimplemented!("templates don't use the data directory")
+        }
+
+        fn write(&self, _path: &Path, _data: &[u8]) -> Result<()> {
+            unimplemented!("templates don't use the data directory")
+        }
+
+        fn remove(&self, _path: &Path) -> Result<()> {
+            unimplemented!("templates don't use the data directory")
+        }
+
+        fn rename(&self, _from: &Path, _to: &Path) -> Result<()> {
+            unimplemented!("templates don't use the data directory")
+        }
+
+        fn run_command(&self, program: &str, args: &[&str]) -> Result<Vec<u8>> {
+            self.commands.borrow_mut().push(
+                std::iter::once(program)
+                    .chain(args.iter().copied())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            );
+            let mut files = self.files.borrow_mut();
+            match (program, args) {
+                ("mv", [from, to]) => {
+                    let data = files.remove(Path::new(from)).unwrap();
+                    files.insert(PathBuf::from(to), data);
+                }
+                ("rm", ["-f", "--", paths @ ..]) => {
+                    for path in paths {
+                        files.remove(Path::new(path));
+                    }
+                }
+                _ => {}
+            }
+            Ok(Vec::default())
+        }
+
+        fn read_system_file(&self, path: &Path) -> Result<Vec<u8>> {
+            if self.unreadable.as_deref() == Some(path) {
+                return Err(std::io::Error::from(ErrorKind::PermissionDenied).into());
+            }
+            self.files
+                .borrow()
+                .get(path)
+                .cloned()
+                .ok_or_else(|| std::io::Error::from(ErrorKind::NotFound).into())
+        }
+
+        fn write_system_file(&self, path: &Path, data: &[u8]) -> Result<()> {
+            self.files
+                .borrow_mut()
+                .insert(path.to_path_buf(), data.to_vec());
+            Ok(())
+        }
+    }
+
+    fn templates_path(name: &str) -> PathBuf {
+        Path::new(TEMPLATES_DIR).join(name)
+    }
+
+    fn original_index() -> Value {
+        json!({
+            "templates": [{
+                "name": "Blank",
+                "filename": "Blank",
+                "iconCode": "\u{e9fe}",
+                "categories": ["Creative"]
+            }],
+            "version": 2
+        })
+    }
+
+    fn add_args(name: &str) -> AddArgs {
+        AddArgs {
+            style: TemplateStyle::Lines,
+            spacing: None,
+            page_size: PageSize::REMARKABLE,
+            landscape: false,
+            filename: None,
+            icon: None,
+            category: Vec::default(),
+            output: None,
+            restart: false,
+            name: name.to_string(),
+        }
+    }
+
+    fn rm_args(filenames: &[&str], purge: bool) -> RmArgs {
+        RmArgs {
+            purge,
+            restart: false,
+            filenames: filenames.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn add_installs_the_image_and_the_entry() {
+        let device = Device::with_index(&original_index());
+        let original = device.file("templates.json").unwrap();
+        let conn = Connection::with_backend(device.clone());
+        add(&conn, &add_args("Narrow lines")).unwrap();
+
+        let png = device.file("Narrow lines.png").unwrap();
+        let image = printpdf::image::load_from_memory(&png).unwrap();
+        assert_eq!(image.dimensions(), (1404, 1872));
+
+        let mut expected = original_index();
+        expected["templates"].as_array_mut().unwrap().push(json!({
+            "name": "Narrow lines",
+            "filename": "Narrow lines",
+            "iconCode": DEFAULT_ICON,
+            "categories": ["Lines"]
+        }));
+        assert_eq!(device.index(), expected);
+        // The original index is kept, and the new one was moved into place.
+        assert_eq!(device.file("templates.json.bak").unwrap(), original);
+        assert_eq!(device.file("templates.json.tmp"), None);
+    }
+
+    #[test]
+    fn only_the_first_change_is_backed_up() {
+        let device = Device::with_index(&original_index());
+        let original = device.file("templates.json").unwrap();
+        let conn = Connection::with_backend(device.clone());
+        add(&conn, &add_args("One")).unwrap();
+        add(&conn, &add_args("Two")).unwrap();
+        assert_eq!(device.file("templates.json.bak").unwrap(), original);
+        assert_eq!(device.index()["templates"].as_array().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn add_refuses_a_file_name_in_use() {
+        let device = Device::with_index(&original_index());
+        let conn = Connection::with_backend(device.clone());
+        let args = AddArgs {
+            filename: Some("Blank".to_string()),
+            ..add_args("Another blank")
+        };
+        match add(&conn, &args) {
+            Err(Error::TemplateExists(filename)) => assert_eq!(filename, "Blank"),
+            result => panic!("expected TemplateExists, got {:?}", result),
+        }
+        assert_eq!(device.index(), original_index());
+        assert_eq!(device.file("Blank.png"), None);
+    }
+
+    #[test]
+    fn unreadable_backups_are_errors() {
+        let device = Device {
+            unreadable: Some(templates_path("templates.json.bak")),
+            ..Device::with_index(&original_index())
+        };
+        let conn = Connection::with_backend(device.clone());
+        match add(&conn, &add_args("Lines")) {
+            Err(Error::IOError(err)) => assert_eq!(err.kind(), ErrorKind::PermissionDenied),
+            result => panic!("expected IOError, got {:?}", result),
+        }
+        assert_eq!(device.index(), original_index());
+        assert_eq!(device.file("templates.json.bak"), None);
+    }
+
+    #[test]
+    fn rm_removes_the_entries() {
+        let device = Device::with_index(&original_index());
+        let conn = Connection::with_backend(device.clone());
+        add(&conn, &add_args("Lines")).unwrap();
+        rm(&conn, &rm_args(&["Lines"], false)).unwrap();
+        assert_eq!(device.index(), original_index());
+        // The image stays without --purge.
+        assert!(device.file("Lines.png").is_some());
+
+        rm(&conn, &rm_args(&["Blank"], true)).unwrap();
+        assert_eq!(device.index()["templates"], json!([]));
+        assert_eq!(
+            device.commands.borrow().last().unwrap(),
+            "rm -f -- /usr/share/remarkable/templates/Blank.png \
+             /usr/share/remarkable/templates/Blank.svg"
+        );
+    }
+
+    #[test]
+    fn rm_of_a_missing_template_changes_nothing() {
+        let device = Device::with_index(&original_index());
+        let conn = Connection::with_backend(device.clone());
+        match rm(&conn, &rm_args(&["Blank", "Dots"], true)) {
+            Err(Error::TemplateNotFound(filename)) => assert_eq!(filename, "Dots"),
+            result => panic!("expected TemplateNotFound, got {:?}", result),
+        }
+        assert_eq!(device.index(), original_index());
+        assert!(device.commands.borrow().is_empty());
+    }
+}
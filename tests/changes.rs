@@ -0,0 +1,181 @@
+//! Tests of the subcommands that change the data directory, run with `--local` against a copy
+//! of `tests/fixtures/xochitl` (see `tests/local.rs` for its layout).
+
+use mktemp::Temp;
+use mrktools::{Connection, Metadata, TRASH_ID};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A copy of the fixture data directory, deleted when dropped.
+struct DataDir {
+    dir: Temp,
+}
+
+impl DataDir {
+    fn new() -> DataDir {
+        let dir = Temp::new_dir().unwrap();
+        let fixture = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/xochitl");
+        for entry in std::fs::read_dir(fixture).unwrap() {
+            let entry = entry.unwrap();
+            std::fs::copy(entry.path(), dir.join(entry.file_name())).unwrap();
+        }
+        DataDir { dir }
+    }
+
+    fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Runs mrktools on the copy, with `args` after `--local <dir>`.
+    fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_mrktools"))
+            .arg("--local")
+            .arg(self.path())
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    fn metadata(&self, id: &str) -> Metadata {
+        let conn = Connection::local(self.path()).unwrap();
+        Metadata::load(conn.backend(), id).unwrap()
+    }
+}
+
+fn assert_success(output: &Output) {
+    assert!(
+        output.status.success(),
+        "failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn mv_renames_and_moves() {
+    let data = DataDir::new();
+    assert_success(&data.run(&["mv", "Notes", "Work/Jottings"]));
+    let notes = data.metadata("notes");
+    assert_eq!(notes.parent, "work");
+    assert_eq!(notes.visible_name, "Jottings");
+    assert_eq!(notes.version(), Some(4));
+
+    // Papers already has a Shared.
+    let output = data.run(&["mv", "Work/Shared", "Work/Papers"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Work/Papers/Shared, already exists"));
+    assert_eq!(data.metadata("shared-1").parent, "work");
+}
+
+#[test]
+fn mv_to_the_trash_can_be_restored() {
+    let data = DataDir::new();
+    assert_success(&data.run(&["mv", "Work/Papers/foo", "trash"]));
+    let foo = data.metadata("foo");
+    assert_eq!(foo.parent, TRASH_ID);
+    assert_eq!(foo.original_parent(), Some("papers"));
+
+    assert_success(&data.run(&["restore", "foo"]));
+    let foo = data.metadata("foo");
+    assert_eq!(foo.parent, "papers");
+    assert_eq!(foo.original_parent(), None);
+}
+
+#[test]
+fn rm_and_restore_round_trip() {
+    let data = DataDir::new();
+    assert_success(&data.run(&["rm", "Notes", "Work/Papers/foo"]));
+    for (id, parent) in &[("notes", ""), ("foo", "papers")] {
+        let metadata = data.metadata(id);
+        assert_eq!(metadata.parent, TRASH_ID);
+        assert_eq!(metadata.original_parent(), Some(*parent));
+        assert_eq!(metadata.version(), Some(4));
+    }
+
+    // Found in the trash without saying so.
+    assert_success(&data.run(&["restore", "Notes", "trash/foo"]));
+    for (id, parent) in &[("notes", ""), ("foo", "papers")] {
+        let metadata = data.metadata(id);
+        assert_eq!(metadata.parent, *parent);
+        assert_eq!(metadata.original_parent(), None);
+    }
+}
+
+#[test]
+fn restore_goes_to_the_top_level_if_the_folder_is_gone() {
+    let data = DataDir::new();
+    assert_success(&data.run(&["rm", "Work/Papers/foo"]));
+    assert_success(&data.run(&["rm", "Work/Papers"]));
+    assert_success(&data.run(&["restore", "foo"]));
+    assert_eq!(data.metadata("foo").parent, "");
+}
+
+#[test]
+fn rm_trashes_items_without_a_path() {
+    let data = DataDir::new();
+    assert_success(&data.run(&["rm", "lost", "loop-a"]));
+    let lost = data.metadata("lost");
+    assert_eq!(lost.parent, TRASH_ID);
+    assert_eq!(lost.original_parent(), Some("missing-folder"));
+    assert_eq!(data.metadata("loop-a").parent, TRASH_ID);
+}
+
+#[test]
+fn purge_deletes_every_file_of_the_items_and_their_contents() {
+    let data = DataDir::new();
+    let files = [
+        "foo.content",
+        "foo.pagedata",
+        "foo.pdf",
+        "foo/page.rm",
+        "foo.thumbnails/page.jpg",
+        "papers.content",
+        "shared-2.content",
+        "shared-1.content",
+    ];
+    for file in &files {
+        let path = data.path().join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, b"").unwrap();
+    }
+
+    assert_success(&data.run(&["rm", "--purge", "-y", "Work/Papers"]));
+    let mut left = std::fs::read_dir(data.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    left.sort();
+    assert_eq!(
+        left,
+        [
+            "broken.metadata",
+            "gone.metadata",
+            "loop-a.metadata",
+            "loop-b.metadata",
+            "lost.metadata",
+            "notes.metadata",
+            "old.metadata",
+            "recent.metadata",
+            "shared-1.content",
+            "shared-1.metadata",
+            "work.metadata",
+        ]
+    );
+}
+
+#[test]
+fn empty_trash_deletes_only_the_trash() {
+    let data = DataDir::new();
+    std::fs::write(data.path().join("old.content"), b"").unwrap();
+    assert_success(&data.run(&["rm", "Notes"]));
+    assert_success(&data.run(&["empty-trash", "-y"]));
+    for file in &["old.metadata", "old.content", "notes.metadata"] {
+        assert!(!data.path().join(file).exists(), "{} is left", file);
+    }
+    assert!(data.path().join("work.metadata").exists());
+
+    let output = data.run(&["empty-trash", "-y"]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "The trash is empty.\n"
+    );
+}
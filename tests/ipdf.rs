@@ -0,0 +1,67 @@
+//! Tests of `ipdf`, run with `--local` against `tests/fixtures/xochitl`, writing the new
+//! documents to a temporary directory.
+
+use mktemp::Temp;
+use mrktools::{Connection, Content, Metadata, Pagedata, DEFAULT_TEMPLATE};
+use printpdf::image::{GrayImage, Luma};
+use std::path::PathBuf;
+use std::process::Command;
+
+#[test]
+fn writes_a_combined_document() {
+    let dir = Temp::new_dir().unwrap();
+    let (images, out) = (dir.join("images"), dir.join("out"));
+    std::fs::create_dir(&images).unwrap();
+    for name in &["a.png", "b.png"] {
+        GrayImage::from_pixel(30, 40, Luma([200]))
+            .save(images.join(name))
+            .unwrap();
+    }
+
+    let fixture = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/xochitl");
+    let output = Command::new(env!("CARGO_BIN_EXE_mrktools"))
+        .arg("--local")
+        .arg(fixture)
+        .args(["ipdf", "-m", "-n", "Scans", "-p", "Work", "-o"])
+        .arg(&out)
+        .arg(&images)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+
+    let conn = Connection::local(&out).unwrap();
+    let files = conn.files().unwrap();
+    assert_eq!(files.len(), 1);
+    let id = &files[0].id();
+    let metadata = Metadata::load(conn.backend(), id).unwrap();
+    assert_eq!(metadata.visible_name, "Scans");
+    assert_eq!(metadata.parent, "work");
+    assert_eq!(metadata.version(), Some(2));
+
+    let content = Content::load(conn.backend(), id).unwrap();
+    assert_eq!(content.page_ids().len(), 2);
+    let pagedata = Pagedata::load(conn.backend(), id).unwrap();
+    assert_eq!(pagedata.templates(), [DEFAULT_TEMPLATE, DEFAULT_TEMPLATE]);
+
+    // Everything was saved through a temporary file, which is gone.
+    let mut names = std::fs::read_dir(&out)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with(id.as_str()) && !name.ends_with("thumbnails"))
+        .map(|name| name[id.len()..].to_string())
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "",
+            ".content",
+            ".highlights",
+            ".metadata",
+            ".pagedata",
+            ".pdf",
+            ".textconvertion"
+        ]
+    );
+}
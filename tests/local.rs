@@ -0,0 +1,205 @@
+//! Tests of path resolution and the folder hierarchy, run with `--local` against the data
+//! directory in `tests/fixtures/xochitl`:
+//!
+//! ```text
+//! Notes
+//! Work/
+//!    Papers/
+//!       Shared
+//!       foo
+//!    Recent
+//!    Shared
+//! trash/Old
+//! ```
+//!
+//! `Recent` has firmware 3 metadata, without `deleted`, `version` and the like. Besides these,
+//! `Gone` is deleted, `Lost` is in a folder that doesn't exist, `Loop A` and `Loop B` are inside
+//! each other, and `broken.metadata` isn't valid JSON.
+
+use mrktools::{Connection, Error, ROOT_ID, TRASH_ID};
+use std::path::PathBuf;
+use std::process::Command;
+use std::rc::Rc;
+
+fn fixture_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/xochitl")
+}
+
+fn connect() -> Connection {
+    Connection::local(fixture_dir()).unwrap()
+}
+
+#[test]
+fn missing_directory_is_an_error() {
+    match Connection::local(fixture_dir().join("missing")) {
+        Err(Error::DirNotFound(_)) => {}
+        result => panic!("expected DirNotFound, got {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn loads_every_metadata_file() {
+    let conn = connect();
+    let files = conn.files().unwrap();
+    assert_eq!(files.len(), 13);
+    let broken = files
+        .iter()
+        .filter(|f| f.filedata.is_err())
+        .collect::<Vec<_>>();
+    assert_eq!(broken.len(), 1);
+    assert_eq!(broken[0].id(), "broken");
+}
+
+#[test]
+fn resolves_paths() {
+    let conn = connect();
+    assert_eq!(conn.resolve("").unwrap(), ROOT_ID);
+    assert_eq!(conn.resolve("/").unwrap(), ROOT_ID);
+    assert_eq!(conn.resolve("Work").unwrap(), "work");
+    assert_eq!(conn.resolve("/Work/Papers/").unwrap(), "papers");
+    assert_eq!(conn.resolve("Work/Papers/foo").unwrap(), "foo");
+    assert_eq!(conn.resolve("Work/Shared").unwrap(), "shared-1");
+    assert_eq!(conn.resolve("Work/Recent").unwrap(), "recent");
+    assert_eq!(conn.resolve("trash").unwrap(), TRASH_ID);
+    assert_eq!(conn.resolve("trash/Old").unwrap(), "old");
+    // A single component may be an id.
+    assert_eq!(conn.resolve("foo").unwrap(), "foo");
+
+    for missing in &["Work/nope", "Gone", "Old", "Broken", "Work/Papers/foo/bar"] {
+        match conn.resolve(missing) {
+            Err(Error::PathNotFound(path)) => assert_eq!(path, *missing),
+            result => panic!("{}: expected PathNotFound, got {:?}", missing, result),
+        }
+    }
+}
+
+#[test]
+fn finds_documents_and_folders_by_bare_name() {
+    let conn = connect();
+    assert_eq!(conn.find_document("foo").unwrap(), "foo");
+    assert_eq!(conn.find_document("Notes").unwrap(), "notes");
+    assert_eq!(conn.find_folder("Papers").unwrap(), "papers");
+    assert_eq!(conn.find_folder("").unwrap(), ROOT_ID);
+
+    match conn.find_document("Shared") {
+        Err(Error::AmbiguousPath(name, ids)) => {
+            assert_eq!(name, "Shared");
+            assert_eq!(ids, "shared-1, shared-2");
+        }
+        result => panic!("expected AmbiguousPath, got {:?}", result),
+    }
+    match conn.find_document("Papers") {
+        Err(Error::DocumentNotFound(name)) => assert_eq!(name, "Papers"),
+        result => panic!("expected DocumentNotFound, got {:?}", result),
+    }
+    match conn.find_folder("Work/Papers/foo") {
+        Err(Error::FolderNotFound(name)) => assert_eq!(name, "Work/Papers/foo"),
+        result => panic!("expected FolderNotFound, got {:?}", result),
+    }
+}
+
+#[test]
+fn builds_paths_of_items() {
+    let conn = connect();
+    assert_eq!(conn.path_of("foo").unwrap(), "Work/Papers/foo");
+    assert_eq!(conn.path_of("old").unwrap(), "trash/Old");
+    assert_eq!(conn.path_of(ROOT_ID).unwrap(), "");
+    match conn.path_of("loop-a") {
+        Err(Error::FolderCycle(_)) => {}
+        result => panic!("expected FolderCycle, got {:?}", result),
+    }
+    match conn.path_of("lost") {
+        Err(Error::FolderNotFound(id)) => assert_eq!(id, "missing-folder"),
+        result => panic!("expected FolderNotFound, got {:?}", result),
+    }
+}
+
+#[test]
+fn walks_the_hierarchy_in_order() {
+    let tree = connect().tree().unwrap();
+    let walked = tree
+        .walk(ROOT_ID)
+        .map(|(depth, node)| (depth, node.id()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        walked,
+        vec![
+            (0, "notes"),
+            (0, "work"),
+            (1, "papers"),
+            (2, "shared-2"),
+            (2, "foo"),
+            (1, "recent"),
+            (1, "shared-1"),
+        ]
+    );
+
+    let ancestors = tree.ancestors("foo").map(|n| n.id()).collect::<Vec<_>>();
+    assert_eq!(ancestors, vec!["papers", "work"]);
+    assert!(tree.get("gone").is_none());
+    assert!(tree.is_folder(TRASH_ID));
+    assert!(!tree.is_folder("foo"));
+}
+
+#[test]
+fn finds_orphans_and_cycles() {
+    let tree = connect().tree().unwrap();
+    let orphans = tree.orphans().iter().map(|n| n.id()).collect::<Vec<_>>();
+    assert_eq!(orphans, vec!["lost"]);
+    let cycles = tree.cycles().iter().map(|n| n.id()).collect::<Vec<_>>();
+    assert_eq!(cycles, vec!["loop-a", "loop-b"]);
+
+    // Walking a cycle visits each of its items once.
+    let walked = tree.walk("loop-a").map(|(_, n)| n.id()).collect::<Vec<_>>();
+    assert_eq!(walked, vec!["loop-b", "loop-a"]);
+}
+
+#[test]
+fn tree_is_built_once_until_refresh() {
+    let conn = connect();
+    let tree = conn.tree().unwrap();
+    assert!(Rc::ptr_eq(&tree, &conn.tree().unwrap()));
+    conn.resolve("Work/Papers").unwrap();
+    assert!(Rc::ptr_eq(&tree, &conn.tree().unwrap()));
+
+    // The old tree can still be used after a refresh.
+    conn.refresh();
+    assert!(!Rc::ptr_eq(&tree, &conn.tree().unwrap()));
+    assert_eq!(tree.len(), conn.tree().unwrap().len());
+}
+
+#[test]
+fn commands_that_need_the_device_fail() {
+    match connect().restart() {
+        Err(Error::UnsupportedOperation(_)) => {}
+        result => panic!("expected UnsupportedOperation, got {:?}", result),
+    }
+}
+
+#[test]
+fn ls_lists_the_fixture() {
+    let output = Command::new(env!("CARGO_BIN_EXE_mrktools"))
+        .arg("--local")
+        .arg(fixture_dir())
+        .arg("ls")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Notes\nWork/\n   Papers/\n      Shared\n      foo\n   Recent\n   Shared\n"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("ORPHANED: Lost (lost) is in the missing folder missing-folder"));
+    assert!(stderr.contains("IN A FOLDER CYCLE: Loop A (loop-a)"));
+    assert!(stderr.contains("IN A FOLDER CYCLE: Loop B (loop-b)"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mrktools"))
+        .arg("--local")
+        .arg(fixture_dir())
+        .arg("ls")
+        .arg("Work/Papers")
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "Shared\nfoo\n");
+}
@@ -0,0 +1,149 @@
+//! Tests of `SftpBackend` against a real SSH server, such as one in a container:
+//!
+//! ```text
+//! docker run -d --name mrktools-sshd -p 2222:2222 -e USER_NAME=rm \
+//!     -e PUBLIC_KEY="$(cat ~/.ssh/id_ed25519.pub)" lscr.io/linuxserver/openssh-server
+//! MRKTOOLS_TEST_SFTP=rm@localhost:2222 cargo test --test sftp
+//! ```
+//!
+//! `MRKTOOLS_TEST_SFTP` is `user@host[:port]`, and `MRKTOOLS_TEST_IDENTITY` optionally names the
+//! private key. Without `MRKTOOLS_TEST_SFTP`, the tests pass without doing anything. Each test
+//! works in a directory of its own under the user's home directory, and removes it when done.
+
+use mrktools::{Backend, Error, Metadata, SftpBackend};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A connection for one test, and the directory that it may use.
+struct Server {
+    backend: SftpBackend,
+    dir: PathBuf,
+}
+
+impl Server {
+    fn connect(test: &str) -> Option<Server> {
+        let target = match std::env::var("MRKTOOLS_TEST_SFTP") {
+            Ok(target) => target,
+            Err(_) => {
+                eprintln!("MRKTOOLS_TEST_SFTP is not set, skipping {}", test);
+                return None;
+            }
+        };
+        let (user, host) = target
+            .split_once('@')
+            .expect("MRKTOOLS_TEST_SFTP should be user@host[:port]");
+        let identity = std::env::var("MRKTOOLS_TEST_IDENTITY").ok();
+        let backend = SftpBackend::connect(user, host, identity, ".").unwrap();
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = PathBuf::from(format!("mrktools-test-{}-{}", test, nanos));
+        backend.write(&dir.join("placeholder"), b"").unwrap();
+        Some(Server { backend, dir })
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.backend.remove(&self.dir);
+    }
+}
+
+#[test]
+fn files_round_trip() {
+    let server = match Server::connect("files") {
+        Some(server) => server,
+        None => return,
+    };
+    let backend = &server.backend;
+    let file = server.path("nested/dir/a.metadata");
+    backend.write(&file, b"hello").unwrap();
+    assert_eq!(backend.read(&file).unwrap(), b"hello");
+
+    let mut names = backend
+        .list(&server.dir)
+        .unwrap()
+        .into_iter()
+        .map(|e| (e.path, e.is_dir))
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(
+        names,
+        vec![
+            (server.path("nested"), true),
+            (server.path("placeholder"), false)
+        ]
+    );
+
+    backend.remove(&server.path("nested")).unwrap();
+    assert!(backend.read(&file).is_err());
+}
+
+#[test]
+fn rename_replaces_an_existing_file() {
+    let server = match Server::connect("rename") {
+        Some(server) => server,
+        None => return,
+    };
+    let backend = &server.backend;
+    let (from, to) = (server.path("a.tmp"), server.path("a"));
+    backend.write(&to, b"old").unwrap();
+    backend.write(&from, b"new").unwrap();
+    backend.rename(&from, &to).unwrap();
+    assert_eq!(backend.read(&to).unwrap(), b"new");
+    assert!(backend.read(&from).is_err());
+
+    // Which is what saving metadata over an existing file does.
+    let item = server.path("item");
+    let mut metadata = Metadata::with_name_and_parent("Item", "");
+    metadata.save(backend, &item).unwrap();
+    metadata.visible_name = "Renamed".to_string();
+    metadata.save(backend, &item).unwrap();
+    assert_eq!(
+        Metadata::load(backend, &item).unwrap().visible_name,
+        "Renamed"
+    );
+}
+
+#[test]
+fn run_command_passes_arguments_unchanged() {
+    let server = match Server::connect("quoting") {
+        Some(server) => server,
+        None => return,
+    };
+    let arg = "it's $(echo not run); echo \"not run\" `echo either`";
+    let output = server.backend.run_command("printf", &["%s", arg]).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), arg);
+}
+
+#[test]
+fn run_command_fails_on_non_zero_exit() {
+    let server = match Server::connect("exit") {
+        Some(server) => server,
+        None => return,
+    };
+    match server.backend.run_command("ls", &["/no/such/mrktools/dir"]) {
+        Err(Error::CommandFailed(_, stderr)) => assert!(!stderr.is_empty()),
+        result => panic!("expected CommandFailed, got {:?}", result),
+    }
+}
+
+#[test]
+fn system_files_round_trip() {
+    let server = match Server::connect("system") {
+        Some(server) => server,
+        None => return,
+    };
+    let backend = &server.backend;
+    let home = backend.run_command("pwd", &[]).unwrap();
+    let home = String::from_utf8(home).unwrap();
+    let file = Path::new(home.trim()).join(server.path("system.json"));
+    backend.write_system_file(&file, b"{}").unwrap();
+    assert_eq!(backend.read_system_file(&file).unwrap(), b"{}");
+}